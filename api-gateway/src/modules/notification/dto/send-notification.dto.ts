@@ -5,6 +5,9 @@ import {
   IsOptional,
   IsUUID,
   IsNumber,
+  IsDateString,
+  IsInt,
+  Min,
 } from 'class-validator';
 import { ApiProperty } from '@nestjs/swagger';
 
@@ -52,4 +55,20 @@ export class SendNotificationDto {
   @IsOptional()
   @IsObject()
   metadata?: Record<string, any>;
+
+  @ApiProperty({ required: false, example: '2025-01-01T18:00:00.000Z' })
+  @IsOptional()
+  @IsDateString()
+  send_at?: string;
+
+  @ApiProperty({ required: false, example: '2025-01-01T18:30:00.000Z' })
+  @IsOptional()
+  @IsDateString()
+  expires_at?: string;
+
+  @ApiProperty({ required: false, example: 600 })
+  @IsOptional()
+  @IsInt()
+  @Min(1)
+  ttl_seconds?: number;
 }
@@ -4,13 +4,65 @@ CREATE TABLE IF NOT EXISTS audit_logs (
     user_id UUID NOT NULL,
     notification_type VARCHAR(50) NOT NULL,
     template_code VARCHAR(100) NOT NULL,
-    status VARCHAR(50) NOT NULL CHECK (status IN ('queued', 'processing', 'sent', 'failed', 'dlq')),
+    status VARCHAR(50) NOT NULL CHECK (status IN ('queued', 'processing', 'sent', 'partially_sent', 'suppressed', 'deferred', 'expired', 'failed', 'dlq')),
     error_message TEXT,
+    error_code VARCHAR(100),
     metadata JSONB,
     created_at TIMESTAMP NOT NULL DEFAULT NOW()
 );
 
+ALTER TABLE audit_logs ADD COLUMN IF NOT EXISTS error_code VARCHAR(100);
+
+ALTER TABLE audit_logs DROP CONSTRAINT IF EXISTS audit_logs_status_check;
+ALTER TABLE audit_logs ADD CONSTRAINT audit_logs_status_check
+    CHECK (status IN ('queued', 'processing', 'sent', 'partially_sent', 'suppressed', 'deferred', 'expired', 'failed', 'dlq'));
+
 CREATE INDEX IF NOT EXISTS idx_audit_logs_trace_id ON audit_logs(trace_id);
 CREATE INDEX IF NOT EXISTS idx_audit_logs_user_id ON audit_logs(user_id);
 CREATE INDEX IF NOT EXISTS idx_audit_logs_status ON audit_logs(status);
-CREATE INDEX IF NOT EXISTS idx_audit_logs_created_at ON audit_logs(created_at DESC);
\ No newline at end of file
+CREATE INDEX IF NOT EXISTS idx_audit_logs_created_at ON audit_logs(created_at DESC);
+
+CREATE TABLE IF NOT EXISTS device_tokens (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL,
+    token TEXT NOT NULL UNIQUE,
+    platform VARCHAR(20) NOT NULL CHECK (platform IN ('android', 'ios', 'web')),
+    provider VARCHAR(50) NOT NULL,
+    app_id VARCHAR(255),
+    locale VARCHAR(35),
+    timezone VARCHAR(64),
+    last_seen TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    invalidated_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_device_tokens_user_id ON device_tokens(user_id) WHERE invalidated_at IS NULL;
+CREATE INDEX IF NOT EXISTS idx_device_tokens_invalidated_at ON device_tokens(invalidated_at) WHERE invalidated_at IS NOT NULL;
+
+CREATE TABLE IF NOT EXISTS scheduled_notifications (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    idempotency_key VARCHAR(255) NOT NULL UNIQUE,
+    trace_id VARCHAR(100) NOT NULL,
+    user_id UUID NOT NULL,
+    reason VARCHAR(50) NOT NULL CHECK (reason IN ('quiet_hours', 'send_at')),
+    payload JSONB NOT NULL,
+    deliver_at TIMESTAMPTZ NOT NULL,
+    claimed_at TIMESTAMPTZ,
+    status VARCHAR(20) NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'dispatching', 'dispatched', 'cancelled')),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+ALTER TABLE scheduled_notifications DROP CONSTRAINT IF EXISTS scheduled_notifications_reason_check;
+ALTER TABLE scheduled_notifications ADD CONSTRAINT scheduled_notifications_reason_check
+    CHECK (reason IN ('quiet_hours', 'send_at'));
+
+ALTER TABLE scheduled_notifications ADD COLUMN IF NOT EXISTS claimed_at TIMESTAMPTZ;
+
+ALTER TABLE scheduled_notifications DROP CONSTRAINT IF EXISTS scheduled_notifications_status_check;
+ALTER TABLE scheduled_notifications ADD CONSTRAINT scheduled_notifications_status_check
+    CHECK (status IN ('pending', 'dispatching', 'dispatched', 'cancelled'));
+
+CREATE INDEX IF NOT EXISTS idx_scheduled_notifications_trace_id ON scheduled_notifications(trace_id);
+CREATE INDEX IF NOT EXISTS idx_scheduled_notifications_due ON scheduled_notifications(deliver_at) WHERE status = 'pending';
@@ -1,42 +1,113 @@
 use std::sync::Arc;
 
+use anyhow::anyhow;
 use axum::{
     Router,
-    extract::State,
-    http::StatusCode,
-    response::{IntoResponse, Json},
-    routing::get,
+    extract::{Path, Query, Request, State},
+    http::{StatusCode, header::AUTHORIZATION},
+    middleware::{self, Next},
+    response::{IntoResponse, Json, Response},
+    routing::{get, patch, post},
 };
 use tokio::net::TcpListener;
 use tower_http::trace::TraceLayer;
 use tracing::info;
+use uuid::Uuid;
 
 use crate::{
-    clients::{database::DatabaseClient, health::HealthChecker},
+    clients::{
+        database::DatabaseClient, fcm::FcmClient, health::HealthChecker, rbmq::RabbitMqClient,
+        redis::RedisClient,
+    },
     config::Config,
-    models::{health::HealthStatus, response::ApiResponse},
+    dlq,
+    models::{
+        device::{DeviceToken, PrunedTokenStats, RefreshDeviceRequest, RegisterDeviceRequest},
+        dlq::{DlqInspection, DlqPurgeQuery, DlqPurgeResult, DlqReplayRequest, DlqReplayResult},
+        fcm::{TopicAction, TopicManagementResult, TopicSubscriptionRequest},
+        health::HealthStatus,
+        response::ApiResponse,
+        schedule::ScheduledNotification,
+        validation::{validate_apns_token, validate_fcm_token, validate_fcm_topic},
+        webpush::WebPushSubscription,
+    },
 };
 
+type ApiResult<T> = (StatusCode, Json<ApiResponse<T>>);
+
+/// How many DLQ messages a peek returns when the caller does not say
+const DEFAULT_DLQ_PEEK_LIMIT: usize = 50;
+
 pub struct AppState {
     health_checker: HealthChecker,
     database_client: Arc<DatabaseClient>,
+    fcm_client: FcmClient,
+    rabbitmq_client: Arc<RabbitMqClient>,
+    redis_client: RedisClient,
+    admin_api_token: Option<String>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct DlqPeekQuery {
+    limit: Option<usize>,
 }
 
 pub async fn run_api_server(
     config: Config,
     database_client: Arc<DatabaseClient>,
+    fcm_client: FcmClient,
+    rabbitmq_client: Arc<RabbitMqClient>,
+    redis_client: RedisClient,
+    shutdown: impl Future<Output = ()> + Send + 'static,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let state = Arc::new(AppState {
-        health_checker: HealthChecker::new(config.clone()),
+        health_checker: HealthChecker::new(config.clone())
+            .with_fcm_credentials(fcm_client.credentials())
+            .with_rabbitmq_client(Arc::clone(&rabbitmq_client)),
         database_client,
+        fcm_client,
+        rabbitmq_client,
+        redis_client,
+        // An empty token would let a bare "Bearer " header through
+        admin_api_token: config
+            .admin_api_token
+            .clone()
+            .filter(|token| !token.is_empty()),
     });
 
+    let admin = Router::new()
+        .route("/dlq", get(inspect_dlq).delete(purge_dlq))
+        .route("/dlq/replay", post(replay_dlq))
+        .route_layer(middleware::from_fn_with_state(
+            Arc::clone(&state),
+            require_admin_token,
+        ));
+
     let app = Router::new()
         .route("/health", get(health_check))
         .route(
             "/api/v1/push/status/{request_id}",
             get(get_notification_status),
         )
+        .route(
+            "/api/v1/push/scheduled/{request_id}",
+            get(list_scheduled).delete(cancel_scheduled),
+        )
+        .route("/api/v1/devices/pruned", get(get_pruned_stats))
+        .nest("/api/v1/admin", admin)
+        .route(
+            "/api/v1/users/{user_id}/devices",
+            get(list_devices).post(register_device),
+        )
+        .route(
+            "/api/v1/users/{user_id}/devices/{device_id}",
+            patch(refresh_device).delete(delete_device),
+        )
+        .route("/api/v1/topics/{topic}/subscribe", post(subscribe_to_topic))
+        .route(
+            "/api/v1/topics/{topic}/unsubscribe",
+            post(unsubscribe_from_topic),
+        )
         .layer(TraceLayer::new_for_http())
         .with_state(state);
 
@@ -45,7 +116,11 @@ pub async fn run_api_server(
 
     info!(address = %addr, "Health check server started");
 
-    axum::serve(listener, app).await?;
+    axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown)
+        .await?;
+
+    info!("Health check server stopped");
 
     Ok(())
 }
@@ -98,3 +173,398 @@ async fn get_notification_status(
         }
     }
 }
+
+async fn list_scheduled(
+    State(state): State<Arc<AppState>>,
+    Path(request_id): Path<String>,
+) -> ApiResult<Vec<ScheduledNotification>> {
+    match state
+        .database_client
+        .scheduled_notifications()
+        .list_for_request(&request_id)
+        .await
+    {
+        Ok(scheduled) => {
+            let response =
+                ApiResponse::success(scheduled, "Scheduled notifications retrieved".to_string());
+            (StatusCode::OK, Json(response))
+        }
+        Err(e) => {
+            let response = ApiResponse::error(e.to_string(), "Database query failed".to_string());
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(response))
+        }
+    }
+}
+
+async fn cancel_scheduled(
+    State(state): State<Arc<AppState>>,
+    Path(request_id): Path<String>,
+) -> ApiResult<Vec<ScheduledNotification>> {
+    match state
+        .database_client
+        .scheduled_notifications()
+        .cancel(&request_id)
+        .await
+    {
+        Ok(cancelled) if cancelled.is_empty() => {
+            let response = ApiResponse::error(
+                "No pending scheduled notifications for this request".to_string(),
+                "Not found".to_string(),
+            );
+            (StatusCode::NOT_FOUND, Json(response))
+        }
+        Ok(cancelled) => {
+            let response =
+                ApiResponse::success(cancelled, "Scheduled notifications cancelled".to_string());
+            (StatusCode::OK, Json(response))
+        }
+        Err(e) => {
+            let response = ApiResponse::error(e.to_string(), "Database query failed".to_string());
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(response))
+        }
+    }
+}
+
+async fn inspect_dlq(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<DlqPeekQuery>,
+) -> ApiResult<DlqInspection> {
+    let limit = query.limit.unwrap_or(DEFAULT_DLQ_PEEK_LIMIT);
+
+    match dlq::inspect(&state.rabbitmq_client, limit).await {
+        Ok(inspection) => {
+            let response = ApiResponse::success(inspection, "DLQ messages retrieved".to_string());
+            (StatusCode::OK, Json(response))
+        }
+        Err(e) => {
+            let response = ApiResponse::error(e.to_string(), "DLQ inspection failed".to_string());
+            (StatusCode::BAD_GATEWAY, Json(response))
+        }
+    }
+}
+
+async fn replay_dlq(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<DlqReplayRequest>,
+) -> ApiResult<DlqReplayResult> {
+    match dlq::replay(&state.rabbitmq_client, &state.redis_client, &request).await {
+        Ok(result) => {
+            let response = ApiResponse::success(result, "DLQ messages replayed".to_string());
+            (StatusCode::OK, Json(response))
+        }
+        Err(e) => {
+            let response = ApiResponse::error(e.to_string(), "DLQ replay failed".to_string());
+            (StatusCode::BAD_GATEWAY, Json(response))
+        }
+    }
+}
+
+async fn purge_dlq(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<DlqPurgeQuery>,
+) -> ApiResult<DlqPurgeResult> {
+    let Some(older_than) = i64::try_from(query.older_than_seconds)
+        .ok()
+        .and_then(chrono::Duration::try_seconds)
+    else {
+        let response = ApiResponse::error(
+            "older_than_seconds is out of range".to_string(),
+            "Invalid request".to_string(),
+        );
+        return (StatusCode::BAD_REQUEST, Json(response));
+    };
+
+    match dlq::purge(&state.rabbitmq_client, older_than).await {
+        Ok(result) => {
+            let response = ApiResponse::success(result, "DLQ messages purged".to_string());
+            (StatusCode::OK, Json(response))
+        }
+        Err(e) => {
+            let response = ApiResponse::error(e.to_string(), "DLQ purge failed".to_string());
+            (StatusCode::BAD_GATEWAY, Json(response))
+        }
+    }
+}
+
+async fn subscribe_to_topic(
+    State(state): State<Arc<AppState>>,
+    Path(topic): Path<String>,
+    Json(request): Json<TopicSubscriptionRequest>,
+) -> impl IntoResponse {
+    manage_topic(&state, TopicAction::Subscribe, &topic, request).await
+}
+
+async fn unsubscribe_from_topic(
+    State(state): State<Arc<AppState>>,
+    Path(topic): Path<String>,
+    Json(request): Json<TopicSubscriptionRequest>,
+) -> impl IntoResponse {
+    manage_topic(&state, TopicAction::Unsubscribe, &topic, request).await
+}
+
+async fn manage_topic(
+    state: &AppState,
+    action: TopicAction,
+    topic: &str,
+    request: TopicSubscriptionRequest,
+) -> ApiResult<TopicManagementResult> {
+    let validation = validate_fcm_topic(topic).and_then(|_| {
+        if request.tokens.is_empty() {
+            return Err(anyhow!("At least one token is required"));
+        }
+
+        request
+            .tokens
+            .iter()
+            .try_for_each(|token| validate_fcm_token(token))
+    });
+
+    if let Err(e) = validation {
+        let response = ApiResponse::error(e.to_string(), "Invalid request".to_string());
+        return (StatusCode::BAD_REQUEST, Json(response));
+    }
+
+    let result = match action {
+        TopicAction::Subscribe => {
+            state
+                .fcm_client
+                .subscribe_to_topic(topic, &request.tokens)
+                .await
+        }
+        TopicAction::Unsubscribe => {
+            state
+                .fcm_client
+                .unsubscribe_from_topic(topic, &request.tokens)
+                .await
+        }
+    };
+
+    match result {
+        Ok(result) => {
+            let response = ApiResponse::success(result, "Topic subscriptions updated".to_string());
+            (StatusCode::OK, Json(response))
+        }
+        Err(e) => {
+            let response = ApiResponse::error(e.to_string(), "Topic management failed".to_string());
+            (StatusCode::BAD_GATEWAY, Json(response))
+        }
+    }
+}
+
+async fn register_device(
+    State(state): State<Arc<AppState>>,
+    Path(user_id): Path<String>,
+    Json(request): Json<RegisterDeviceRequest>,
+) -> ApiResult<DeviceToken> {
+    let user_id = match parse_uuid(&user_id, "user_id") {
+        Ok(user_id) => user_id,
+        Err(response) => return response,
+    };
+
+    if let Err(e) = validate_device_token(request.provider(), &request.token) {
+        let response = ApiResponse::error(e.to_string(), "Invalid device".to_string());
+        return (StatusCode::BAD_REQUEST, Json(response));
+    }
+
+    match state
+        .database_client
+        .device_tokens()
+        .register(user_id, &request)
+        .await
+    {
+        Ok(device) => {
+            let response = ApiResponse::success(device, "Device registered".to_string());
+            (StatusCode::CREATED, Json(response))
+        }
+        Err(e) => {
+            let response = ApiResponse::error(e.to_string(), "Database query failed".to_string());
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(response))
+        }
+    }
+}
+
+async fn list_devices(
+    State(state): State<Arc<AppState>>,
+    Path(user_id): Path<String>,
+) -> ApiResult<Vec<DeviceToken>> {
+    let user_id = match parse_uuid(&user_id, "user_id") {
+        Ok(user_id) => user_id,
+        Err(response) => return response,
+    };
+
+    match state
+        .database_client
+        .device_tokens()
+        .list_for_user(user_id, true)
+        .await
+    {
+        Ok(devices) => {
+            let response = ApiResponse::success(devices, "Devices retrieved".to_string());
+            (StatusCode::OK, Json(response))
+        }
+        Err(e) => {
+            let response = ApiResponse::error(e.to_string(), "Database query failed".to_string());
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(response))
+        }
+    }
+}
+
+async fn refresh_device(
+    State(state): State<Arc<AppState>>,
+    Path((user_id, device_id)): Path<(String, String)>,
+    Json(request): Json<RefreshDeviceRequest>,
+) -> ApiResult<DeviceToken> {
+    let (user_id, device_id) = match (
+        parse_uuid(&user_id, "user_id"),
+        parse_uuid(&device_id, "device_id"),
+    ) {
+        (Ok(user_id), Ok(device_id)) => (user_id, device_id),
+        (Err(response), _) | (_, Err(response)) => return response,
+    };
+
+    let repository = state.database_client.device_tokens();
+
+    // A rotated token has to be valid for the provider the device uses
+    if let Some(token) = &request.token {
+        let existing = match repository.find(user_id, device_id).await {
+            Ok(existing) => existing,
+            Err(e) => {
+                let response =
+                    ApiResponse::error(e.to_string(), "Database query failed".to_string());
+                return (StatusCode::INTERNAL_SERVER_ERROR, Json(response));
+            }
+        };
+
+        if let Some(existing) = existing
+            && let Err(e) = validate_device_token(&existing.provider, token)
+        {
+            let response = ApiResponse::error(e.to_string(), "Invalid device".to_string());
+            return (StatusCode::BAD_REQUEST, Json(response));
+        }
+    }
+
+    match repository.refresh(user_id, device_id, &request).await {
+        Ok(Some(device)) => {
+            let response = ApiResponse::success(device, "Device refreshed".to_string());
+            (StatusCode::OK, Json(response))
+        }
+        Ok(None) => {
+            let response =
+                ApiResponse::error("Device not found".to_string(), "Not found".to_string());
+            (StatusCode::NOT_FOUND, Json(response))
+        }
+        Err(e) => {
+            let response = ApiResponse::error(e.to_string(), "Database query failed".to_string());
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(response))
+        }
+    }
+}
+
+async fn delete_device(
+    State(state): State<Arc<AppState>>,
+    Path((user_id, device_id)): Path<(String, String)>,
+) -> ApiResult<serde_json::Value> {
+    let (user_id, device_id) = match (
+        parse_uuid(&user_id, "user_id"),
+        parse_uuid(&device_id, "device_id"),
+    ) {
+        (Ok(user_id), Ok(device_id)) => (user_id, device_id),
+        (Err(response), _) | (_, Err(response)) => return response,
+    };
+
+    match state
+        .database_client
+        .device_tokens()
+        .delete(user_id, device_id)
+        .await
+    {
+        Ok(true) => {
+            let response = ApiResponse::success(
+                serde_json::json!({ "id": device_id }),
+                "Device deleted".to_string(),
+            );
+            (StatusCode::OK, Json(response))
+        }
+        Ok(false) => {
+            let response =
+                ApiResponse::error("Device not found".to_string(), "Not found".to_string());
+            (StatusCode::NOT_FOUND, Json(response))
+        }
+        Err(e) => {
+            let response = ApiResponse::error(e.to_string(), "Database query failed".to_string());
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(response))
+        }
+    }
+}
+
+async fn get_pruned_stats(State(state): State<Arc<AppState>>) -> ApiResult<PrunedTokenStats> {
+    match state.database_client.device_tokens().pruned_stats().await {
+        Ok(stats) => {
+            let response = ApiResponse::success(stats, "Pruned token counts retrieved".to_string());
+            (StatusCode::OK, Json(response))
+        }
+        Err(e) => {
+            let response = ApiResponse::error(e.to_string(), "Database query failed".to_string());
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(response))
+        }
+    }
+}
+
+/// Lets a request through only if it carries the configured admin token
+async fn require_admin_token(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(expected) = &state.admin_api_token else {
+        let response = ApiResponse::<()>::error(
+            "ADMIN_API_TOKEN is not configured".to_string(),
+            "Admin API is disabled".to_string(),
+        );
+        return (StatusCode::FORBIDDEN, Json(response)).into_response();
+    };
+
+    let provided = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+
+    match provided {
+        Some(token) if constant_time_eq(token.as_bytes(), expected.as_bytes()) => {
+            next.run(request).await
+        }
+        _ => {
+            let response = ApiResponse::<()>::error(
+                "Missing or invalid bearer token".to_string(),
+                "Unauthorized".to_string(),
+            );
+            (StatusCode::UNAUTHORIZED, Json(response)).into_response()
+        }
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn parse_uuid<T>(value: &str, field: &str) -> Result<Uuid, ApiResult<T>> {
+    Uuid::parse_str(value).map_err(|e| {
+        let response = ApiResponse::error(
+            format!("Invalid {}: {}", field, e),
+            "Invalid request".to_string(),
+        );
+        (StatusCode::BAD_REQUEST, Json(response))
+    })
+}
+
+fn validate_device_token(provider: &str, token: &str) -> anyhow::Result<()> {
+    match provider {
+        "fcm" => validate_fcm_token(token),
+        "apns" => validate_apns_token(token),
+        "webpush" => serde_json::from_str::<WebPushSubscription>(token)
+            .map(|_| ())
+            .map_err(|e| anyhow!("Web Push tokens must be a subscription JSON: {}", e)),
+        other => Err(anyhow!("Unknown push provider: {}", other)),
+    }
+}
@@ -0,0 +1,336 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+
+use anyhow::{Error, Result, anyhow};
+use async_trait::async_trait;
+use chrono::Utc;
+use jsonwebtoken::{Algorithm, EncodingKey, Header};
+use reqwest::Client;
+use tracing::{debug, info, warn};
+
+use crate::{
+    clients::{circuit_breaker::CircuitBreaker, provider::PushProvider},
+    config::Config,
+    models::{
+        apns::{ApnsAlert, ApnsClaims, ApnsError, ApnsPayload, Aps},
+        provider::{DeliveryErrorKind, PushNotification, PushPriority, PushTarget, SendReceipt},
+        retry::RetryConfig,
+        validation::validate_apns_token,
+    },
+    utils::retry_with_backoff_if,
+};
+
+struct CachedToken {
+    value: String,
+    issued_at: Instant,
+}
+
+pub struct ApnsTokenSigner {
+    encoding_key: EncodingKey,
+    key_id: String,
+    team_id: String,
+    refresh_interval: Duration,
+    cached: RwLock<Option<CachedToken>>,
+}
+
+impl ApnsTokenSigner {
+    pub fn new(
+        key_pem: &[u8],
+        key_id: String,
+        team_id: String,
+        refresh_interval: Duration,
+    ) -> Result<Self, Error> {
+        let encoding_key = EncodingKey::from_ec_pem(key_pem)
+            .map_err(|e| anyhow!("Invalid APNs signing key: {}", e))?;
+
+        Ok(Self {
+            encoding_key,
+            key_id,
+            team_id,
+            refresh_interval,
+            cached: RwLock::new(None),
+        })
+    }
+
+    pub fn token(&self) -> Result<String, Error> {
+        if let Some(cached) = self.cached.read().unwrap().as_ref()
+            && cached.issued_at.elapsed() < self.refresh_interval
+        {
+            return Ok(cached.value.clone());
+        }
+
+        let mut cached = self.cached.write().unwrap();
+
+        if let Some(existing) = cached.as_ref()
+            && existing.issued_at.elapsed() < self.refresh_interval
+        {
+            return Ok(existing.value.clone());
+        }
+
+        let value = self.sign()?;
+        *cached = Some(CachedToken {
+            value: value.clone(),
+            issued_at: Instant::now(),
+        });
+
+        debug!(key_id = %self.key_id, "APNs provider token rotated");
+
+        Ok(value)
+    }
+
+    pub fn invalidate(&self) {
+        *self.cached.write().unwrap() = None;
+    }
+
+    fn sign(&self) -> Result<String, Error> {
+        let mut header = Header::new(Algorithm::ES256);
+        header.kid = Some(self.key_id.clone());
+
+        let claims = ApnsClaims {
+            iss: self.team_id.clone(),
+            iat: Utc::now().timestamp(),
+        };
+
+        jsonwebtoken::encode(&header, &claims, &self.encoding_key)
+            .map_err(|e| anyhow!("Failed to sign APNs provider token: {}", e))
+    }
+}
+
+#[derive(Clone)]
+pub struct ApnsClient {
+    http_client: Client,
+    base_url: String,
+    topic: String,
+    token_signer: Arc<ApnsTokenSigner>,
+    retry_config: RetryConfig,
+    circuit_breaker: CircuitBreaker,
+}
+
+impl ApnsClient {
+    pub async fn new(config: &Config, circuit_breaker: CircuitBreaker) -> Result<Self, Error> {
+        let key_path = config
+            .apns_key_path
+            .as_ref()
+            .ok_or_else(|| anyhow!("APNS_KEY_PATH is required for APNs"))?;
+        let key_id = config
+            .apns_key_id
+            .clone()
+            .ok_or_else(|| anyhow!("APNS_KEY_ID is required for APNs"))?;
+        let team_id = config
+            .apns_team_id
+            .clone()
+            .ok_or_else(|| anyhow!("APNS_TEAM_ID is required for APNs"))?;
+        let topic = config
+            .apns_topic
+            .clone()
+            .ok_or_else(|| anyhow!("APNS_TOPIC is required for APNs"))?;
+
+        let key_pem = tokio::fs::read(key_path)
+            .await
+            .map_err(|e| anyhow!("Failed to read APNs key {}: {}", key_path, e))?;
+
+        let token_signer = ApnsTokenSigner::new(
+            &key_pem,
+            key_id,
+            team_id,
+            Duration::from_secs(config.apns_token_refresh_seconds),
+        )?;
+
+        let http_client = Client::builder()
+            .use_rustls_tls()
+            .http2_prior_knowledge()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .map_err(|_| anyhow!("Failed to create APNs HTTP client"))?;
+
+        info!(base_url = %config.apns_base_url, topic = %topic, "APNs client initialized");
+
+        Ok(Self {
+            http_client,
+            base_url: config.apns_base_url.trim_end_matches('/').to_string(),
+            topic,
+            token_signer: Arc::new(token_signer),
+            retry_config: config.retry_config(),
+            circuit_breaker,
+        })
+    }
+
+    async fn send_with_retry(
+        &self,
+        device_token: &str,
+        notification: &PushNotification,
+        payload: &ApnsPayload,
+    ) -> Result<SendReceipt, Error> {
+        retry_with_backoff_if(
+            &self.retry_config,
+            || self.send_notification_once(device_token, notification, payload),
+            |e| self.classify_error(e) == DeliveryErrorKind::Retryable,
+        )
+        .await
+    }
+
+    async fn send_notification_once(
+        &self,
+        device_token: &str,
+        notification: &PushNotification,
+        payload: &ApnsPayload,
+    ) -> Result<SendReceipt, Error> {
+        let provider_token = self.token_signer.token()?;
+
+        let url = format!("{}/3/device/{}", self.base_url, device_token);
+
+        // APNs rejects high priority background pushes, so silent messages
+        // always go out at priority 5
+        let (apns_push_type, apns_priority) =
+            match (notification.options.data_only, notification.priority) {
+                (true, _) => ("background", "5"),
+                (false, PushPriority::High) => ("alert", "10"),
+                (false, PushPriority::Normal) => ("alert", "5"),
+            };
+
+        let mut request = self
+            .http_client
+            .post(&url)
+            .bearer_auth(provider_token)
+            .header("apns-topic", &self.topic)
+            .header("apns-push-type", apns_push_type)
+            .header("apns-priority", apns_priority)
+            .json(payload);
+
+        if let Some(expires_at) = notification.expires_at {
+            request = request.header("apns-expiration", expires_at.timestamp().to_string());
+        }
+
+        if let Some(collapse_id) = &notification.collapse_key {
+            request = request.header("apns-collapse-id", collapse_id);
+        }
+
+        let response = request.send().await?;
+        let status = response.status();
+
+        if status.is_success() {
+            let apns_id = response
+                .headers()
+                .get("apns-id")
+                .and_then(|v| v.to_str().ok())
+                .map(|v| v.to_string());
+
+            info!(apns_id = ?apns_id, "APNs push notification sent successfully");
+
+            return Ok(SendReceipt {
+                message_id: apns_id,
+            });
+        }
+
+        let body = response.text().await.unwrap_or_default();
+        let error = ApnsError::from_response(status.as_u16(), &body);
+
+        if error == ApnsError::ExpiredProviderToken {
+            warn!("APNs provider token expired, forcing rotation");
+            self.token_signer.invalidate();
+        }
+
+        Err(error.into())
+    }
+
+    /// Mirrors the `apns` override `FcmClient` builds, so a message looks
+    /// the same on iOS whichever provider delivers it.
+    fn build_payload(notification: &PushNotification) -> ApnsPayload {
+        let options = &notification.options;
+
+        let mut data: HashMap<String, String> = notification.data.clone().unwrap_or_default();
+        data.insert("trace_id".to_string(), notification.trace_id.clone());
+
+        // Silent messages still carry the rendered text so the app can show
+        // it on its own terms.
+        let aps = if options.data_only {
+            data.entry("title".to_string())
+                .or_insert_with(|| notification.title.clone());
+            data.entry("body".to_string())
+                .or_insert_with(|| notification.body.clone());
+
+            Aps {
+                content_available: Some(1),
+                ..Default::default()
+            }
+        } else {
+            Aps {
+                alert: Some(ApnsAlert {
+                    title: notification.title.clone(),
+                    body: notification.body.clone(),
+                }),
+                badge: options.badge,
+                sound: options.sound.clone(),
+                category: options.category.clone(),
+                // Images are only attached on iOS by a service extension
+                mutable_content: (options.mutable_content || options.image.is_some()).then_some(1),
+                content_available: None,
+            }
+        };
+
+        if let Some(image) = &options.image {
+            data.entry("image".to_string())
+                .or_insert_with(|| image.clone());
+        }
+
+        ApnsPayload { aps, data }
+    }
+}
+
+#[async_trait]
+impl PushProvider for ApnsClient {
+    fn name(&self) -> &str {
+        "apns"
+    }
+
+    async fn send(&self, notification: &PushNotification) -> Result<SendReceipt, Error> {
+        let PushTarget::Token(device_token) = &notification.target else {
+            return Err(anyhow!(
+                "APNs does not support {} targets",
+                notification.target.kind()
+            ));
+        };
+
+        debug!(
+            device_token = %device_token,
+            trace_id = %notification.trace_id,
+            "Sending APNs push notification"
+        );
+
+        let payload = Self::build_payload(notification);
+
+        self.circuit_breaker
+            .call_with_filter(
+                || self.send_with_retry(device_token, notification, &payload),
+                |e| self.classify_error(e) == DeliveryErrorKind::Retryable,
+            )
+            .await
+    }
+
+    fn classify_error(&self, error: &Error) -> DeliveryErrorKind {
+        error
+            .downcast_ref::<ApnsError>()
+            .map(|e| e.kind())
+            .unwrap_or(DeliveryErrorKind::Retryable)
+    }
+
+    fn error_code(&self, error: &Error) -> Option<String> {
+        error
+            .downcast_ref::<ApnsError>()
+            .map(|e| e.reason().to_string())
+    }
+
+    fn validate_target(&self, target: &PushTarget) -> Result<(), Error> {
+        match target {
+            PushTarget::Token(token) => validate_apns_token(token),
+            _ => Err(anyhow!(
+                "{} does not support {} targets",
+                self.name(),
+                target.kind()
+            )),
+        }
+    }
+}
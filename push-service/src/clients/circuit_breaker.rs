@@ -1,10 +1,11 @@
 use anyhow::{Error, Result, anyhow};
-use redis::{AsyncCommands, aio::MultiplexedConnection};
-use std::time::{SystemTime, UNIX_EPOCH};
+use redis::{AsyncCommands, SetExpiry, SetOptions, aio::MultiplexedConnection};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tracing::{debug, info, warn};
 
 use crate::models::circuit_breaker::{CircuitBreakerConfig, CircuitState};
 
+#[derive(Clone)]
 pub struct CircuitBreaker {
     service_name: String,
     connection: MultiplexedConnection,
@@ -26,10 +27,26 @@ impl CircuitBreaker {
         }
     }
 
-    pub async fn call<F, Fut, T>(&mut self, operation: F) -> Result<T, Error>
+    pub async fn call<F, Fut, T>(&self, operation: F) -> Result<T, Error>
     where
         F: FnOnce() -> Fut,
         Fut: std::future::Future<Output = Result<T, Error>>,
+    {
+        self.call_with_filter(operation, |_| true).await
+    }
+
+    /// Runs the operation through the breaker, only counting errors for which
+    /// `is_failure` returns true. Other errors mean the downstream service
+    /// answered, so they are recorded as successes.
+    pub async fn call_with_filter<F, Fut, T, P>(
+        &self,
+        operation: F,
+        is_failure: P,
+    ) -> Result<T, Error>
+    where
+        F: FnOnce() -> Fut,
+        Fut: std::future::Future<Output = Result<T, Error>>,
+        P: Fn(&Error) -> bool,
     {
         let state = self.get_state().await?;
 
@@ -38,37 +55,103 @@ impl CircuitBreaker {
                 if self.should_attempt_reset().await? {
                     info!(service = %self.service_name, "Circuit breaker attempting reset");
                     self.set_state(CircuitState::HalfOpen).await?;
-                    return self.try_operation(operation).await;
+                    return self.try_operation(operation, is_failure).await;
                 }
                 warn!(service = %self.service_name, "Circuit breaker is open, rejecting request");
                 Err(anyhow!("Circuit breaker is open for {}", self.service_name))
             }
             CircuitState::HalfOpen => {
                 debug!(service = %self.service_name, "Circuit breaker in half-open state");
-                self.try_operation(operation).await
+                self.try_operation(operation, is_failure).await
             }
-            CircuitState::Closed => self.try_operation(operation).await,
+            CircuitState::Closed => self.try_operation(operation, is_failure).await,
         }
     }
 
-    async fn try_operation<F, Fut, T>(&mut self, operation: F) -> Result<T, Error>
+    /// The state a call made now would find. An open breaker whose timeout
+    /// has passed reports half-open, as the next call goes through as a
+    /// trial.
+    pub async fn current_state(&self) -> Result<CircuitState, Error> {
+        match self.get_state().await? {
+            CircuitState::Open if self.should_attempt_reset().await? => Ok(CircuitState::HalfOpen),
+            state => Ok(state),
+        }
+    }
+
+    /// Returns how long callers should hold off before contacting the
+    /// service again, if a server-requested backoff is in effect.
+    pub async fn backoff_remaining(&self) -> Result<Option<Duration>, Error> {
+        let mut connection = self.connection.clone();
+        let key = format!("circuit:{}:backoff_until", self.service_name);
+        let backoff_until: Option<u64> = connection.get(&key).await?;
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+
+        Ok(backoff_until
+            .filter(|until| *until > now)
+            .map(|until| Duration::from_millis(until - now)))
+    }
+
+    /// Asks every worker sharing this breaker to hold off for `delay`. An
+    /// existing backoff that lasts longer is left untouched.
+    pub async fn extend_backoff(&self, delay: Duration) -> Result<(), Error> {
+        if let Some(remaining) = self.backoff_remaining().await?
+            && remaining >= delay
+        {
+            return Ok(());
+        }
+
+        let mut connection = self.connection.clone();
+        let key = format!("circuit:{}:backoff_until", self.service_name);
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        let delay_ms = (delay.as_millis() as u64).max(1);
+
+        connection
+            .set_options::<_, _, ()>(
+                &key,
+                now + delay_ms,
+                SetOptions::default().with_expiration(SetExpiry::PX(delay_ms)),
+            )
+            .await?;
+
+        warn!(
+            service = %self.service_name,
+            delay_ms,
+            "Service requested backoff, pausing requests across workers"
+        );
+
+        Ok(())
+    }
+
+    async fn try_operation<F, Fut, T, P>(&self, operation: F, is_failure: P) -> Result<T, Error>
     where
         F: FnOnce() -> Fut,
         Fut: std::future::Future<Output = Result<T, Error>>,
+        P: Fn(&Error) -> bool,
     {
         match operation().await {
             Ok(result) => {
                 self.record_success().await?;
                 Ok(result)
             }
-            Err(e) => {
+            Err(e) if is_failure(&e) => {
                 self.record_failure().await?;
                 Err(e)
             }
+            Err(e) => {
+                self.record_success().await?;
+                Err(e)
+            }
         }
     }
 
-    async fn record_success(&mut self) -> Result<(), Error> {
+    async fn record_success(&self) -> Result<(), Error> {
         let state = self.get_state().await?;
 
         if state == CircuitState::HalfOpen {
@@ -92,7 +175,7 @@ impl CircuitBreaker {
         Ok(())
     }
 
-    async fn record_failure(&mut self) -> Result<(), Error> {
+    async fn record_failure(&self) -> Result<(), Error> {
         let state = self.get_state().await?;
 
         if state == CircuitState::HalfOpen {
@@ -123,69 +206,75 @@ impl CircuitBreaker {
         Ok(())
     }
 
-    async fn get_state(&mut self) -> Result<CircuitState, Error> {
+    async fn get_state(&self) -> Result<CircuitState, Error> {
+        let mut connection = self.connection.clone();
         let key = format!("circuit:{}:state", self.service_name);
-        let value: Option<String> = self.connection.get(&key).await?;
+        let value: Option<String> = connection.get(&key).await?;
 
         Ok(value
             .map(|s| CircuitState::from_string(&s))
             .unwrap_or(CircuitState::Closed))
     }
 
-    async fn set_state(&mut self, state: CircuitState) -> Result<(), Error> {
+    async fn set_state(&self, state: CircuitState) -> Result<(), Error> {
+        let mut connection = self.connection.clone();
         let key = format!("circuit:{}:state", self.service_name);
-        self.connection
-            .set::<_, _, ()>(&key, state.as_str())
-            .await?;
+        connection.set::<_, _, ()>(&key, state.as_str()).await?;
         Ok(())
     }
 
-    async fn increment_failure_count(&mut self) -> Result<u32, Error> {
+    async fn increment_failure_count(&self) -> Result<u32, Error> {
+        let mut connection = self.connection.clone();
         let key = format!("circuit:{}:failures", self.service_name);
-        let count: u32 = self.connection.incr(&key, 1).await?;
-        self.connection
+        let count: u32 = connection.incr(&key, 1).await?;
+        connection
             .expire::<_, ()>(&key, self.config.timeout_seconds as i64)
             .await?;
         Ok(count)
     }
 
-    async fn reset_failure_count(&mut self) -> Result<(), Error> {
+    async fn reset_failure_count(&self) -> Result<(), Error> {
+        let mut connection = self.connection.clone();
         let key = format!("circuit:{}:failures", self.service_name);
-        self.connection.del::<_, ()>(&key).await?;
+        connection.del::<_, ()>(&key).await?;
         Ok(())
     }
 
-    async fn increment_success_count(&mut self) -> Result<u32, Error> {
+    async fn increment_success_count(&self) -> Result<u32, Error> {
+        let mut connection = self.connection.clone();
         let key = format!("circuit:{}:successes", self.service_name);
-        let count: u32 = self.connection.incr(&key, 1).await?;
+        let count: u32 = connection.incr(&key, 1).await?;
         Ok(count)
     }
 
-    async fn reset_counters(&mut self) -> Result<(), Error> {
+    async fn reset_counters(&self) -> Result<(), Error> {
+        let mut connection = self.connection.clone();
         let failure_key = format!("circuit:{}:failures", self.service_name);
         let success_key = format!("circuit:{}:successes", self.service_name);
         let opened_key = format!("circuit:{}:opened_at", self.service_name);
 
-        self.connection.del::<_, ()>(&failure_key).await?;
-        self.connection.del::<_, ()>(&success_key).await?;
-        self.connection.del::<_, ()>(&opened_key).await?;
+        connection.del::<_, ()>(&failure_key).await?;
+        connection.del::<_, ()>(&success_key).await?;
+        connection.del::<_, ()>(&opened_key).await?;
 
         Ok(())
     }
 
-    async fn set_opened_at(&mut self) -> Result<(), Error> {
+    async fn set_opened_at(&self) -> Result<(), Error> {
+        let mut connection = self.connection.clone();
         let key = format!("circuit:{}:opened_at", self.service_name);
         let now = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .unwrap()
             .as_secs();
-        self.connection.set::<_, _, ()>(&key, now).await?;
+        connection.set::<_, _, ()>(&key, now).await?;
         Ok(())
     }
 
-    async fn should_attempt_reset(&mut self) -> Result<bool, Error> {
+    async fn should_attempt_reset(&self) -> Result<bool, Error> {
+        let mut connection = self.connection.clone();
         let key = format!("circuit:{}:opened_at", self.service_name);
-        let opened_at: Option<u64> = self.connection.get(&key).await?;
+        let opened_at: Option<u64> = connection.get(&key).await?;
 
         if let Some(opened_at) = opened_at {
             let now = SystemTime::now()
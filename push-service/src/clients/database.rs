@@ -1,9 +1,32 @@
+use std::{collections::HashSet, time::Duration};
+
 use anyhow::{Error, Result, anyhow};
-use tokio_postgres::{Client, NoTls};
+use chrono::{DateTime, Utc};
+use serde_json::Value as JsonValue;
+use tokio_postgres::{Client, NoTls, Row};
 use tracing::{debug, error, info};
 use uuid::Uuid;
 
-use crate::models::{audit::CreateAuditLog, status::NotificationStatus};
+use crate::models::{
+    audit::CreateAuditLog,
+    device::{
+        DevicePlatform, DeviceToken, PrunedTokenStats, RefreshDeviceRequest, RegisterDeviceRequest,
+    },
+    provider::PushTarget,
+    schedule::{ScheduleReason, ScheduleStatus, ScheduledNotification},
+    status::NotificationStatus,
+};
+
+const SCHEDULED_NOTIFICATION_COLUMNS: &str = "id, idempotency_key, trace_id, user_id, reason, \
+    payload, deliver_at, status, created_at, updated_at";
+
+const DEVICE_TOKEN_COLUMNS: &str = "id, user_id, token, platform, provider, app_id, locale, \
+    timezone, last_seen, invalidated_at, created_at, updated_at";
+
+// Web Push rows store the whole subscription, so they are matched on the
+// endpoint the way `PushTarget::identifier` reports it.
+const DEVICE_TOKEN_IDENTIFIER: &str = "CASE WHEN provider = 'webpush' \
+    THEN token::jsonb ->> 'endpoint' ELSE token END";
 
 pub struct DatabaseClient {
     client: Client,
@@ -44,9 +67,10 @@ impl DatabaseClient {
                     template_code, 
                     status, 
                     error_message, 
+                    error_code, 
                     metadata
                 )
-                VALUES ($1, $2, $3, $4, $5, $6, $7)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
                 "#,
                 &[
                     &log.trace_id,
@@ -55,6 +79,7 @@ impl DatabaseClient {
                     &log.template_code,
                     &status_str,
                     &log.error_message,
+                    &log.error_code,
                     &log.metadata,
                 ],
             )
@@ -77,6 +102,18 @@ impl DatabaseClient {
         Ok(())
     }
 
+    pub fn device_tokens(&self) -> DeviceTokenRepository<'_> {
+        DeviceTokenRepository {
+            client: &self.client,
+        }
+    }
+
+    pub fn scheduled_notifications(&self) -> ScheduledNotificationRepository<'_> {
+        ScheduledNotificationRepository {
+            client: &self.client,
+        }
+    }
+
     pub async fn health_check(&self) -> Result<(), Error> {
         self.client
             .query_one("SELECT 1 as check", &[])
@@ -101,6 +138,7 @@ impl DatabaseClient {
                     template_code, 
                     status, 
                     error_message, 
+                    error_code, 
                     metadata
                 FROM audit_logs 
                 WHERE trace_id = $1 
@@ -125,6 +163,10 @@ impl DatabaseClient {
             "queued" => NotificationStatus::Queued,
             "processing" => NotificationStatus::Processing,
             "sent" => NotificationStatus::Sent,
+            "partially_sent" => NotificationStatus::PartiallySent,
+            "suppressed" => NotificationStatus::Suppressed,
+            "deferred" => NotificationStatus::Deferred,
+            "expired" => NotificationStatus::Expired,
             "failed" => NotificationStatus::Failed,
             "dlq" => NotificationStatus::Dlq,
             _ => NotificationStatus::Failed,
@@ -137,9 +179,516 @@ impl DatabaseClient {
             template_code: row.get("template_code"),
             status,
             error_message: row.get("error_message"),
+            error_code: row.get("error_code"),
             metadata: row.get("metadata"),
         };
 
         Ok(Some(log))
     }
 }
+
+pub struct DeviceTokenRepository<'a> {
+    client: &'a Client,
+}
+
+impl DeviceTokenRepository<'_> {
+    /// Registers a device, or takes over the row if the token is already
+    /// known (e.g. a different user signed in on the same device).
+    pub async fn register(
+        &self,
+        user_id: Uuid,
+        request: &RegisterDeviceRequest,
+    ) -> Result<DeviceToken, Error> {
+        let query = format!(
+            r#"
+            INSERT INTO device_tokens (user_id, token, platform, provider, app_id, locale, timezone)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            ON CONFLICT (token) DO UPDATE SET
+                user_id = EXCLUDED.user_id,
+                platform = EXCLUDED.platform,
+                provider = EXCLUDED.provider,
+                app_id = EXCLUDED.app_id,
+                locale = EXCLUDED.locale,
+                timezone = EXCLUDED.timezone,
+                last_seen = NOW(),
+                invalidated_at = NULL,
+                updated_at = NOW()
+            RETURNING {}
+            "#,
+            DEVICE_TOKEN_COLUMNS
+        );
+
+        let row = self
+            .client
+            .query_one(
+                &query,
+                &[
+                    &user_id,
+                    &request.token,
+                    &request.platform.as_str(),
+                    &request.provider(),
+                    &request.app_id,
+                    &request.locale,
+                    &request.timezone,
+                ],
+            )
+            .await
+            .map_err(|e| anyhow!("Failed to register device: {}", e))?;
+
+        let device = device_from_row(&row)?;
+
+        debug!(device_id = %device.id, user_id = %user_id, "Device registered");
+
+        Ok(device)
+    }
+
+    /// Bumps `last_seen` and applies any rotated token or locale change.
+    /// A rotated token revives a device that had been invalidated.
+    pub async fn refresh(
+        &self,
+        user_id: Uuid,
+        device_id: Uuid,
+        request: &RefreshDeviceRequest,
+    ) -> Result<Option<DeviceToken>, Error> {
+        if let Some(token) = &request.token {
+            // The new token may already be registered as a separate row
+            self.client
+                .execute(
+                    "DELETE FROM device_tokens WHERE token = $1 AND id <> $2",
+                    &[token, &device_id],
+                )
+                .await
+                .map_err(|e| anyhow!("Failed to refresh device: {}", e))?;
+        }
+
+        let query = format!(
+            r#"
+            UPDATE device_tokens SET
+                token = COALESCE($3, token),
+                locale = COALESCE($4, locale),
+                timezone = COALESCE($5, timezone),
+                invalidated_at = CASE WHEN $3::TEXT IS NULL THEN invalidated_at END,
+                last_seen = NOW(),
+                updated_at = NOW()
+            WHERE id = $1 AND user_id = $2
+            RETURNING {}
+            "#,
+            DEVICE_TOKEN_COLUMNS
+        );
+
+        let rows = self
+            .client
+            .query(
+                &query,
+                &[
+                    &device_id,
+                    &user_id,
+                    &request.token,
+                    &request.locale,
+                    &request.timezone,
+                ],
+            )
+            .await
+            .map_err(|e| anyhow!("Failed to refresh device: {}", e))?;
+
+        rows.first().map(device_from_row).transpose()
+    }
+
+    pub async fn find(&self, user_id: Uuid, device_id: Uuid) -> Result<Option<DeviceToken>, Error> {
+        let query = format!(
+            "SELECT {} FROM device_tokens WHERE id = $1 AND user_id = $2",
+            DEVICE_TOKEN_COLUMNS
+        );
+
+        let rows = self
+            .client
+            .query(&query, &[&device_id, &user_id])
+            .await
+            .map_err(|e| anyhow!("Failed to fetch device: {}", e))?;
+
+        rows.first().map(device_from_row).transpose()
+    }
+
+    pub async fn list_for_user(
+        &self,
+        user_id: Uuid,
+        include_invalidated: bool,
+    ) -> Result<Vec<DeviceToken>, Error> {
+        let query = format!(
+            r#"
+            SELECT {}
+            FROM device_tokens
+            WHERE user_id = $1 AND ($2 OR invalidated_at IS NULL)
+            ORDER BY last_seen DESC
+            "#,
+            DEVICE_TOKEN_COLUMNS
+        );
+
+        let rows = self
+            .client
+            .query(&query, &[&user_id, &include_invalidated])
+            .await
+            .map_err(|e| anyhow!("Failed to list devices: {}", e))?;
+
+        rows.iter().map(device_from_row).collect()
+    }
+
+    pub async fn delete(&self, user_id: Uuid, device_id: Uuid) -> Result<bool, Error> {
+        let deleted = self
+            .client
+            .execute(
+                "DELETE FROM device_tokens WHERE id = $1 AND user_id = $2",
+                &[&device_id, &user_id],
+            )
+            .await
+            .map_err(|e| anyhow!("Failed to delete device: {}", e))?;
+
+        Ok(deleted > 0)
+    }
+
+    /// Stops sending to every registered device using this target. Returns
+    /// the rows that were invalidated by this call.
+    pub async fn invalidate(&self, target: &PushTarget) -> Result<Vec<DeviceToken>, Error> {
+        let filter = match target {
+            PushTarget::Token(_) => "token = $1".to_string(),
+            PushTarget::WebPush(_) => {
+                format!("provider = 'webpush' AND {} = $1", DEVICE_TOKEN_IDENTIFIER)
+            }
+            PushTarget::Topic(_) | PushTarget::Condition(_) => return Ok(Vec::new()),
+        };
+
+        let query = format!(
+            r#"
+            UPDATE device_tokens SET
+                invalidated_at = NOW(),
+                updated_at = NOW()
+            WHERE {} AND invalidated_at IS NULL
+            RETURNING {}
+            "#,
+            filter, DEVICE_TOKEN_COLUMNS
+        );
+
+        let rows = self
+            .client
+            .query(&query, &[&target.identifier()])
+            .await
+            .map_err(|e| anyhow!("Failed to invalidate device: {}", e))?;
+
+        let devices = rows
+            .iter()
+            .map(device_from_row)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        for device in &devices {
+            info!(device_id = %device.id, user_id = %device.user_id, "Device token invalidated");
+        }
+
+        Ok(devices)
+    }
+
+    /// Which of the given target identifiers belong to invalidated devices
+    pub async fn invalidated_identifiers(
+        &self,
+        identifiers: &[String],
+    ) -> Result<HashSet<String>, Error> {
+        if identifiers.is_empty() {
+            return Ok(HashSet::new());
+        }
+
+        let query = format!(
+            r#"
+            SELECT {identifier} AS identifier
+            FROM device_tokens
+            WHERE invalidated_at IS NOT NULL AND {identifier} = ANY($1)
+            "#,
+            identifier = DEVICE_TOKEN_IDENTIFIER
+        );
+
+        let rows = self
+            .client
+            .query(&query, &[&identifiers])
+            .await
+            .map_err(|e| anyhow!("Failed to look up invalidated devices: {}", e))?;
+
+        Ok(rows.iter().map(|row| row.get("identifier")).collect())
+    }
+
+    pub async fn pruned_stats(&self) -> Result<PrunedTokenStats, Error> {
+        let row = self
+            .client
+            .query_one(
+                r#"
+                SELECT
+                    COUNT(*) AS total,
+                    COUNT(*) FILTER (WHERE invalidated_at > NOW() - INTERVAL '24 hours') AS last_24h
+                FROM device_tokens
+                WHERE invalidated_at IS NOT NULL
+                "#,
+                &[],
+            )
+            .await
+            .map_err(|e| anyhow!("Failed to count pruned devices: {}", e))?;
+
+        Ok(PrunedTokenStats {
+            total: row.get("total"),
+            last_24h: row.get("last_24h"),
+        })
+    }
+}
+
+pub struct ScheduledNotificationRepository<'a> {
+    client: &'a Client,
+}
+
+impl ScheduledNotificationRepository<'_> {
+    /// Holds a message back until `deliver_at`. A redelivery of a message
+    /// that is already waiting keeps the existing row; one that was already
+    /// dispatched is scheduled again. Returns `None` if the message was
+    /// cancelled, in which case it must not be sent.
+    pub async fn schedule(
+        &self,
+        idempotency_key: &str,
+        trace_id: &str,
+        user_id: Uuid,
+        reason: ScheduleReason,
+        payload: &JsonValue,
+        deliver_at: DateTime<Utc>,
+    ) -> Result<Option<ScheduledNotification>, Error> {
+        let query = format!(
+            r#"
+            INSERT INTO scheduled_notifications (idempotency_key, trace_id, user_id, reason, payload, deliver_at)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            ON CONFLICT (idempotency_key) DO UPDATE SET
+                trace_id = EXCLUDED.trace_id,
+                reason = EXCLUDED.reason,
+                payload = EXCLUDED.payload,
+                deliver_at = CASE
+                    WHEN scheduled_notifications.status = 'pending'
+                    THEN scheduled_notifications.deliver_at
+                    ELSE EXCLUDED.deliver_at
+                END,
+                status = 'pending',
+                updated_at = NOW()
+            WHERE scheduled_notifications.status <> 'cancelled'
+            RETURNING {}
+            "#,
+            SCHEDULED_NOTIFICATION_COLUMNS
+        );
+
+        let rows = self
+            .client
+            .query(
+                &query,
+                &[
+                    &idempotency_key,
+                    &trace_id,
+                    &user_id,
+                    &reason.as_str(),
+                    payload,
+                    &deliver_at,
+                ],
+            )
+            .await
+            .map_err(|e| anyhow!("Failed to schedule notification: {}", e))?;
+
+        let Some(row) = rows.first() else {
+            debug!(idempotency_key, "Scheduled notification was cancelled");
+            return Ok(None);
+        };
+
+        let scheduled = scheduled_from_row(row)?;
+
+        debug!(
+            id = %scheduled.id,
+            idempotency_key,
+            deliver_at = %scheduled.deliver_at,
+            "Notification scheduled"
+        );
+
+        Ok(Some(scheduled))
+    }
+
+    /// Claims up to `limit` due messages and returns them. A claim that was
+    /// not confirmed with `mark_dispatched` within `lease`, e.g. because the
+    /// replica holding it died, is handed out again. Rows locked by another
+    /// replica are skipped rather than waited on.
+    pub async fn claim_due(
+        &self,
+        limit: i64,
+        lease: Duration,
+    ) -> Result<Vec<ScheduledNotification>, Error> {
+        let query = format!(
+            r#"
+            UPDATE scheduled_notifications SET
+                status = 'dispatching',
+                claimed_at = NOW(),
+                updated_at = NOW()
+            WHERE id IN (
+                SELECT id
+                FROM scheduled_notifications
+                WHERE (status = 'pending' AND deliver_at <= NOW())
+                    OR (status = 'dispatching' AND claimed_at <= NOW() - make_interval(secs => $2))
+                ORDER BY deliver_at
+                LIMIT $1
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING {}
+            "#,
+            SCHEDULED_NOTIFICATION_COLUMNS
+        );
+
+        let rows = self
+            .client
+            .query(&query, &[&limit, &lease.as_secs_f64()])
+            .await
+            .map_err(|e| anyhow!("Failed to claim scheduled notifications: {}", e))?;
+
+        rows.iter().map(scheduled_from_row).collect()
+    }
+
+    /// Settles a claimed message once the broker has confirmed the publish.
+    pub async fn mark_dispatched(&self, id: Uuid) -> Result<(), Error> {
+        self.client
+            .execute(
+                r#"
+                UPDATE scheduled_notifications SET
+                    status = 'dispatched',
+                    updated_at = NOW()
+                WHERE id = $1 AND status = 'dispatching'
+                "#,
+                &[&id],
+            )
+            .await
+            .map_err(|e| anyhow!("Failed to mark scheduled notification as dispatched: {}", e))?;
+
+        Ok(())
+    }
+
+    /// Puts a claimed message back so the next poll picks it up again.
+    pub async fn release(&self, id: Uuid) -> Result<(), Error> {
+        self.client
+            .execute(
+                r#"
+                UPDATE scheduled_notifications SET
+                    status = 'pending',
+                    updated_at = NOW()
+                WHERE id = $1 AND status = 'dispatching'
+                "#,
+                &[&id],
+            )
+            .await
+            .map_err(|e| anyhow!("Failed to release scheduled notification: {}", e))?;
+
+        Ok(())
+    }
+
+    pub async fn list_for_request(
+        &self,
+        request_id: &str,
+    ) -> Result<Vec<ScheduledNotification>, Error> {
+        let query = format!(
+            r#"
+            SELECT {}
+            FROM scheduled_notifications
+            WHERE trace_id = $1
+            ORDER BY deliver_at
+            "#,
+            SCHEDULED_NOTIFICATION_COLUMNS
+        );
+
+        let rows = self
+            .client
+            .query(&query, &[&request_id])
+            .await
+            .map_err(|e| anyhow!("Failed to list scheduled notifications: {}", e))?;
+
+        rows.iter().map(scheduled_from_row).collect()
+    }
+
+    /// Cancels every message of the request that is still waiting. Messages
+    /// already handed to the push queue can no longer be stopped.
+    pub async fn cancel(&self, request_id: &str) -> Result<Vec<ScheduledNotification>, Error> {
+        let query = format!(
+            r#"
+            UPDATE scheduled_notifications SET
+                status = 'cancelled',
+                updated_at = NOW()
+            WHERE trace_id = $1 AND status = 'pending'
+            RETURNING {}
+            "#,
+            SCHEDULED_NOTIFICATION_COLUMNS
+        );
+
+        let rows = self
+            .client
+            .query(&query, &[&request_id])
+            .await
+            .map_err(|e| anyhow!("Failed to cancel scheduled notifications: {}", e))?;
+
+        let cancelled = rows
+            .iter()
+            .map(scheduled_from_row)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        for scheduled in &cancelled {
+            info!(id = %scheduled.id, request_id, "Scheduled notification cancelled");
+        }
+
+        Ok(cancelled)
+    }
+
+    pub async fn find_by_idempotency_key(
+        &self,
+        idempotency_key: &str,
+    ) -> Result<Option<ScheduledNotification>, Error> {
+        let query = format!(
+            "SELECT {} FROM scheduled_notifications WHERE idempotency_key = $1",
+            SCHEDULED_NOTIFICATION_COLUMNS
+        );
+
+        let rows = self
+            .client
+            .query(&query, &[&idempotency_key])
+            .await
+            .map_err(|e| anyhow!("Failed to fetch scheduled notification: {}", e))?;
+
+        rows.first().map(scheduled_from_row).transpose()
+    }
+}
+
+fn scheduled_from_row(row: &Row) -> Result<ScheduledNotification, Error> {
+    let reason: String = row.get("reason");
+    let status: String = row.get("status");
+
+    Ok(ScheduledNotification {
+        id: row.get("id"),
+        idempotency_key: row.get("idempotency_key"),
+        trace_id: row.get("trace_id"),
+        user_id: row.get("user_id"),
+        reason: ScheduleReason::from_string(&reason)?,
+        payload: row.get("payload"),
+        deliver_at: row.get("deliver_at"),
+        status: ScheduleStatus::from_string(&status)?,
+        created_at: row.get("created_at"),
+        updated_at: row.get("updated_at"),
+    })
+}
+
+fn device_from_row(row: &Row) -> Result<DeviceToken, Error> {
+    let platform: String = row.get("platform");
+
+    Ok(DeviceToken {
+        id: row.get("id"),
+        user_id: row.get("user_id"),
+        token: row.get("token"),
+        platform: DevicePlatform::from_string(&platform)?,
+        provider: row.get("provider"),
+        app_id: row.get("app_id"),
+        locale: row.get("locale"),
+        timezone: row.get("timezone"),
+        last_seen: row.get("last_seen"),
+        invalidated_at: row.get("invalidated_at"),
+        created_at: row.get("created_at"),
+        updated_at: row.get("updated_at"),
+    })
+}
@@ -0,0 +1,23 @@
+use anyhow::{Error, Result};
+use async_trait::async_trait;
+use serde::Serialize;
+
+use crate::models::event::EventEnvelope;
+
+#[async_trait]
+pub trait EventPublisher: Send + Sync {
+    /// Publishes an already serialized event under the given routing key.
+    async fn publish(&self, routing_key: &str, payload: &[u8]) -> Result<(), Error>;
+}
+
+/// Wraps `data` in an event envelope and publishes it with the pattern as
+/// the routing key.
+pub async fn publish_event<T: Serialize>(
+    publisher: &dyn EventPublisher,
+    pattern: &str,
+    data: &T,
+) -> Result<(), Error> {
+    let payload = serde_json::to_vec(&EventEnvelope { pattern, data })?;
+
+    publisher.publish(pattern, &payload).await
+}
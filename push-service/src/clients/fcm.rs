@@ -1,122 +1,466 @@
 use std::collections::HashMap;
 
 use anyhow::{Error, Result, anyhow};
-use reqwest::Client;
-use tracing::{debug, info};
+use async_trait::async_trait;
+use chrono::Utc;
+use reqwest::{Client, Response, header::RETRY_AFTER};
+use tokio::time::sleep;
+use tracing::{debug, info, warn};
 
 use crate::{
-    clients::circuit_breaker::CircuitBreaker,
+    clients::{circuit_breaker::CircuitBreaker, fcm_auth::FcmCredentials, provider::PushProvider},
     config::Config,
     models::{
-        fcm::{FcmMessage, FcmNotification, FcmRequest},
-        retry::RetryConfig,
+        fcm::{
+            AndroidConfig, AndroidMessagePriority, AndroidNotification, ApnsConfig,
+            ApnsConfigPayload, FcmAps, FcmError, FcmMessage, FcmNotification, FcmOptions,
+            FcmRequest, FcmResponse, FcmTarget, IidBatchRequest, IidBatchResponse, TopicAction,
+            TopicManagementError, TopicManagementResult, WebpushConfig, WebpushFcmOptions,
+        },
+        provider::{DeliveryErrorKind, PushNotification, PushPriority, PushTarget, SendReceipt},
+        retry::{RetryConfig, RetryDecision},
+        validation::{validate_fcm_condition, validate_fcm_token, validate_fcm_topic},
     },
-    utils::retry_with_backoff,
+    utils::{parse_retry_after, retry_with_backoff_policy},
 };
 
+/// Max registration tokens accepted by a single IID batch request
+const IID_BATCH_SIZE: usize = 1000;
+
+#[derive(Clone)]
 pub struct FcmClient {
     http_client: Client,
     fcm_project_id: String,
+    base_url: String,
+    iid_base_url: String,
+    credentials: FcmCredentials,
     retry_config: RetryConfig,
     circuit_breaker: CircuitBreaker,
 }
 
 impl FcmClient {
-    pub async fn new(config: &Config, circuit_breaker: CircuitBreaker) -> Self {
-        info!(project_id = %config.fcm_project_id, "FCM client initialized");
+    pub async fn new(config: &Config, circuit_breaker: CircuitBreaker) -> Result<Self, Error> {
+        let credentials = FcmCredentials::from_config(config)?;
+        credentials.spawn_refresh();
+
+        let base_url = config.fcm_base_url.trim_end_matches('/').to_string();
+
+        info!(
+            project_id = %config.fcm_project_id,
+            base_url = %base_url,
+            auth_mode = ?config.fcm_auth_mode,
+            "FCM client initialized"
+        );
 
-        Self {
+        Ok(Self {
             http_client: Client::new(),
             fcm_project_id: config.fcm_project_id.clone(),
+            base_url,
+            iid_base_url: config.fcm_iid_base_url.trim_end_matches('/').to_string(),
+            credentials,
             retry_config: config.retry_config(),
             circuit_breaker,
-        }
+        })
     }
 
-    pub async fn send_notification(
-        &mut self,
-        device_token: &str,
-        title: &str,
-        body: &str,
-        trace_id: &str,
-        data: Option<HashMap<String, String>>,
-    ) -> Result<(), Error> {
-        debug!(device_token, trace_id, "Sending FCM push notification");
-
-        let mut payload_data = data.unwrap_or_default();
-        payload_data.insert("trace_id".to_string(), trace_id.to_string());
-
-        let message = FcmMessage {
-            token: device_token.to_string(),
-            notification: FcmNotification {
-                title: title.to_string(),
-                body: body.to_string(),
-            },
-            data: Some(payload_data),
-        };
-
-        let request = FcmRequest { message };
+    pub fn credentials(&self) -> FcmCredentials {
+        self.credentials.clone()
+    }
 
-        let http_client = self.http_client.clone();
-        let fcm_project_id = self.fcm_project_id.clone();
-        let retry_config = self.retry_config.clone();
+    pub async fn subscribe_to_topic(
+        &self,
+        topic: &str,
+        tokens: &[String],
+    ) -> Result<TopicManagementResult, Error> {
+        self.manage_topic(TopicAction::Subscribe, topic, tokens)
+            .await
+    }
 
-        self.circuit_breaker
-            .call(|| {
-                Self::send_with_retry_static(
-                    http_client.clone(),
-                    fcm_project_id.clone(),
-                    retry_config.clone(),
-                    request.clone(),
-                )
-            })
+    pub async fn unsubscribe_from_topic(
+        &self,
+        topic: &str,
+        tokens: &[String],
+    ) -> Result<TopicManagementResult, Error> {
+        self.manage_topic(TopicAction::Unsubscribe, topic, tokens)
             .await
     }
 
-    async fn send_with_retry_static(
-        http_client: Client,
-        fcm_project_id: String,
-        retry_config: RetryConfig,
-        request: FcmRequest,
-    ) -> Result<(), Error> {
-        retry_with_backoff(&retry_config, || {
-            Self::send_notification_once_static(
-                http_client.clone(),
-                fcm_project_id.clone(),
-                &request,
+    async fn manage_topic(
+        &self,
+        action: TopicAction,
+        topic: &str,
+        tokens: &[String],
+    ) -> Result<TopicManagementResult, Error> {
+        validate_fcm_topic(topic)?;
+
+        let mut result = TopicManagementResult::default();
+
+        for (batch, chunk) in tokens.chunks(IID_BATCH_SIZE).enumerate() {
+            let request = IidBatchRequest {
+                to: format!("/topics/{}", topic),
+                registration_tokens: chunk.to_vec(),
+            };
+
+            let response = retry_with_backoff_policy(
+                &self.retry_config,
+                || self.send_iid_batch_once(action, &request),
+                Self::retry_decision,
             )
-        })
-        .await
+            .await?;
+
+            // Results come back in the same order as the tokens we sent
+            for (offset, (token, item)) in chunk.iter().zip(response.results).enumerate() {
+                match item.error {
+                    None => result.success_count += 1,
+                    Some(reason) => {
+                        result.failure_count += 1;
+                        result.errors.push(TopicManagementError {
+                            index: batch * IID_BATCH_SIZE + offset,
+                            token: token.clone(),
+                            reason,
+                        });
+                    }
+                }
+            }
+        }
+
+        info!(
+            topic = %topic,
+            method = action.method(),
+            success_count = result.success_count,
+            failure_count = result.failure_count,
+            "FCM topic subscriptions updated"
+        );
+
+        Ok(result)
     }
 
-    async fn send_notification_once_static(
-        http_client: Client,
-        fcm_project_id: String,
-        request: &FcmRequest,
-    ) -> Result<(), Error> {
-        let provider = gcp_auth::provider().await?;
-        let scopes = &["https://www.googleapis.com/auth/firebase.messaging"];
+    async fn send_iid_batch_once(
+        &self,
+        action: TopicAction,
+        request: &IidBatchRequest,
+    ) -> Result<IidBatchResponse, Error> {
+        let token = self.credentials.token().await?;
+
+        let url = format!("{}/iid/v1:{}", self.iid_base_url, action.method());
+
+        // IID only accepts OAuth2 tokens when this header is present
+        let mut http_request = self
+            .http_client
+            .post(&url)
+            .header("access_token_auth", "true")
+            .json(request);
+
+        if let Some(token) = token {
+            http_request = http_request.bearer_auth(token);
+        }
 
-        let token = provider.token(scopes).await?;
+        let response = http_request.send().await?;
+        let status = response.status();
+
+        if status.is_success() {
+            return Ok(response.json().await?);
+        }
+
+        Err(self.rejection(response).await)
+    }
+
+    async fn send_with_retry(&self, request: &FcmRequest) -> Result<SendReceipt, Error> {
+        retry_with_backoff_policy(
+            &self.retry_config,
+            || self.send_notification_once(request),
+            Self::retry_decision,
+        )
+        .await
+    }
+
+    async fn send_notification_once(&self, request: &FcmRequest) -> Result<SendReceipt, Error> {
+        let token = self.credentials.token().await?;
 
         let url = format!(
-            "https://fcm.googleapis.com/v1/projects/{}/messages:send",
-            fcm_project_id
+            "{}/v1/projects/{}/messages:send",
+            self.base_url, self.fcm_project_id
         );
 
-        let response = http_client
-            .post(&url)
-            .bearer_auth(token.as_str())
-            .json(&request)
-            .send()
-            .await?;
+        let mut http_request = self.http_client.post(&url).json(request);
+
+        if let Some(token) = token {
+            http_request = http_request.bearer_auth(token);
+        }
+
+        let response = http_request.send().await?;
+
+        let status = response.status();
+
+        if status.is_success() {
+            // FCM has accepted the message at this point, so an unreadable
+            // body must not send it down a retry path a second time
+            let message_id = match response.json::<FcmResponse>().await {
+                Ok(fcm_response) => fcm_response.name,
+                Err(e) => {
+                    warn!(error = %e, "FCM accepted the message but its response could not be parsed");
+                    None
+                }
+            };
+
+            info!(message_id = ?message_id, "FCM push notification sent successfully");
+            return Ok(SendReceipt { message_id });
+        }
+
+        Err(self.rejection(response).await)
+    }
+
+    /// Turns a non-success response from FCM or IID into an error.
+    async fn rejection(&self, response: Response) -> Error {
+        let status = response.status();
+        let retry_after = response
+            .headers()
+            .get(RETRY_AFTER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(parse_retry_after);
+
+        let error_text = response.text().await.unwrap_or_default();
+        let error =
+            FcmError::from_response(status.as_u16(), &error_text).with_retry_after(retry_after);
+
+        self.drop_rejected_token(&error);
+
+        // Quota is per project, so every worker has to back off, not just
+        // the one that happened to receive the 429.
+        if let Some(delay) = error.retry_after()
+            && let Err(e) = self.circuit_breaker.extend_backoff(delay).await
+        {
+            return e;
+        }
+
+        error.into()
+    }
+
+    /// A 401 means our access token is no good; forget it so the retry
+    /// fetches a new one.
+    fn drop_rejected_token(&self, error: &FcmError) {
+        if matches!(error, FcmError::Unauthenticated { .. }) {
+            warn!("FCM rejected the access token, fetching a new one");
+            self.credentials.invalidate();
+        }
+    }
+
+    async fn wait_for_backoff(&self) -> Result<(), Error> {
+        let Some(remaining) = self.circuit_breaker.backoff_remaining().await? else {
+            return Ok(());
+        };
+
+        if remaining.as_millis() as u64 > self.retry_config.max_delay_ms {
+            return Err(FcmError::QuotaExceeded {
+                message: "FCM backoff in effect".to_string(),
+                retry_after: Some(remaining),
+            }
+            .into());
+        }
+
+        debug!(
+            remaining_ms = remaining.as_millis() as u64,
+            "Waiting for shared FCM backoff to expire"
+        );
+
+        sleep(remaining).await;
+
+        Ok(())
+    }
 
-        if response.status().is_success() {
-            info!("FCM push notification sent successfully");
-            Ok(())
+    fn build_message(target: FcmTarget, notification: &PushNotification) -> FcmMessage {
+        let options = &notification.options;
+
+        let mut data = notification.data.clone().unwrap_or_default();
+        data.insert("trace_id".to_string(), notification.trace_id.clone());
+
+        // Silent messages still carry the rendered text so the app can show
+        // it on its own terms.
+        let fcm_notification = if options.data_only {
+            data.entry("title".to_string())
+                .or_insert_with(|| notification.title.clone());
+            data.entry("body".to_string())
+                .or_insert_with(|| notification.body.clone());
+            None
         } else {
-            let error_text = response.text().await?;
-            Err(anyhow!("FCM request failed: {}", error_text))
+            Some(FcmNotification {
+                title: notification.title.clone(),
+                body: notification.body.clone(),
+                image: options.image.clone(),
+            })
+        };
+
+        let ttl_seconds = notification
+            .expires_at
+            .map(|expires_at| (expires_at - Utc::now()).num_seconds().max(0));
+
+        let android_notification = AndroidNotification {
+            icon: options.icon.clone(),
+            color: options.color.clone(),
+            sound: options.sound.clone(),
+            click_action: options.click_action.clone(),
+            channel_id: options.channel_id.clone(),
+        };
+
+        let android = AndroidConfig {
+            priority: match notification.priority {
+                PushPriority::High => AndroidMessagePriority::High,
+                PushPriority::Normal => AndroidMessagePriority::Normal,
+            },
+            collapse_key: notification.collapse_key.clone(),
+            ttl: ttl_seconds.map(|seconds| format!("{}s", seconds)),
+            notification: (!options.data_only && android_notification != Default::default())
+                .then_some(android_notification),
+        };
+
+        // APNs rejects high priority background pushes, so silent messages
+        // always go out at priority 5.
+        let (apns_push_type, apns_priority) = match (options.data_only, notification.priority) {
+            (true, _) => ("background", "5"),
+            (false, PushPriority::High) => ("alert", "10"),
+            (false, PushPriority::Normal) => ("alert", "5"),
+        };
+
+        let mut apns_headers = HashMap::from([
+            ("apns-push-type".to_string(), apns_push_type.to_string()),
+            ("apns-priority".to_string(), apns_priority.to_string()),
+        ]);
+
+        if let Some(collapse_id) = &notification.collapse_key {
+            apns_headers.insert("apns-collapse-id".to_string(), collapse_id.clone());
+        }
+
+        if let Some(expires_at) = notification.expires_at {
+            apns_headers.insert(
+                "apns-expiration".to_string(),
+                expires_at.timestamp().to_string(),
+            );
+        }
+
+        let aps = if options.data_only {
+            FcmAps {
+                content_available: Some(1),
+                ..Default::default()
+            }
+        } else {
+            FcmAps {
+                badge: options.badge,
+                sound: options.sound.clone(),
+                category: options.category.clone(),
+                // Images are only attached on iOS by a service extension
+                mutable_content: (options.mutable_content || options.image.is_some()).then_some(1),
+                content_available: None,
+            }
+        };
+
+        let mut webpush_headers = HashMap::from([(
+            "Urgency".to_string(),
+            match notification.priority {
+                PushPriority::High => "high",
+                PushPriority::Normal => "normal",
+            }
+            .to_string(),
+        )]);
+
+        if let Some(seconds) = ttl_seconds {
+            webpush_headers.insert("TTL".to_string(), seconds.to_string());
+        }
+
+        FcmMessage {
+            target,
+            notification: fcm_notification,
+            data: Some(data),
+            android: Some(android),
+            apns: Some(ApnsConfig {
+                headers: apns_headers,
+                payload: ApnsConfigPayload { aps },
+            }),
+            webpush: Some(WebpushConfig {
+                headers: webpush_headers,
+                fcm_options: options.link.clone().map(|link| WebpushFcmOptions { link }),
+            }),
+            fcm_options: options
+                .analytics_label
+                .clone()
+                .map(|analytics_label| FcmOptions { analytics_label }),
+        }
+    }
+
+    fn retry_decision(error: &Error) -> RetryDecision {
+        match error.downcast_ref::<FcmError>() {
+            Some(e) if e.kind() != DeliveryErrorKind::Retryable => RetryDecision::Abort,
+            Some(e) => e
+                .retry_after()
+                .map(RetryDecision::RetryAfter)
+                .unwrap_or(RetryDecision::Retry),
+            None => RetryDecision::Retry,
+        }
+    }
+
+    fn classify(error: &Error) -> DeliveryErrorKind {
+        error
+            .downcast_ref::<FcmError>()
+            .map(|e| e.kind())
+            .unwrap_or(DeliveryErrorKind::Retryable)
+    }
+
+    fn is_retryable(error: &Error) -> bool {
+        Self::classify(error) == DeliveryErrorKind::Retryable
+    }
+}
+
+#[async_trait]
+impl PushProvider for FcmClient {
+    fn name(&self) -> &str {
+        "fcm"
+    }
+
+    async fn send(&self, notification: &PushNotification) -> Result<SendReceipt, Error> {
+        let target = match &notification.target {
+            PushTarget::Token(token) => FcmTarget::Token(token.clone()),
+            PushTarget::Topic(topic) => FcmTarget::Topic(topic.clone()),
+            PushTarget::Condition(condition) => FcmTarget::Condition(condition.clone()),
+            other => {
+                return Err(anyhow!("FCM does not support {} targets", other.kind()));
+            }
+        };
+
+        debug!(
+            target_kind = notification.target.kind(),
+            target = %notification.target.identifier(),
+            trace_id = %notification.trace_id,
+            "Sending FCM push notification"
+        );
+
+        let message = Self::build_message(target, notification);
+
+        let request = FcmRequest { message };
+
+        self.wait_for_backoff().await?;
+
+        // Only errors worth retrying say anything about FCM's health; a dead
+        // token or a malformed message must not trip the breaker.
+        self.circuit_breaker
+            .call_with_filter(|| self.send_with_retry(&request), Self::is_retryable)
+            .await
+    }
+
+    fn classify_error(&self, error: &Error) -> DeliveryErrorKind {
+        Self::classify(error)
+    }
+
+    fn error_code(&self, error: &Error) -> Option<String> {
+        error
+            .downcast_ref::<FcmError>()
+            .map(|e| e.code().to_string())
+    }
+
+    fn validate_target(&self, target: &PushTarget) -> Result<(), Error> {
+        match target {
+            PushTarget::Token(token) => validate_fcm_token(token),
+            PushTarget::Topic(topic) => validate_fcm_topic(topic),
+            PushTarget::Condition(condition) => validate_fcm_condition(condition),
+            other => Err(anyhow!("FCM does not support {} targets", other.kind())),
         }
     }
 }
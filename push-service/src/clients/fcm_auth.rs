@@ -0,0 +1,271 @@
+use std::{
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use anyhow::{Error, Result, anyhow};
+use chrono::Utc;
+use gcp_auth::{Token, TokenProvider};
+use tokio::{
+    sync::{Mutex, OnceCell},
+    task::JoinHandle,
+    time::sleep,
+};
+use tracing::{debug, info, warn};
+
+use crate::{
+    config::Config,
+    models::{fcm::FcmAuthMode, health::ServiceHealth},
+};
+
+const FCM_SCOPES: &[&str] = &["https://www.googleapis.com/auth/firebase.messaging"];
+
+// Cached tokens stop being handed out this close to expiry so a request
+// never leaves with a token that dies in flight.
+const MIN_TOKEN_LIFETIME: Duration = Duration::from_secs(10);
+
+const MIN_REFRESH_INTERVAL: Duration = Duration::from_secs(5);
+const FAILED_REFRESH_DELAY: Duration = Duration::from_secs(10);
+
+#[derive(Clone)]
+pub struct FcmCredentials {
+    source: CredentialSource,
+}
+
+#[derive(Clone)]
+enum CredentialSource {
+    Managed(Arc<CredentialsState>),
+    Static(Arc<str>),
+    Disabled,
+}
+
+struct CredentialsState {
+    provider: OnceCell<Arc<dyn TokenProvider>>,
+    token: RwLock<Option<Arc<Token>>>,
+    last_error: RwLock<Option<String>>,
+    refresh_lock: Mutex<()>,
+    refresh_margin: Duration,
+}
+
+impl FcmCredentials {
+    pub fn from_config(config: &Config) -> Result<Self, Error> {
+        let refresh_margin = Duration::from_secs(config.fcm_token_refresh_margin_seconds);
+
+        match config.fcm_auth_mode {
+            FcmAuthMode::ApplicationDefault => Ok(Self::new(refresh_margin)),
+            FcmAuthMode::ServiceAccount => {
+                let path = config.fcm_service_account_path.as_ref().ok_or_else(|| {
+                    anyhow!("FCM_SERVICE_ACCOUNT_PATH is required for service_account auth")
+                })?;
+
+                let service_account = gcp_auth::CustomServiceAccount::from_file(path)
+                    .map_err(|e| anyhow!("Failed to load FCM service account {}: {}", path, e))?;
+
+                Ok(Self::with_provider(
+                    Arc::new(service_account),
+                    refresh_margin,
+                ))
+            }
+            FcmAuthMode::StaticToken => {
+                let token = config
+                    .fcm_static_token
+                    .as_deref()
+                    .ok_or_else(|| anyhow!("FCM_STATIC_TOKEN is required for static_token auth"))?;
+
+                Ok(Self::static_token(token))
+            }
+            FcmAuthMode::None => Ok(Self::disabled()),
+        }
+    }
+
+    pub fn new(refresh_margin: Duration) -> Self {
+        Self::from_cell(OnceCell::new(), refresh_margin)
+    }
+
+    pub fn static_token(token: &str) -> Self {
+        Self {
+            source: CredentialSource::Static(Arc::from(token)),
+        }
+    }
+
+    pub fn disabled() -> Self {
+        Self {
+            source: CredentialSource::Disabled,
+        }
+    }
+
+    pub fn with_provider(provider: Arc<dyn TokenProvider>, refresh_margin: Duration) -> Self {
+        Self::from_cell(OnceCell::new_with(Some(provider)), refresh_margin)
+    }
+
+    fn from_cell(provider: OnceCell<Arc<dyn TokenProvider>>, refresh_margin: Duration) -> Self {
+        Self {
+            source: CredentialSource::Managed(Arc::new(CredentialsState {
+                provider,
+                token: RwLock::new(None),
+                last_error: RwLock::new(None),
+                refresh_lock: Mutex::new(()),
+                refresh_margin,
+            })),
+        }
+    }
+
+    /// Returns the bearer token to send, or `None` when auth is disabled.
+    pub async fn token(&self) -> Result<Option<String>, Error> {
+        let state = match &self.source {
+            CredentialSource::Managed(state) => state,
+            CredentialSource::Static(token) => return Ok(Some(token.to_string())),
+            CredentialSource::Disabled => return Ok(None),
+        };
+
+        if let Some(token) = state.cached_token() {
+            return Ok(Some(token.as_str().to_string()));
+        }
+
+        let _guard = state.refresh_lock.lock().await;
+
+        // Another sender may have refreshed while we waited for the lock
+        if let Some(token) = state.cached_token() {
+            return Ok(Some(token.as_str().to_string()));
+        }
+
+        let token = state.fetch_token().await?;
+
+        Ok(Some(token.as_str().to_string()))
+    }
+
+    /// Drops the cached token after FCM rejected it, so the next send
+    /// fetches a new one instead of reusing it until it expires.
+    pub fn invalidate(&self) {
+        if let CredentialSource::Managed(state) = &self.source {
+            *state.token.write().unwrap() = None;
+        }
+    }
+
+    /// Keeps the cached token fresh from a background task so sends never
+    /// wait on the token endpoint. The task stops once every handle to these
+    /// credentials has been dropped. Static and disabled credentials have
+    /// nothing to refresh.
+    pub fn spawn_refresh(&self) -> Option<JoinHandle<()>> {
+        let CredentialSource::Managed(state) = &self.source else {
+            return None;
+        };
+
+        let state = Arc::downgrade(state);
+
+        Some(tokio::spawn(async move {
+            loop {
+                let Some(state) = state.upgrade() else {
+                    debug!("FCM credentials dropped, stopping token refresh");
+                    break;
+                };
+
+                let delay = {
+                    let _guard = state.refresh_lock.lock().await;
+
+                    match state.fetch_token().await {
+                        Ok(_) => state.next_refresh_in(),
+                        Err(_) => FAILED_REFRESH_DELAY,
+                    }
+                };
+
+                drop(state);
+                sleep(delay).await;
+            }
+        }))
+    }
+
+    pub fn health(&self) -> ServiceHealth {
+        let state = match &self.source {
+            CredentialSource::Managed(state) => state,
+            CredentialSource::Static(_) | CredentialSource::Disabled => {
+                return ServiceHealth::healthy(0);
+            }
+        };
+
+        let last_error = state.last_error.read().unwrap().clone();
+
+        match (state.cached_token(), last_error) {
+            (Some(_), None) => ServiceHealth::healthy(0),
+            (Some(_), Some(error)) => {
+                ServiceHealth::degraded(format!("Token refresh failing: {}", error))
+            }
+            (None, Some(error)) => ServiceHealth::unhealthy(error),
+            (None, None) => ServiceHealth::degraded("Credentials not yet initialized".to_string()),
+        }
+    }
+}
+
+impl CredentialsState {
+    fn cached_token(&self) -> Option<Arc<Token>> {
+        self.token
+            .read()
+            .unwrap()
+            .as_ref()
+            .filter(|token| remaining_lifetime(token) > MIN_TOKEN_LIFETIME)
+            .cloned()
+    }
+
+    fn next_refresh_in(&self) -> Duration {
+        // gcp_auth keeps its own cache and only refetches shortly before
+        // expiry, so inside the margin we keep polling until a newer token
+        // comes back.
+        self.token
+            .read()
+            .unwrap()
+            .as_ref()
+            .map(|token| remaining_lifetime(token).saturating_sub(self.refresh_margin))
+            .unwrap_or_default()
+            .max(MIN_REFRESH_INTERVAL)
+    }
+
+    async fn fetch_token(&self) -> Result<Arc<Token>, Error> {
+        match self.request_token().await {
+            Ok(token) => {
+                let mut cached = self.token.write().unwrap();
+
+                let is_newer = cached
+                    .as_ref()
+                    .is_none_or(|existing| token.expires_at() > existing.expires_at());
+
+                if is_newer {
+                    debug!(expires_at = %token.expires_at(), "FCM access token refreshed");
+                    *cached = Some(Arc::clone(&token));
+                }
+
+                *self.last_error.write().unwrap() = None;
+
+                Ok(token)
+            }
+            Err(e) => {
+                warn!(error = %e, "Failed to obtain FCM access token");
+                *self.last_error.write().unwrap() = Some(e.to_string());
+                Err(e)
+            }
+        }
+    }
+
+    async fn request_token(&self) -> Result<Arc<Token>, Error> {
+        let provider = self
+            .provider
+            .get_or_try_init(|| async {
+                let provider = gcp_auth::provider()
+                    .await
+                    .map_err(|e| anyhow!("Failed to initialize GCP credentials: {}", e))?;
+                info!("GCP credentials provider initialized");
+                Ok::<_, Error>(provider)
+            })
+            .await?;
+
+        provider
+            .token(FCM_SCOPES)
+            .await
+            .map_err(|e| anyhow!("Failed to fetch FCM access token: {}", e))
+    }
+}
+
+fn remaining_lifetime(token: &Token) -> Duration {
+    (token.expires_at() - Utc::now())
+        .to_std()
+        .unwrap_or_default()
+}
@@ -1,4 +1,4 @@
-use std::{collections::HashMap, time::Instant};
+use std::{collections::HashMap, sync::Arc, time::Instant};
 
 use anyhow::Result;
 use chrono::Utc;
@@ -6,21 +6,39 @@ use redis::AsyncCommands;
 use tracing::{debug, warn};
 
 use crate::{
-    clients::{database::DatabaseClient, rbmq::RabbitMqClient},
+    clients::{database::DatabaseClient, fcm_auth::FcmCredentials, rbmq::RabbitMqClient},
     config::Config,
     models::{
         circuit_breaker::CircuitState,
-        health::{HealthCheckResponse, HealthStatus, ServiceHealth},
+        health::{BrokerConnectionState, HealthCheckResponse, HealthStatus, ServiceHealth},
     },
 };
 
 pub struct HealthChecker {
     config: Config,
+    fcm_credentials: Option<FcmCredentials>,
+    rabbitmq_client: Option<Arc<RabbitMqClient>>,
 }
 
 impl HealthChecker {
     pub fn new(config: Config) -> Self {
-        Self { config }
+        Self {
+            config,
+            fcm_credentials: None,
+            rabbitmq_client: None,
+        }
+    }
+
+    pub fn with_fcm_credentials(mut self, credentials: FcmCredentials) -> Self {
+        self.fcm_credentials = Some(credentials);
+        self
+    }
+
+    /// Reports the worker's own broker connection instead of opening a new
+    /// one on every check
+    pub fn with_rabbitmq_client(mut self, client: Arc<RabbitMqClient>) -> Self {
+        self.rabbitmq_client = Some(client);
+        self
     }
 
     pub async fn check_all(&self) -> HealthCheckResponse {
@@ -38,9 +56,23 @@ impl HealthChecker {
         let fcm_health = self.check_circuit_breaker("fcm").await;
         checks.insert("fcm".to_string(), fcm_health);
 
+        if let Some(credentials) = &self.fcm_credentials {
+            checks.insert("fcm_auth".to_string(), credentials.health());
+        }
+
         let template_health = self.check_circuit_breaker("template_service").await;
         checks.insert("template_service".to_string(), template_health);
 
+        if self.config.apns_key_path.is_some() {
+            let apns_health = self.check_circuit_breaker("apns").await;
+            checks.insert("apns".to_string(), apns_health);
+        }
+
+        if self.config.vapid_private_key.is_some() {
+            let webpush_health = self.check_circuit_breaker("webpush").await;
+            checks.insert("webpush".to_string(), webpush_health);
+        }
+
         let overall_status = self.determine_overall_status(&checks);
 
         HealthCheckResponse {
@@ -101,6 +133,21 @@ impl HealthChecker {
     }
 
     async fn check_rabbitmq(&self) -> ServiceHealth {
+        if let Some(client) = &self.rabbitmq_client {
+            let state = client.connection_state();
+
+            return match &state {
+                BrokerConnectionState::Connected { .. } => {
+                    ServiceHealth::healthy(0).with_connection(state)
+                }
+                BrokerConnectionState::Reconnecting { attempt, .. } => {
+                    warn!(attempt, "RabbitMQ connection is being recovered");
+                    ServiceHealth::unhealthy(format!("Reconnecting, attempt {}", attempt))
+                        .with_connection(state)
+                }
+            };
+        }
+
         let start = Instant::now();
 
         match RabbitMqClient::connect(&self.config).await {
@@ -135,6 +182,7 @@ impl HealthChecker {
                         response_time_ms: None,
                         circuit_breaker: Some(state_str),
                         error: Some("Circuit breaker in recovery mode".to_string()),
+                        connection: None,
                     },
                     CircuitState::Open => ServiceHealth::degraded_circuit_open(state_str),
                 }
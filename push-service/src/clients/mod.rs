@@ -1,7 +1,13 @@
+pub mod apns;
 pub mod circuit_breaker;
 pub mod database;
+pub mod events;
 pub mod fcm;
+pub mod fcm_auth;
 pub mod health;
+pub mod provider;
 pub mod rbmq;
 pub mod redis;
 pub mod template;
+pub mod user;
+pub mod webpush;
@@ -0,0 +1,70 @@
+use std::{collections::HashMap, sync::Arc};
+
+use anyhow::{Error, Result, anyhow};
+use async_trait::async_trait;
+use tracing::info;
+
+use crate::models::provider::{DeliveryErrorKind, PushNotification, PushTarget, SendReceipt};
+
+#[async_trait]
+pub trait PushProvider: Send + Sync {
+    fn name(&self) -> &str;
+
+    async fn send(&self, notification: &PushNotification) -> Result<SendReceipt, Error>;
+
+    fn classify_error(&self, error: &Error) -> DeliveryErrorKind;
+
+    fn error_code(&self, _error: &Error) -> Option<String> {
+        None
+    }
+
+    /// Rejects targets this provider cannot address or that are malformed
+    /// for it, before anything is sent.
+    fn validate_target(&self, target: &PushTarget) -> Result<(), Error>;
+}
+
+#[derive(Clone, Default)]
+pub struct ProviderRegistry {
+    providers: HashMap<String, Arc<dyn PushProvider>>,
+    default_provider: Option<String>,
+}
+
+impl ProviderRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register<P>(mut self, provider: P) -> Self
+    where
+        P: PushProvider + 'static,
+    {
+        let name = provider.name().to_string();
+
+        info!(provider = %name, "Push provider registered");
+
+        if self.default_provider.is_none() {
+            self.default_provider = Some(name.clone());
+        }
+
+        self.providers.insert(name, Arc::new(provider));
+        self
+    }
+
+    pub fn with_default(mut self, name: &str) -> Self {
+        self.default_provider = Some(name.to_string());
+        self
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<dyn PushProvider>> {
+        self.providers.get(name).cloned()
+    }
+
+    pub fn resolve(&self, name: Option<&str>) -> Result<Arc<dyn PushProvider>, Error> {
+        let name = name
+            .or(self.default_provider.as_deref())
+            .ok_or_else(|| anyhow!("No push provider registered"))?;
+
+        self.get(name)
+            .ok_or_else(|| anyhow!("Unknown push provider: {}", name))
+    }
+}
@@ -1,87 +1,147 @@
+use std::{
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
 use anyhow::{Error, Result, anyhow};
+use async_trait::async_trait;
+use chrono::{SecondsFormat, Utc};
+use futures_util::StreamExt;
 use lapin::{
-    BasicProperties, Channel, Connection, ConnectionProperties, Consumer,
+    BasicProperties, Channel, Connection, ConnectionProperties, Consumer, ExchangeKind,
+    acker::Acker,
+    message::Delivery,
     options::{
-        BasicAckOptions, BasicConsumeOptions, BasicPublishOptions, BasicQosOptions,
-        BasicRejectOptions, QueueDeclareOptions,
+        BasicAckOptions, BasicCancelOptions, BasicConsumeOptions, BasicGetOptions,
+        BasicPublishOptions, BasicQosOptions, BasicRejectOptions, ConfirmSelectOptions,
+        ExchangeDeclareOptions, QueueBindOptions, QueueDeclareOptions,
     },
-    types::FieldTable,
+    publisher_confirm::Confirmation,
+    types::{AMQPValue, FieldTable},
 };
-use tracing::{debug, info};
+use tokio::time::sleep;
+use tracing::{debug, info, warn};
 
-use crate::{config::Config, models::message::DlqMessage};
+use crate::{
+    clients::events::{EventPublisher, publish_event},
+    config::Config,
+    models::{
+        event::{EventEnvelope, PushOutcomeEvent},
+        health::BrokerConnectionState,
+        message::{DlqMessage, NotificationMessage},
+    },
+};
 
+const CONSUMER_TAG: &str = "push_worker";
+
+/// Owns the broker connection. When the connection drops, `reconnect` opens
+/// a new one and redeclares the topology; callers always pick up the
+/// current channel, so publishes resume on their own once it is back.
 pub struct RabbitMqClient {
-    pub channel: Channel,
+    config: Config,
+    session: RwLock<Session>,
+    state: RwLock<BrokerConnectionState>,
     push_queue_name: String,
     failed_queue_name: String,
+    events_exchange: String,
+    dead_letter_exchange: String,
+    dead_letter_routing_key: String,
+    /// Retry tiers, shortest delay first
+    retry_queues: Vec<String>,
+}
+
+struct Session {
+    connection: Arc<Connection>,
+    channel: Channel,
 }
 
 impl RabbitMqClient {
     pub async fn connect(config: &Config) -> Result<Self, Error> {
-        info!("Connecting to RabbitMQ");
+        let session = open_session(config).await?;
 
-        let connection = Connection::connect(&config.rabbitmq_url, ConnectionProperties::default())
-            .await
-            .map_err(|_| anyhow!("Failed to connect to RabbitMQ"))?;
+        Ok(Self {
+            config: config.clone(),
+            session: RwLock::new(session),
+            state: RwLock::new(BrokerConnectionState::Connected { since: now() }),
+            push_queue_name: config.push_queue_name.clone(),
+            failed_queue_name: config.failed_queue_name.clone(),
+            events_exchange: config.push_events_exchange.clone(),
+            dead_letter_exchange: config.dead_letter_exchange.clone(),
+            dead_letter_routing_key: config.dead_letter_routing_key.clone(),
+            retry_queues: config
+                .retry_queue_delays_seconds
+                .iter()
+                .map(|&delay_seconds| retry_queue_name(&config.push_queue_name, delay_seconds))
+                .collect(),
+        })
+    }
 
-        info!("RabbitMQ connection established");
+    /// The channel of the current connection
+    pub fn channel(&self) -> Channel {
+        self.session.read().unwrap().channel.clone()
+    }
 
-        let channel = connection
-            .create_channel()
-            .await
-            .map_err(|_| anyhow!("RabbitMQ channel creation failed"))?;
+    pub fn connection_state(&self) -> BrokerConnectionState {
+        self.state.read().unwrap().clone()
+    }
 
-        info!("RabbitMQ channel created");
+    /// Replaces a lost connection, retrying with backoff until the broker
+    /// accepts us again. Deliveries taken from the old channel can no longer
+    /// be settled; the broker redelivers them on the new one.
+    pub async fn reconnect(&self) {
+        let since = now();
+        let mut delay_ms = self.config.rabbitmq_reconnect_initial_delay_ms;
+        let mut attempt = 0;
+        let mut last_error = None;
 
-        channel
-            .basic_qos(config.prefetch_count, BasicQosOptions::default())
-            .await
-            .map_err(|_| anyhow!("Failed to set up QoS"))?;
+        loop {
+            attempt += 1;
 
-        info!(prefetch_count = config.prefetch_count, "Prefetch count set");
+            *self.state.write().unwrap() = BrokerConnectionState::Reconnecting {
+                since: since.clone(),
+                attempt,
+                last_error: last_error.clone(),
+            };
 
-        channel
-            .queue_declare(
-                &config.push_queue_name,
-                QueueDeclareOptions {
-                    durable: true,
-                    ..Default::default()
-                },
-                FieldTable::default(),
-            )
-            .await
-            .map_err(|_| anyhow!("Failed to declare push queue"))?;
+            match open_session(&self.config).await {
+                Ok(session) => {
+                    let previous = std::mem::replace(&mut *self.session.write().unwrap(), session);
 
-        info!(queue = %config.push_queue_name, "Push queue declared");
+                    // The old connection is usually gone already; this only
+                    // cleans up after a channel-level failure
+                    if let Err(e) = previous.connection.close(200, "Reconnecting").await {
+                        debug!(error = %e, "Previous RabbitMQ connection already closed");
+                    }
 
-        channel
-            .queue_declare(
-                &config.failed_queue_name,
-                QueueDeclareOptions {
-                    durable: true,
-                    ..Default::default()
-                },
-                FieldTable::default(),
-            )
-            .await
-            .map_err(|_| anyhow!("Failed to declare failed queue"))?;
+                    *self.state.write().unwrap() =
+                        BrokerConnectionState::Connected { since: now() };
 
-        info!(queue = %config.failed_queue_name, "Failed queue declared");
+                    info!(attempt, "RabbitMQ connection recovered");
 
-        Ok(Self {
-            channel,
-            push_queue_name: config.push_queue_name.clone(),
-            failed_queue_name: config.failed_queue_name.clone(),
-        })
+                    return;
+                }
+                Err(e) => {
+                    warn!(attempt, delay_ms, error = %e, "RabbitMQ reconnect failed");
+                    last_error = Some(e.to_string());
+                }
+            }
+
+            sleep(Duration::from_millis(delay_ms)).await;
+
+            delay_ms = delay_ms
+                .saturating_mul(2)
+                .min(self.config.rabbitmq_reconnect_max_delay_ms);
+        }
     }
 
+    /// Starts consuming the push queue on the current channel. The stream
+    /// ends when that channel dies.
     pub async fn create_consumer(&self) -> Result<Consumer, Error> {
         let consumer = self
-            .channel
+            .channel()
             .basic_consume(
                 &self.push_queue_name,
-                "push_worker",
+                CONSUMER_TAG,
                 BasicConsumeOptions::default(),
                 FieldTable::default(),
             )
@@ -93,43 +153,127 @@ impl RabbitMqClient {
         Ok(consumer)
     }
 
-    pub async fn acknowledge(&self, delivery_tag: u64) -> Result<(), Error> {
-        self.channel
-            .basic_ack(delivery_tag, BasicAckOptions::default())
+    /// Asks the broker to stop sending deliveries to the push consumer.
+    /// Messages it already handed over stay unacked and can still be
+    /// settled on the same channel.
+    pub async fn cancel_consumer(&self) -> Result<(), Error> {
+        self.channel()
+            .basic_cancel(CONSUMER_TAG, BasicCancelOptions::default())
+            .await
+            .map_err(|e| anyhow!("Failed to cancel consumer: {}", e))?;
+
+        info!(queue = %self.push_queue_name, "Consumer cancelled");
+
+        Ok(())
+    }
+
+    /// Stops the consumer without letting go of the connection. Deliveries
+    /// the broker had already pushed to it are requeued untouched, so they
+    /// wait on the queue instead of being processed while paused.
+    pub async fn pause_consumer(&self, consumer: &mut Consumer) -> Result<usize, Error> {
+        self.cancel_consumer().await?;
+
+        let mut requeued = 0;
+
+        // Once the cancel is confirmed the stream yields what it had
+        // buffered and then ends
+        while let Some(delivery) = consumer.next().await {
+            if let Ok(delivery) = delivery {
+                self.reject(&delivery.acker, true).await?;
+                requeued += 1;
+            }
+        }
+
+        Ok(requeued)
+    }
+
+    /// Closes the current connection. Anything still unacked on it goes back
+    /// to the queue.
+    pub async fn close(&self) -> Result<(), Error> {
+        let connection = Arc::clone(&self.session.read().unwrap().connection);
+
+        connection
+            .close(200, "Shutting down")
+            .await
+            .map_err(|e| anyhow!("Failed to close RabbitMQ connection: {}", e))?;
+
+        info!("RabbitMQ connection closed");
+
+        Ok(())
+    }
+
+    /// Settles a delivery on the channel it came from. Delivery tags only
+    /// mean something on their own channel, so one that died in the meantime
+    /// is left to the broker, which redelivers the message.
+    pub async fn acknowledge(&self, acker: &Acker) -> Result<(), Error> {
+        let acked = acker
+            .ack(BasicAckOptions::default())
             .await
             .map_err(|_| anyhow!("Failed to acknowledge message"))?;
 
-        debug!(delivery_tag, "Message acknowledged");
+        if !acked {
+            warn!("Channel closed before the message was acknowledged, it will be redelivered");
+        }
+
+        debug!("Message acknowledged");
 
         Ok(())
     }
 
-    pub async fn reject(&self, delivery_tag: u64, requeue: bool) -> Result<(), Error> {
-        self.channel
-            .basic_reject(delivery_tag, BasicRejectOptions { requeue })
+    pub async fn reject(&self, acker: &Acker, requeue: bool) -> Result<(), Error> {
+        let rejected = acker
+            .reject(BasicRejectOptions { requeue })
             .await
             .map_err(|_| anyhow!("Failed to reject message"))?;
 
-        debug!(delivery_tag, requeue, "Message rejected");
+        if !rejected {
+            warn!("Channel closed before the message was rejected, it will be redelivered");
+        }
+
+        debug!(requeue, "Message rejected");
 
         Ok(())
     }
 
+    /// Puts a payload back on the push queue, e.g. once its scheduled time
+    /// has come.
+    pub async fn publish_to_push_queue(&self, payload: &[u8]) -> Result<(), Error> {
+        publish_confirmed(
+            &self.channel(),
+            "",
+            &self.push_queue_name,
+            true,
+            payload,
+            BasicProperties::default()
+                .with_delivery_mode(2)
+                .with_content_type("application/json".into()),
+        )
+        .await
+        .map_err(|e| anyhow!("Failed to publish message to push queue: {}", e))?;
+
+        debug!(queue = %self.push_queue_name, "Message published to push queue");
+
+        Ok(())
+    }
+
+    /// Returns once the broker has queued the copy, so the caller can drop
+    /// the original delivery.
     pub async fn publish_to_dlq(&self, message: &DlqMessage) -> Result<(), Error> {
         let payload = serde_json::to_vec(message)?;
 
-        self.channel
-            .basic_publish(
-                "",
-                &self.failed_queue_name,
-                BasicPublishOptions::default(),
-                &payload,
-                BasicProperties::default().with_delivery_mode(2),
-            )
-            .await
-            .map_err(|_| anyhow!("Failed to publish message to dlq"))?;
+        publish_confirmed(
+            &self.channel(),
+            &self.dead_letter_exchange,
+            &self.dead_letter_routing_key,
+            true,
+            &payload,
+            BasicProperties::default().with_delivery_mode(2),
+        )
+        .await
+        .map_err(|e| anyhow!("Failed to publish message to dlq: {}", e))?;
 
         debug!(
+            queue = %self.failed_queue_name,
             idempotency_key = %message.original_message.idempotency_key,
             reason = %message.failure_reason,
             "Message published to DLQ"
@@ -137,4 +281,442 @@ impl RabbitMqClient {
 
         Ok(())
     }
+
+    /// Which delivery of the message this is, starting at 1, judging by the
+    /// retry tiers it has already passed through
+    pub fn delivery_attempt(&self, headers: Option<&FieldTable>) -> u32 {
+        retry_attempts(headers, &self.retry_queues) as u32 + 1
+    }
+
+    /// Announces a terminal outcome on the events exchange as
+    /// `notification.push.delivered`, `notification.push.expired` or
+    /// `notification.push.failed`.
+    pub async fn publish_outcome(&self, event: &PushOutcomeEvent) -> Result<(), Error> {
+        publish_event(self, event.pattern(), event).await?;
+
+        debug!(
+            request_id = %event.request_id,
+            status = %event.status,
+            attempt = event.attempt,
+            "Delivery outcome published"
+        );
+
+        Ok(())
+    }
+
+    /// Opens a private channel for walking the failed queue. Messages taken
+    /// from it stay unacknowledged until they are removed or replayed; the
+    /// rest go back to the queue when the browser is finished.
+    pub async fn dlq_browser(&self) -> Result<DlqBrowser, Error> {
+        let connection = Arc::clone(&self.session.read().unwrap().connection);
+
+        let channel = connection
+            .create_channel()
+            .await
+            .map_err(|_| anyhow!("RabbitMQ channel creation failed"))?;
+
+        channel
+            .confirm_select(ConfirmSelectOptions::default())
+            .await
+            .map_err(|_| anyhow!("Failed to enable publisher confirms"))?;
+
+        let queue = channel
+            .queue_declare(
+                &self.failed_queue_name,
+                QueueDeclareOptions {
+                    passive: true,
+                    ..Default::default()
+                },
+                FieldTable::default(),
+            )
+            .await
+            .map_err(|_| anyhow!("Failed to inspect failed queue"))?;
+
+        Ok(DlqBrowser {
+            channel,
+            push_queue_name: self.push_queue_name.clone(),
+            failed_queue_name: self.failed_queue_name.clone(),
+            total: queue.message_count(),
+            fetched: 0,
+        })
+    }
+
+    /// Parks a failed delivery in the next retry tier; the broker moves it
+    /// back to the push queue once the tier's delay has passed. Returns
+    /// `false` when every tier has been used and the message belongs in the
+    /// DLQ instead.
+    pub async fn publish_for_retry(
+        &self,
+        payload: &[u8],
+        headers: Option<&FieldTable>,
+    ) -> Result<bool, Error> {
+        let attempts = retry_attempts(headers, &self.retry_queues);
+
+        let Some(queue) = self.retry_queues.get(attempts) else {
+            return Ok(false);
+        };
+
+        // Carry the broker's x-death history over so the next failure
+        // knows which tiers were already used
+        let mut properties = BasicProperties::default()
+            .with_delivery_mode(2)
+            .with_content_type("application/json".into());
+
+        if let Some(headers) = headers {
+            properties = properties.with_headers(headers.clone());
+        }
+
+        publish_confirmed(&self.channel(), "", queue, true, payload, properties)
+            .await
+            .map_err(|e| anyhow!("Failed to publish message to retry queue: {}", e))?;
+
+        info!(queue = %queue, attempt = attempts + 1, "Message scheduled for retry");
+
+        Ok(true)
+    }
+}
+
+/// A single pass over the failed queue. Only the messages that were queued
+/// when the browser was opened are visited, so a busy DLQ cannot keep it
+/// going forever.
+pub struct DlqBrowser {
+    channel: Channel,
+    push_queue_name: String,
+    failed_queue_name: String,
+    total: u32,
+    fetched: u32,
+}
+
+impl DlqBrowser {
+    /// Messages in the failed queue when the browser was opened
+    pub fn total(&self) -> u32 {
+        self.total
+    }
+
+    pub async fn next(&mut self) -> Result<Option<Delivery>, Error> {
+        if self.fetched >= self.total {
+            return Ok(None);
+        }
+
+        let message = self
+            .channel
+            .basic_get(&self.failed_queue_name, BasicGetOptions { no_ack: false })
+            .await
+            .map_err(|_| anyhow!("Failed to read from failed queue"))?;
+
+        self.fetched += 1;
+
+        Ok(message.map(|message| message.delivery))
+    }
+
+    /// Drops the message from the failed queue for good
+    pub async fn remove(&self, delivery: &Delivery) -> Result<(), Error> {
+        self.channel
+            .basic_ack(delivery.delivery_tag, BasicAckOptions::default())
+            .await
+            .map_err(|_| anyhow!("Failed to remove message from failed queue"))?;
+
+        Ok(())
+    }
+
+    /// Republishes the original message in the envelope the gateway uses and
+    /// removes it from the failed queue
+    pub async fn replay(
+        &self,
+        delivery: &Delivery,
+        message: &NotificationMessage,
+    ) -> Result<(), Error> {
+        let payload = serde_json::to_vec(&EventEnvelope {
+            pattern: &self.push_queue_name,
+            data: message,
+        })?;
+
+        publish_confirmed(
+            &self.channel,
+            "",
+            &self.push_queue_name,
+            true,
+            &payload,
+            BasicProperties::default()
+                .with_delivery_mode(2)
+                .with_content_type("application/json".into()),
+        )
+        .await
+        .map_err(|e| anyhow!("Failed to publish message to push queue: {}", e))?;
+
+        self.remove(delivery).await?;
+
+        debug!(
+            idempotency_key = %message.idempotency_key,
+            "Message replayed from DLQ"
+        );
+
+        Ok(())
+    }
+
+    /// Closes the channel, which puts every message that was neither removed
+    /// nor replayed back on the failed queue
+    pub async fn finish(self) -> Result<(), Error> {
+        self.channel
+            .close(200, "DLQ browse finished")
+            .await
+            .map_err(|_| anyhow!("Failed to close DLQ browser channel"))?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl EventPublisher for RabbitMqClient {
+    async fn publish(&self, routing_key: &str, payload: &[u8]) -> Result<(), Error> {
+        publish_confirmed(
+            &self.channel(),
+            &self.events_exchange,
+            routing_key,
+            false,
+            payload,
+            BasicProperties::default()
+                .with_delivery_mode(2)
+                .with_content_type("application/json".into()),
+        )
+        .await
+        .map_err(|e| anyhow!("Failed to publish event: {}", e))?;
+
+        debug!(exchange = %self.events_exchange, routing_key, "Event published");
+
+        Ok(())
+    }
+}
+
+/// Connects and declares every queue and exchange the service relies on.
+async fn open_session(config: &Config) -> Result<Session, Error> {
+    info!("Connecting to RabbitMQ");
+
+    let connection = Connection::connect(&config.rabbitmq_url, ConnectionProperties::default())
+        .await
+        .map_err(|_| anyhow!("Failed to connect to RabbitMQ"))?;
+
+    info!("RabbitMQ connection established");
+
+    let channel = connection
+        .create_channel()
+        .await
+        .map_err(|_| anyhow!("RabbitMQ channel creation failed"))?;
+
+    info!("RabbitMQ channel created");
+
+    channel
+        .basic_qos(config.prefetch_count, BasicQosOptions::default())
+        .await
+        .map_err(|_| anyhow!("Failed to set up QoS"))?;
+
+    info!(prefetch_count = config.prefetch_count, "Prefetch count set");
+
+    channel
+        .confirm_select(ConfirmSelectOptions::default())
+        .await
+        .map_err(|_| anyhow!("Failed to enable publisher confirms"))?;
+
+    channel
+        .queue_declare(
+            &config.push_queue_name,
+            QueueDeclareOptions {
+                durable: true,
+                ..Default::default()
+            },
+            FieldTable::default(),
+        )
+        .await
+        .map_err(|_| anyhow!("Failed to declare push queue"))?;
+
+    info!(queue = %config.push_queue_name, "Push queue declared");
+
+    channel
+        .queue_declare(
+            &config.failed_queue_name,
+            QueueDeclareOptions {
+                durable: true,
+                ..Default::default()
+            },
+            FieldTable::default(),
+        )
+        .await
+        .map_err(|_| anyhow!("Failed to declare failed queue"))?;
+
+    info!(queue = %config.failed_queue_name, "Failed queue declared");
+
+    channel
+        .exchange_declare(
+            &config.dead_letter_exchange,
+            ExchangeKind::Direct,
+            ExchangeDeclareOptions {
+                durable: true,
+                ..Default::default()
+            },
+            FieldTable::default(),
+        )
+        .await
+        .map_err(|_| anyhow!("Failed to declare dead letter exchange"))?;
+
+    // Final failures land in the failed queue; expired retries go back
+    // to the push queue.
+    for (queue, routing_key) in [
+        (&config.failed_queue_name, &config.dead_letter_routing_key),
+        (&config.push_queue_name, &config.push_queue_name),
+    ] {
+        channel
+            .queue_bind(
+                queue,
+                &config.dead_letter_exchange,
+                routing_key,
+                QueueBindOptions::default(),
+                FieldTable::default(),
+            )
+            .await
+            .map_err(|_| anyhow!("Failed to bind {} to dead letter exchange", queue))?;
+    }
+
+    info!(exchange = %config.dead_letter_exchange, "Dead letter exchange declared");
+
+    for &delay_seconds in &config.retry_queue_delays_seconds {
+        let name = retry_queue_name(&config.push_queue_name, delay_seconds);
+
+        let mut arguments = FieldTable::default();
+        arguments.insert(
+            "x-message-ttl".into(),
+            AMQPValue::LongLongInt(delay_seconds.saturating_mul(1_000) as i64),
+        );
+        arguments.insert(
+            "x-dead-letter-exchange".into(),
+            AMQPValue::LongString(config.dead_letter_exchange.as_str().into()),
+        );
+        arguments.insert(
+            "x-dead-letter-routing-key".into(),
+            AMQPValue::LongString(config.push_queue_name.as_str().into()),
+        );
+
+        channel
+            .queue_declare(
+                &name,
+                QueueDeclareOptions {
+                    durable: true,
+                    ..Default::default()
+                },
+                arguments,
+            )
+            .await
+            .map_err(|_| anyhow!("Failed to declare retry queue {}", name))?;
+
+        info!(queue = %name, delay_seconds, "Retry queue declared");
+    }
+
+    channel
+        .exchange_declare(
+            &config.push_events_exchange,
+            ExchangeKind::Topic,
+            ExchangeDeclareOptions {
+                durable: true,
+                ..Default::default()
+            },
+            FieldTable::default(),
+        )
+        .await
+        .map_err(|_| anyhow!("Failed to declare events exchange"))?;
+
+    info!(exchange = %config.push_events_exchange, "Events exchange declared");
+
+    Ok(Session {
+        connection: Arc::new(connection),
+        channel,
+    })
+}
+
+/// Publishes and waits for the broker's confirm. A nack is an error, and so
+/// is a `mandatory` message returned as unroutable, so callers only settle
+/// the original delivery once the copy is safely queued. Events go out
+/// without `mandatory`: having no subscriber is not a failure.
+async fn publish_confirmed(
+    channel: &Channel,
+    exchange: &str,
+    routing_key: &str,
+    mandatory: bool,
+    payload: &[u8],
+    properties: BasicProperties,
+) -> Result<(), Error> {
+    let confirmation = channel
+        .basic_publish(
+            exchange,
+            routing_key,
+            BasicPublishOptions {
+                mandatory,
+                ..Default::default()
+            },
+            payload,
+            properties,
+        )
+        .await?
+        .await?;
+
+    match confirmation {
+        Confirmation::Ack(None) => Ok(()),
+        Confirmation::Ack(Some(returned)) | Confirmation::Nack(Some(returned)) => Err(anyhow!(
+            "returned as unroutable ({} {})",
+            returned.reply_code,
+            returned.reply_text
+        )),
+        Confirmation::Nack(None) => Err(anyhow!("rejected by the broker")),
+        Confirmation::NotRequested => Err(anyhow!("publisher confirms are not enabled")),
+    }
+}
+
+/// Names a retry tier after its delay, e.g. `push_notifications.retry.5m`.
+pub fn retry_queue_name(push_queue_name: &str, delay_seconds: u64) -> String {
+    let delay = match delay_seconds {
+        0 => "0s".to_string(),
+        seconds if seconds.is_multiple_of(3_600) => format!("{}h", seconds / 3_600),
+        seconds if seconds.is_multiple_of(60) => format!("{}m", seconds / 60),
+        seconds => format!("{}s", seconds),
+    };
+
+    format!("{}.retry.{}", push_queue_name, delay)
+}
+
+/// How many times a message already expired out of one of `retry_queues`,
+/// according to the `x-death` entries the broker adds on dead-lettering.
+pub fn retry_attempts(headers: Option<&FieldTable>, retry_queues: &[String]) -> usize {
+    let Some(deaths) = headers
+        .and_then(|headers| headers.inner().get("x-death"))
+        .and_then(AMQPValue::as_array)
+    else {
+        return 0;
+    };
+
+    deaths
+        .as_slice()
+        .iter()
+        .filter_map(AMQPValue::as_field_table)
+        .filter(|death| {
+            death
+                .inner()
+                .get("queue")
+                .and_then(AMQPValue::as_long_string)
+                .is_some_and(|queue| {
+                    retry_queues
+                        .iter()
+                        .any(|name| name.as_bytes() == queue.as_bytes())
+                })
+        })
+        .map(|death| {
+            let count = death.inner().get("count");
+            count
+                .and_then(AMQPValue::as_long_long_int)
+                .or_else(|| count.and_then(AMQPValue::as_long_int).map(i64::from))
+                .unwrap_or(1)
+                .max(0) as usize
+        })
+        .sum()
+}
+
+fn now() -> String {
+    Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true)
 }
@@ -1,5 +1,5 @@
 use anyhow::{Error, Result, anyhow};
-use redis::{AsyncCommands, Client, aio::MultiplexedConnection};
+use redis::{AsyncCommands, Client, Script, aio::MultiplexedConnection};
 use tracing::{debug, info, warn};
 
 use crate::{
@@ -8,6 +8,7 @@ use crate::{
     utils::retry_with_backoff,
 };
 
+#[derive(Clone)]
 pub struct RedisClient {
     connection: MultiplexedConnection,
     idempotency_ttl_seconds: u64,
@@ -36,13 +37,14 @@ impl RedisClient {
     }
 
     pub async fn check_idempotency(
-        &mut self,
+        &self,
         idempotency_key: &str,
     ) -> Result<IdempotencyStatus, Error> {
         let key = format!("idempotency:{}", idempotency_key);
 
         let value: Option<String> = self
             .connection
+            .clone()
             .get(&key)
             .await
             .map_err(|_| anyhow!("Failed to get cached value"))?;
@@ -51,6 +53,8 @@ impl RedisClient {
             None => IdempotencyStatus::NotFound,
             Some("processing") => IdempotencyStatus::Processing,
             Some("sent") => IdempotencyStatus::Sent,
+            Some("suppressed") => IdempotencyStatus::Suppressed,
+            Some("expired") => IdempotencyStatus::Expired,
             Some("failed") => IdempotencyStatus::Failed,
             Some(other) => {
                 warn!(
@@ -67,10 +71,11 @@ impl RedisClient {
         Ok(status)
     }
 
-    pub async fn mark_as_processing(&mut self, idempotency_key: &str) -> Result<(), Error> {
+    pub async fn mark_as_processing(&self, idempotency_key: &str) -> Result<(), Error> {
         let key = format!("idempotency:{}", idempotency_key);
 
         self.connection
+            .clone()
             .set_ex::<_, _, ()>(&key, "processing", self.idempotency_ttl_seconds)
             .await
             .map_err(|e| anyhow!("Failed to mark value as processing: {}", e))?;
@@ -80,7 +85,7 @@ impl RedisClient {
         Ok(())
     }
 
-    pub async fn mark_as_sent(&mut self, idempotency_key: &str) -> Result<(), Error> {
+    pub async fn mark_as_sent(&self, idempotency_key: &str) -> Result<(), Error> {
         let key = format!("idempotency:{}", idempotency_key);
 
         retry_with_backoff(&self.retry_config, || {
@@ -102,10 +107,39 @@ impl RedisClient {
         Ok(())
     }
 
-    pub async fn mark_as_failed(&mut self, idempotency_key: &str) -> Result<(), Error> {
+    pub async fn mark_as_suppressed(&self, idempotency_key: &str) -> Result<(), Error> {
         let key = format!("idempotency:{}", idempotency_key);
 
         self.connection
+            .clone()
+            .set_ex::<_, _, ()>(&key, "suppressed", self.idempotency_ttl_seconds)
+            .await
+            .map_err(|e| anyhow!("Failed to mark value as suppressed: {}", e))?;
+
+        debug!(idempotency_key, "Marked as suppressed");
+
+        Ok(())
+    }
+
+    pub async fn mark_as_expired(&self, idempotency_key: &str) -> Result<(), Error> {
+        let key = format!("idempotency:{}", idempotency_key);
+
+        self.connection
+            .clone()
+            .set_ex::<_, _, ()>(&key, "expired", self.idempotency_ttl_seconds)
+            .await
+            .map_err(|e| anyhow!("Failed to mark value as expired: {}", e))?;
+
+        debug!(idempotency_key, "Marked as expired");
+
+        Ok(())
+    }
+
+    pub async fn mark_as_failed(&self, idempotency_key: &str) -> Result<(), Error> {
+        let key = format!("idempotency:{}", idempotency_key);
+
+        self.connection
+            .clone()
             .set_ex::<_, _, ()>(&key, "failed", self.idempotency_ttl_seconds)
             .await
             .map_err(|_| anyhow!("Failed to mark value as failed"))?;
@@ -114,4 +148,106 @@ impl RedisClient {
 
         Ok(())
     }
+
+    /// Forgets the key so a later redelivery of the message is processed
+    /// from scratch.
+    pub async fn release(&self, idempotency_key: &str) -> Result<(), Error> {
+        let key = format!("idempotency:{}", idempotency_key);
+
+        self.connection
+            .clone()
+            .del::<_, ()>(&key)
+            .await
+            .map_err(|e| anyhow!("Failed to release idempotency key: {}", e))?;
+
+        debug!(idempotency_key, "Released idempotency key");
+
+        Ok(())
+    }
+
+    /// Claims one device of a message, recording it alongside the message
+    /// so an interrupted attempt knows which device keys it left behind.
+    pub async fn mark_device_as_processing(
+        &self,
+        idempotency_key: &str,
+        device_id: &str,
+    ) -> Result<(), Error> {
+        let devices_key = format!("idempotency:{}:devices", idempotency_key);
+        let key = format!(
+            "idempotency:{}",
+            device_idempotency_key(idempotency_key, device_id)
+        );
+
+        // Tracked before the claim, so no processing key goes unrecorded
+        redis::pipe()
+            .sadd(&devices_key, device_id)
+            .ignore()
+            .expire(&devices_key, self.idempotency_ttl_seconds as i64)
+            .ignore()
+            .set_ex(&key, "processing", self.idempotency_ttl_seconds)
+            .ignore()
+            .query_async::<()>(&mut self.connection.clone())
+            .await
+            .map_err(|e| anyhow!("Failed to mark device as processing: {}", e))?;
+
+        debug!(idempotency_key, device_id, "Marked device as processing");
+
+        Ok(())
+    }
+
+    /// Releases the message key and the device keys it claimed that are
+    /// still marked as processing, so a message interrupted mid-send is
+    /// picked up again on redelivery. Devices that were already settled keep
+    /// their status and are not sent twice.
+    pub async fn release_unfinished(&self, idempotency_key: &str) -> Result<usize, Error> {
+        let mut conn = self.connection.clone();
+
+        let device_ids: Vec<String> = conn
+            .smembers(format!("idempotency:{}:devices", idempotency_key))
+            .await
+            .map_err(|e| anyhow!("Failed to read claimed devices: {}", e))?;
+
+        let script = Script::new(RELEASE_UNFINISHED_SCRIPT);
+        let mut release = script.prepare_invoke();
+        release
+            .arg("processing")
+            .key(format!("idempotency:{}", idempotency_key));
+
+        for device_id in &device_ids {
+            release.key(format!(
+                "idempotency:{}",
+                device_idempotency_key(idempotency_key, device_id)
+            ));
+        }
+
+        let released: usize = release
+            .invoke_async(&mut conn)
+            .await
+            .map_err(|e| anyhow!("Failed to release idempotency keys: {}", e))?;
+
+        debug!(
+            idempotency_key,
+            released, "Released unfinished idempotency keys"
+        );
+
+        Ok(released)
+    }
+}
+
+/// Deletes each key that still holds `ARGV[1]`, in one step so a key that
+/// settles meanwhile is never removed
+const RELEASE_UNFINISHED_SCRIPT: &str = r"
+local released = 0
+for _, key in ipairs(KEYS) do
+    if redis.call('GET', key) == ARGV[1] then
+        redis.call('DEL', key)
+        released = released + 1
+    end
+end
+return released
+";
+
+/// The key one device of a message is deduplicated under
+pub fn device_idempotency_key(idempotency_key: &str, device_id: &str) -> String {
+    format!("{}:{}", idempotency_key, device_id)
 }
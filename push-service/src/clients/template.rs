@@ -14,6 +14,7 @@ use crate::{
     utils::retry_with_backoff,
 };
 
+#[derive(Clone)]
 pub struct TemplateServiceClient {
     http_client: Client,
     base_url: String,
@@ -39,7 +40,7 @@ impl TemplateServiceClient {
     }
 
     pub async fn fetch_template(
-        &mut self,
+        &self,
         template_code: &str,
         language: Option<&str>,
     ) -> Result<Template, Error> {
@@ -112,7 +113,25 @@ impl TemplateServiceClient {
         let title = Self::replace_variables(&template.content.title, variables)?;
         let body = Self::replace_variables(&template.content.body, variables)?;
 
-        Ok(TemplateContent { title, body })
+        let image = template
+            .content
+            .image
+            .as_deref()
+            .map(|image| Self::replace_variables(image, variables))
+            .transpose()?;
+        let link = template
+            .content
+            .link
+            .as_deref()
+            .map(|link| Self::replace_variables(link, variables))
+            .transpose()?;
+
+        Ok(TemplateContent {
+            title,
+            body,
+            image,
+            link,
+        })
     }
 
     fn replace_variables(
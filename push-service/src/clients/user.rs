@@ -0,0 +1,201 @@
+use std::{fmt, time::Duration};
+
+use anyhow::{Error, Result, anyhow};
+use redis::{AsyncCommands, aio::MultiplexedConnection};
+use reqwest::{Client, StatusCode};
+use tracing::{debug, info, warn};
+
+use crate::{
+    clients::circuit_breaker::CircuitBreaker,
+    config::Config,
+    models::{
+        retry::RetryConfig,
+        user::{UserProfile, UserServiceResponse},
+    },
+    utils::retry_with_backoff_if,
+};
+
+#[derive(Clone)]
+pub struct UserServiceClient {
+    http_client: Client,
+    base_url: String,
+    auth_token: Option<String>,
+    retry_config: RetryConfig,
+    circuit_breaker: CircuitBreaker,
+    cache: MultiplexedConnection,
+    cache_ttl_seconds: u64,
+}
+
+/// A failed lookup, split by whether trying again could help.
+#[derive(Debug)]
+struct LookupError {
+    retryable: bool,
+    message: String,
+}
+
+impl fmt::Display for LookupError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for LookupError {}
+
+impl UserServiceClient {
+    pub fn new(
+        config: &Config,
+        circuit_breaker: CircuitBreaker,
+        cache: MultiplexedConnection,
+    ) -> Result<Self, Error> {
+        let http_client = Client::builder()
+            .timeout(Duration::from_secs(5))
+            .build()
+            .map_err(|_| anyhow!("Failed to create HTTP client"))?;
+
+        info!(base_url = %config.user_service_url, "User service client initialized");
+
+        Ok(Self {
+            http_client,
+            base_url: config.user_service_url.trim_end_matches('/').to_string(),
+            auth_token: config.user_service_token.clone(),
+            retry_config: config.retry_config(),
+            circuit_breaker,
+            cache,
+            cache_ttl_seconds: config.user_cache_ttl_seconds,
+        })
+    }
+
+    /// Looks up the user's current push token and preferences. Returns
+    /// `None` when user-service does not know the user; any error means the
+    /// service could not be asked.
+    pub async fn fetch_user(&self, user_id: &str) -> Result<Option<UserProfile>, Error> {
+        let cache_key = Self::cache_key(user_id);
+
+        if let Some(profile) = self.cached(&cache_key).await {
+            debug!(user_id, "User profile served from cache");
+            return Ok(Some(profile));
+        }
+
+        let url = format!("{}/api/v1/users/{}", self.base_url, user_id);
+
+        debug!(user_id, "Fetching user from service");
+
+        let profile = self
+            .circuit_breaker
+            .call_with_filter(
+                || self.fetch_with_retry(url),
+                // A request user-service rejected says nothing about its health
+                |e| e.downcast_ref::<LookupError>().is_none_or(|e| e.retryable),
+            )
+            .await?;
+
+        if let Some(profile) = &profile {
+            self.store(&cache_key, profile).await;
+        }
+
+        Ok(profile)
+    }
+
+    /// Drops the cached profile, e.g. after its push token was found dead.
+    pub async fn forget(&self, user_id: &str) {
+        if let Err(e) = self
+            .cache
+            .clone()
+            .del::<_, ()>(Self::cache_key(user_id))
+            .await
+        {
+            warn!(user_id, error = %e, "Failed to evict cached user profile");
+        }
+    }
+
+    async fn fetch_with_retry(&self, url: String) -> Result<Option<UserProfile>, Error> {
+        retry_with_backoff_if(
+            &self.retry_config,
+            || {
+                let url = url.clone();
+                let client = self.http_client.clone();
+                let auth_token = self.auth_token.clone();
+
+                async move {
+                    let mut request = client.get(&url);
+
+                    if let Some(token) = auth_token {
+                        request = request.bearer_auth(token);
+                    }
+
+                    let response = request.send().await.map_err(|e| LookupError {
+                        retryable: true,
+                        message: e.to_string(),
+                    })?;
+
+                    let status = response.status();
+
+                    if status == StatusCode::NOT_FOUND {
+                        return Ok(None);
+                    }
+
+                    if !status.is_success() {
+                        return Err(LookupError {
+                            retryable: status.is_server_error()
+                                || status == StatusCode::TOO_MANY_REQUESTS,
+                            message: format!("User Service returned status {}", status),
+                        });
+                    }
+
+                    let body: UserServiceResponse<UserProfile> =
+                        response.json().await.map_err(|e| LookupError {
+                            retryable: false,
+                            message: format!("Failed to parse user JSON: {}", e),
+                        })?;
+
+                    match body.data {
+                        Some(profile) if body.success => Ok(Some(profile)),
+                        _ => Err(LookupError {
+                            retryable: false,
+                            message: format!("User Service returned no user: {}", body.message),
+                        }),
+                    }
+                }
+            },
+            |e| e.retryable,
+        )
+        .await
+        .map_err(|e| {
+            Error::new(LookupError {
+                message: format!("Failed to fetch user: {}", e.message),
+                ..e
+            })
+        })
+    }
+
+    async fn cached(&self, key: &str) -> Option<UserProfile> {
+        let value: Option<String> = match self.cache.clone().get(key).await {
+            Ok(value) => value,
+            Err(e) => {
+                warn!(error = %e, "Failed to read cached user profile");
+                return None;
+            }
+        };
+
+        value.and_then(|value| serde_json::from_str(&value).ok())
+    }
+
+    async fn store(&self, key: &str, profile: &UserProfile) {
+        let Ok(value) = serde_json::to_string(profile) else {
+            return;
+        };
+
+        if let Err(e) = self
+            .cache
+            .clone()
+            .set_ex::<_, _, ()>(key, value, self.cache_ttl_seconds)
+            .await
+        {
+            warn!(error = %e, "Failed to cache user profile");
+        }
+    }
+
+    fn cache_key(user_id: &str) -> String {
+        format!("user_profile:{}", user_id)
+    }
+}
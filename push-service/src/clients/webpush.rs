@@ -0,0 +1,450 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+
+use aes_gcm::{
+    Aes128Gcm, Nonce,
+    aead::{Aead, KeyInit},
+};
+use anyhow::{Error, Result, anyhow};
+use async_trait::async_trait;
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use chrono::Utc;
+use hkdf::Hkdf;
+use jsonwebtoken::{Algorithm, EncodingKey, Header};
+use p256::{
+    PublicKey, SecretKey, ecdh::diffie_hellman, elliptic_curve::sec1::ToEncodedPoint,
+    pkcs8::EncodePrivateKey,
+};
+use reqwest::{Client, Url, header::RETRY_AFTER};
+use sha2::Sha256;
+use tracing::{debug, info};
+
+use crate::{
+    clients::{circuit_breaker::CircuitBreaker, provider::PushProvider},
+    config::Config,
+    models::{
+        provider::{DeliveryErrorKind, PushNotification, PushPriority, PushTarget, SendReceipt},
+        retry::{RetryConfig, RetryDecision},
+        webpush::{
+            VapidClaims, WebPushError, WebPushNotification, WebPushPayload, WebPushSubscription,
+        },
+    },
+    utils::{parse_retry_after, retry_with_backoff_policy},
+};
+
+const RECORD_SIZE: u32 = 4096;
+
+// Salt (16) + record size (4) + key id length (1) + public key (65) + padding delimiter (1)
+// + AES-GCM tag (16) must all fit alongside the payload in a single record.
+const MAX_PLAINTEXT_SIZE: usize = RECORD_SIZE as usize - 103;
+
+const MAX_VAPID_TOKEN_TTL_SECONDS: u64 = 86_400;
+
+/// Decodes a base64url value, tolerating the padded form some browsers emit.
+pub fn decode_base64url(value: &str) -> Result<Vec<u8>, Error> {
+    URL_SAFE_NO_PAD
+        .decode(value.trim_end_matches('='))
+        .map_err(|e| anyhow!("Invalid base64url value: {}", e))
+}
+
+/// Encrypts a payload for a subscription using a fresh ephemeral key and salt.
+pub fn encrypt_payload(
+    plaintext: &[u8],
+    subscription: &WebPushSubscription,
+) -> Result<Vec<u8>, Error> {
+    let ua_public = decode_base64url(&subscription.keys.p256dh)?;
+    let auth_secret = decode_base64url(&subscription.keys.auth)?;
+
+    let as_private = loop {
+        if let Ok(key) = SecretKey::from_slice(&rand::random::<[u8; 32]>()) {
+            break key;
+        }
+    };
+    let salt = rand::random::<[u8; 16]>();
+
+    encrypt_payload_with_keys(plaintext, &ua_public, &auth_secret, &as_private, &salt)
+}
+
+/// Encrypts a payload with the aes128gcm content coding described in RFC 8291.
+pub fn encrypt_payload_with_keys(
+    plaintext: &[u8],
+    ua_public: &[u8],
+    auth_secret: &[u8],
+    as_private: &SecretKey,
+    salt: &[u8; 16],
+) -> Result<Vec<u8>, Error> {
+    if plaintext.len() > MAX_PLAINTEXT_SIZE {
+        return Err(WebPushError::PayloadTooLarge.into());
+    }
+
+    let ua_public_key = PublicKey::from_sec1_bytes(ua_public)
+        .map_err(|_| anyhow!("Invalid p256dh key in subscription"))?;
+    let as_public = as_private.public_key().to_encoded_point(false);
+
+    let shared_secret = diffie_hellman(as_private.to_nonzero_scalar(), ua_public_key.as_affine());
+
+    let mut key_info = b"WebPush: info\0".to_vec();
+    key_info.extend_from_slice(ua_public);
+    key_info.extend_from_slice(as_public.as_bytes());
+
+    let mut ikm = [0u8; 32];
+    Hkdf::<Sha256>::new(Some(auth_secret), shared_secret.raw_secret_bytes())
+        .expand(&key_info, &mut ikm)
+        .map_err(|_| anyhow!("Failed to derive web push input key"))?;
+
+    let prk = Hkdf::<Sha256>::new(Some(salt), &ikm);
+
+    let mut cek = [0u8; 16];
+    prk.expand(b"Content-Encoding: aes128gcm\0", &mut cek)
+        .map_err(|_| anyhow!("Failed to derive web push content key"))?;
+
+    let mut nonce = [0u8; 12];
+    prk.expand(b"Content-Encoding: nonce\0", &mut nonce)
+        .map_err(|_| anyhow!("Failed to derive web push nonce"))?;
+
+    let mut record = plaintext.to_vec();
+    record.push(0x02);
+
+    let ciphertext = Aes128Gcm::new_from_slice(&cek)
+        .map_err(|_| anyhow!("Invalid web push content key"))?
+        .encrypt(Nonce::from_slice(&nonce), record.as_slice())
+        .map_err(|_| anyhow!("Failed to encrypt web push payload"))?;
+
+    let mut body = Vec::with_capacity(86 + ciphertext.len());
+    body.extend_from_slice(salt);
+    body.extend_from_slice(&RECORD_SIZE.to_be_bytes());
+    body.push(as_public.len() as u8);
+    body.extend_from_slice(as_public.as_bytes());
+    body.extend_from_slice(&ciphertext);
+
+    Ok(body)
+}
+
+struct CachedToken {
+    value: String,
+    issued_at: Instant,
+}
+
+pub struct VapidSigner {
+    encoding_key: EncodingKey,
+    public_key: String,
+    subject: String,
+    token_ttl: Duration,
+    cached: RwLock<HashMap<String, CachedToken>>,
+}
+
+impl VapidSigner {
+    pub fn new(private_key: &str, subject: String, token_ttl: Duration) -> Result<Self, Error> {
+        let secret_key = SecretKey::from_slice(&decode_base64url(private_key)?)
+            .map_err(|_| anyhow!("Invalid VAPID private key"))?;
+
+        let der = secret_key
+            .to_pkcs8_der()
+            .map_err(|e| anyhow!("Failed to encode VAPID private key: {}", e))?;
+
+        let public_key =
+            URL_SAFE_NO_PAD.encode(secret_key.public_key().to_encoded_point(false).as_bytes());
+
+        Ok(Self {
+            encoding_key: EncodingKey::from_ec_der(der.as_bytes()),
+            public_key,
+            subject,
+            token_ttl: token_ttl.min(Duration::from_secs(MAX_VAPID_TOKEN_TTL_SECONDS)),
+            cached: RwLock::new(HashMap::new()),
+        })
+    }
+
+    pub fn public_key(&self) -> &str {
+        &self.public_key
+    }
+
+    pub fn authorization(&self, endpoint: &str) -> Result<String, Error> {
+        let audience = audience_for(endpoint)?;
+        let token = self.token(&audience)?;
+
+        Ok(format!("vapid t={}, k={}", token, self.public_key))
+    }
+
+    pub fn token(&self, audience: &str) -> Result<String, Error> {
+        // Tokens are re-signed halfway through their lifetime so in-flight
+        // requests never carry a JWT that is about to expire.
+        let refresh_after = self.token_ttl / 2;
+
+        if let Some(cached) = self.cached.read().unwrap().get(audience)
+            && cached.issued_at.elapsed() < refresh_after
+        {
+            return Ok(cached.value.clone());
+        }
+
+        let mut cached = self.cached.write().unwrap();
+
+        if let Some(existing) = cached.get(audience)
+            && existing.issued_at.elapsed() < refresh_after
+        {
+            return Ok(existing.value.clone());
+        }
+
+        let value = self.sign(audience)?;
+        cached.insert(
+            audience.to_string(),
+            CachedToken {
+                value: value.clone(),
+                issued_at: Instant::now(),
+            },
+        );
+
+        debug!(audience = %audience, "VAPID token signed");
+
+        Ok(value)
+    }
+
+    fn sign(&self, audience: &str) -> Result<String, Error> {
+        let claims = VapidClaims {
+            aud: audience.to_string(),
+            exp: Utc::now().timestamp() + self.token_ttl.as_secs() as i64,
+            sub: self.subject.clone(),
+        };
+
+        jsonwebtoken::encode(&Header::new(Algorithm::ES256), &claims, &self.encoding_key)
+            .map_err(|e| anyhow!("Failed to sign VAPID token: {}", e))
+    }
+}
+
+fn audience_for(endpoint: &str) -> Result<String, Error> {
+    let url = Url::parse(endpoint).map_err(|e| anyhow!("Invalid web push endpoint: {}", e))?;
+
+    match url.scheme() {
+        "http" | "https" => Ok(url.origin().ascii_serialization()),
+        scheme => Err(anyhow!("Unsupported web push endpoint scheme: {}", scheme)),
+    }
+}
+
+#[derive(Clone)]
+pub struct WebPushClient {
+    http_client: Client,
+    vapid_signer: Arc<VapidSigner>,
+    ttl_seconds: u64,
+    retry_config: RetryConfig,
+    circuit_breaker: CircuitBreaker,
+}
+
+impl WebPushClient {
+    pub fn new(config: &Config, circuit_breaker: CircuitBreaker) -> Result<Self, Error> {
+        let private_key = config
+            .vapid_private_key
+            .as_ref()
+            .ok_or_else(|| anyhow!("VAPID_PRIVATE_KEY is required for Web Push"))?;
+        let subject = config
+            .vapid_subject
+            .clone()
+            .ok_or_else(|| anyhow!("VAPID_SUBJECT is required for Web Push"))?;
+
+        let vapid_signer = VapidSigner::new(
+            private_key,
+            subject,
+            Duration::from_secs(config.vapid_token_ttl_seconds),
+        )?;
+
+        let http_client = Client::builder()
+            .use_rustls_tls()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .map_err(|_| anyhow!("Failed to create Web Push HTTP client"))?;
+
+        info!(public_key = %vapid_signer.public_key(), "Web Push client initialized");
+
+        Ok(Self {
+            http_client,
+            vapid_signer: Arc::new(vapid_signer),
+            ttl_seconds: config.web_push_ttl_seconds,
+            retry_config: config.retry_config(),
+            circuit_breaker,
+        })
+    }
+
+    pub fn vapid_public_key(&self) -> &str {
+        self.vapid_signer.public_key()
+    }
+
+    async fn send_with_retry(
+        &self,
+        subscription: &WebPushSubscription,
+        notification: &PushNotification,
+        body: &[u8],
+    ) -> Result<SendReceipt, Error> {
+        retry_with_backoff_policy(
+            &self.retry_config,
+            || self.send_notification_once(subscription, notification, body),
+            |e| self.retry_decision(e),
+        )
+        .await
+    }
+
+    fn retry_decision(&self, error: &Error) -> RetryDecision {
+        if self.classify_error(error) != DeliveryErrorKind::Retryable {
+            return RetryDecision::Abort;
+        }
+
+        error
+            .downcast_ref::<WebPushError>()
+            .and_then(WebPushError::retry_after)
+            .map(RetryDecision::RetryAfter)
+            .unwrap_or(RetryDecision::Retry)
+    }
+
+    async fn send_notification_once(
+        &self,
+        subscription: &WebPushSubscription,
+        notification: &PushNotification,
+        body: &[u8],
+    ) -> Result<SendReceipt, Error> {
+        let authorization = self.vapid_signer.authorization(&subscription.endpoint)?;
+
+        let ttl = match notification.expires_at {
+            Some(expires_at) => (expires_at - Utc::now()).num_seconds().max(0) as u64,
+            None => self.ttl_seconds,
+        };
+
+        let urgency = match notification.priority {
+            PushPriority::High => "high",
+            PushPriority::Normal => "normal",
+        };
+
+        let mut request = self
+            .http_client
+            .post(&subscription.endpoint)
+            .header("Authorization", authorization)
+            .header("Content-Encoding", "aes128gcm")
+            .header("Content-Type", "application/octet-stream")
+            .header("TTL", ttl.to_string())
+            .header("Urgency", urgency)
+            .body(body.to_vec());
+
+        if let Some(topic) = &notification.collapse_key {
+            request = request.header("Topic", topic);
+        }
+
+        let response = request.send().await?;
+        let status = response.status();
+
+        if status.is_success() {
+            let location = response
+                .headers()
+                .get("location")
+                .and_then(|v| v.to_str().ok())
+                .map(|v| v.to_string());
+
+            info!(location = ?location, "Web push notification sent successfully");
+
+            return Ok(SendReceipt {
+                message_id: location,
+            });
+        }
+
+        // Throttling is per push service, so unlike FCM the delay only
+        // applies to this request rather than every worker
+        let retry_after = response
+            .headers()
+            .get(RETRY_AFTER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(parse_retry_after);
+
+        let body = response.text().await.unwrap_or_default();
+
+        Err(WebPushError::from_response(status.as_u16(), &body)
+            .with_retry_after(retry_after)
+            .into())
+    }
+}
+
+#[async_trait]
+impl PushProvider for WebPushClient {
+    fn name(&self) -> &str {
+        "webpush"
+    }
+
+    async fn send(&self, notification: &PushNotification) -> Result<SendReceipt, Error> {
+        let PushTarget::WebPush(subscription) = &notification.target else {
+            return Err(anyhow!(
+                "Web Push does not support {} targets",
+                notification.target.kind()
+            ));
+        };
+
+        debug!(
+            endpoint = %subscription.endpoint,
+            trace_id = %notification.trace_id,
+            "Sending web push notification"
+        );
+
+        let mut data: HashMap<String, String> = notification.data.clone().unwrap_or_default();
+        data.insert("trace_id".to_string(), notification.trace_id.clone());
+
+        let payload = serde_json::to_vec(&WebPushPayload {
+            notification: WebPushNotification {
+                title: notification.title.clone(),
+                body: notification.body.clone(),
+            },
+            data,
+        })?;
+
+        let body = encrypt_payload(&payload, subscription).map_err(|e| {
+            e.downcast::<WebPushError>()
+                .unwrap_or_else(|e| WebPushError::InvalidSubscription {
+                    reason: e.to_string(),
+                })
+        })?;
+
+        self.circuit_breaker
+            .call_with_filter(
+                || self.send_with_retry(subscription, notification, &body),
+                |e| self.classify_error(e) == DeliveryErrorKind::Retryable,
+            )
+            .await
+    }
+
+    fn classify_error(&self, error: &Error) -> DeliveryErrorKind {
+        if let Some(e) = error.downcast_ref::<WebPushError>() {
+            return e.kind();
+        }
+
+        // Only a failed request can go better next time; anything else went
+        // wrong locally and will again
+        if error.is::<reqwest::Error>() {
+            DeliveryErrorKind::Retryable
+        } else {
+            DeliveryErrorKind::Permanent
+        }
+    }
+
+    fn error_code(&self, error: &Error) -> Option<String> {
+        error
+            .downcast_ref::<WebPushError>()
+            .map(|e| e.code().to_string())
+    }
+
+    fn validate_target(&self, target: &PushTarget) -> Result<(), Error> {
+        let PushTarget::WebPush(subscription) = target else {
+            return Err(anyhow!(
+                "{} does not support {} targets",
+                self.name(),
+                target.kind()
+            ));
+        };
+
+        audience_for(&subscription.endpoint)?;
+
+        if decode_base64url(&subscription.keys.p256dh)?.len() != 65 {
+            return Err(anyhow!("Subscription p256dh key must be 65 bytes"));
+        }
+
+        if decode_base64url(&subscription.keys.auth)?.len() != 16 {
+            return Err(anyhow!("Subscription auth secret must be 16 bytes"));
+        }
+
+        Ok(())
+    }
+}
@@ -2,7 +2,7 @@ use anyhow::{Error, Result, anyhow};
 use dotenvy::dotenv;
 use serde::Deserialize;
 
-use crate::models::{circuit_breaker::CircuitBreakerConfig, retry::RetryConfig};
+use crate::models::{circuit_breaker::CircuitBreakerConfig, fcm::FcmAuthMode, retry::RetryConfig};
 
 #[derive(Clone, Deserialize, Debug)]
 pub struct Config {
@@ -11,6 +11,13 @@ pub struct Config {
     pub failed_queue_name: String,
     pub prefetch_count: u16,
 
+    /// Backoff between attempts to get the broker back after losing it
+    #[serde(default = "default_rabbitmq_reconnect_initial_delay_ms")]
+    pub rabbitmq_reconnect_initial_delay_ms: u64,
+
+    #[serde(default = "default_rabbitmq_reconnect_max_delay_ms")]
+    pub rabbitmq_reconnect_max_delay_ms: u64,
+
     pub redis_url: String,
     pub idempotency_ttl_seconds: u64,
 
@@ -18,12 +25,61 @@ pub struct Config {
 
     pub template_service_url: String,
 
+    #[serde(default = "default_user_service_url")]
+    pub user_service_url: String,
+
+    /// Service JWT sent to user-service, whose user routes sit behind a guard
+    pub user_service_token: Option<String>,
+
+    #[serde(default = "default_user_cache_ttl_seconds")]
+    pub user_cache_ttl_seconds: u64,
+
     pub fcm_project_id: String,
 
+    #[serde(default = "default_fcm_base_url")]
+    pub fcm_base_url: String,
+
+    #[serde(default = "default_fcm_iid_base_url")]
+    pub fcm_iid_base_url: String,
+
+    #[serde(default)]
+    pub fcm_auth_mode: FcmAuthMode,
+
+    pub fcm_service_account_path: Option<String>,
+    pub fcm_static_token: Option<String>,
+
+    #[serde(default = "default_fcm_token_refresh_margin_seconds")]
+    pub fcm_token_refresh_margin_seconds: u64,
+
+    #[serde(default = "default_apns_base_url")]
+    pub apns_base_url: String,
+
+    pub apns_key_path: Option<String>,
+    pub apns_key_id: Option<String>,
+    pub apns_team_id: Option<String>,
+    pub apns_topic: Option<String>,
+
+    #[serde(default = "default_apns_token_refresh_seconds")]
+    pub apns_token_refresh_seconds: u64,
+
+    pub vapid_private_key: Option<String>,
+    pub vapid_subject: Option<String>,
+
+    #[serde(default = "default_vapid_token_ttl_seconds")]
+    pub vapid_token_ttl_seconds: u64,
+
+    #[serde(default = "default_web_push_ttl_seconds")]
+    pub web_push_ttl_seconds: u64,
+
     pub circuit_breaker_failure_threshold: u32,
     pub circuit_breaker_timeout_seconds: u64,
     pub circuit_breaker_success_threshold: u32,
 
+    /// How often the consumer checks whether the FCM breaker has opened or
+    /// is ready to let traffic through again
+    #[serde(default = "default_circuit_breaker_poll_interval_ms")]
+    pub circuit_breaker_poll_interval_ms: u64,
+
     pub max_retry_attempts: u32,
     pub initial_retry_delay_ms: u64,
     pub max_retry_delay_ms: u64,
@@ -31,8 +87,20 @@ pub struct Config {
 
     pub worker_concurrency: usize,
 
+    /// How long a shutdown waits for in-flight messages before handing them
+    /// back to the broker
+    #[serde(default = "default_shutdown_grace_period_ms")]
+    pub shutdown_grace_period_ms: u64,
+
     pub server_port: u16,
 
+    /// Bearer token the `/api/v1/admin` routes require. Without one they
+    /// refuse every request.
+    pub admin_api_token: Option<String>,
+
+    /// How long a message stays worth sending when it carries no
+    /// `expires_at` or `ttl_seconds` of its own, on top of the time the
+    /// retry tiers may hold it back
     #[serde(default = "default_push_queue_ttl_ms")]
     pub push_queue_ttl_ms: u32,
 
@@ -41,6 +109,29 @@ pub struct Config {
 
     #[serde(default = "default_dead_letter_routing_key")]
     pub dead_letter_routing_key: String,
+
+    /// Delay of each retry tier, in the order a failing message walks them.
+    /// Once every tier has been used the message goes to the failed queue.
+    #[serde(default = "default_retry_queue_delays_seconds")]
+    pub retry_queue_delays_seconds: Vec<u64>,
+
+    #[serde(default = "default_push_events_exchange")]
+    pub push_events_exchange: String,
+
+    /// Messages with a priority above this are sent during quiet hours
+    #[serde(default = "default_quiet_hours_bypass_priority")]
+    pub quiet_hours_bypass_priority: i32,
+
+    #[serde(default = "default_scheduler_poll_interval_ms")]
+    pub scheduler_poll_interval_ms: u64,
+
+    #[serde(default = "default_scheduler_batch_size")]
+    pub scheduler_batch_size: i64,
+
+    /// How long a claimed scheduled message may go unconfirmed before
+    /// another poll hands it out again
+    #[serde(default = "default_scheduler_claim_lease_seconds")]
+    pub scheduler_claim_lease_seconds: u64,
 }
 
 impl Config {
@@ -61,6 +152,15 @@ impl Config {
         }
     }
 
+    /// Expiry for messages that carry none of their own. It includes every
+    /// retry delay, or a message would always expire in the last tier.
+    pub fn default_message_ttl(&self) -> chrono::Duration {
+        let retry_delay_seconds: u64 = self.retry_queue_delays_seconds.iter().sum();
+
+        chrono::Duration::milliseconds(self.push_queue_ttl_ms.into())
+            + chrono::Duration::seconds(retry_delay_seconds as i64)
+    }
+
     pub fn circuit_breaker_config(&self) -> CircuitBreakerConfig {
         CircuitBreakerConfig {
             failure_threshold: self.circuit_breaker_failure_threshold,
@@ -70,6 +170,58 @@ impl Config {
     }
 }
 
+fn default_rabbitmq_reconnect_initial_delay_ms() -> u64 {
+    1_000
+}
+
+fn default_rabbitmq_reconnect_max_delay_ms() -> u64 {
+    30_000
+}
+
+fn default_shutdown_grace_period_ms() -> u64 {
+    25_000
+}
+
+fn default_user_service_url() -> String {
+    "http://localhost:3001".to_string()
+}
+
+fn default_user_cache_ttl_seconds() -> u64 {
+    30
+}
+
+fn default_fcm_base_url() -> String {
+    "https://fcm.googleapis.com".to_string()
+}
+
+fn default_fcm_iid_base_url() -> String {
+    "https://iid.googleapis.com".to_string()
+}
+
+fn default_fcm_token_refresh_margin_seconds() -> u64 {
+    60
+}
+
+fn default_apns_base_url() -> String {
+    "https://api.push.apple.com".to_string()
+}
+
+fn default_apns_token_refresh_seconds() -> u64 {
+    3000
+}
+
+fn default_vapid_token_ttl_seconds() -> u64 {
+    43_200
+}
+
+fn default_web_push_ttl_seconds() -> u64 {
+    86_400
+}
+
+fn default_circuit_breaker_poll_interval_ms() -> u64 {
+    1_000
+}
+
 fn default_push_queue_ttl_ms() -> u32 {
     3_600_000
 }
@@ -81,3 +233,27 @@ fn default_dead_letter_exchange() -> String {
 fn default_dead_letter_routing_key() -> String {
     "failed".to_string()
 }
+
+fn default_retry_queue_delays_seconds() -> Vec<u64> {
+    vec![30, 300, 3_600]
+}
+
+fn default_push_events_exchange() -> String {
+    "push.events".to_string()
+}
+
+fn default_quiet_hours_bypass_priority() -> i32 {
+    5
+}
+
+fn default_scheduler_poll_interval_ms() -> u64 {
+    5_000
+}
+
+fn default_scheduler_batch_size() -> i64 {
+    100
+}
+
+fn default_scheduler_claim_lease_seconds() -> u64 {
+    60
+}
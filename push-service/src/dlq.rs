@@ -0,0 +1,142 @@
+use anyhow::{Error, Result};
+use chrono::{DateTime, Duration, Utc};
+use tracing::{info, warn};
+
+use crate::{
+    clients::{rbmq::RabbitMqClient, redis::RedisClient},
+    models::{
+        dlq::{
+            DlqInspection, DlqPurgeResult, DlqReplayRequest, DlqReplayResult, FailureReasonCount,
+        },
+        message::DlqMessage,
+        status::IdempotencyStatus,
+    },
+};
+
+/// Peeks at up to `limit` failed messages without removing any of them.
+pub async fn inspect(
+    rabbitmq_client: &RabbitMqClient,
+    limit: usize,
+) -> Result<DlqInspection, Error> {
+    let mut browser = rabbitmq_client.dlq_browser().await?;
+    let total = browser.total();
+
+    let mut messages = Vec::new();
+    let mut inspected = 0;
+    let mut unparseable = 0;
+
+    while inspected < limit {
+        let Some(delivery) = browser.next().await? else {
+            break;
+        };
+
+        inspected += 1;
+
+        match serde_json::from_slice::<DlqMessage>(&delivery.data) {
+            Ok(message) => messages.push(message),
+            Err(_) => unparseable += 1,
+        }
+    }
+
+    browser.finish().await?;
+
+    Ok(DlqInspection {
+        total,
+        inspected,
+        unparseable,
+        by_reason: FailureReasonCount::group(&messages),
+        messages,
+    })
+}
+
+/// Puts the failed messages selected by `request` back on the push queue.
+/// Their `failed` idempotency state is cleared first so the worker does not
+/// treat the replay as a duplicate.
+pub async fn replay(
+    rabbitmq_client: &RabbitMqClient,
+    redis_client: &RedisClient,
+    request: &DlqReplayRequest,
+) -> Result<DlqReplayResult, Error> {
+    let mut browser = rabbitmq_client.dlq_browser().await?;
+    let total = browser.total() as usize;
+    let limit = request.limit.unwrap_or(usize::MAX);
+
+    let mut replayed = 0;
+
+    while replayed < limit {
+        let Some(delivery) = browser.next().await? else {
+            break;
+        };
+
+        let Ok(dlq_message) = serde_json::from_slice::<DlqMessage>(&delivery.data) else {
+            warn!("Skipping unparseable DLQ message");
+            continue;
+        };
+
+        if !request.matches(&dlq_message) {
+            continue;
+        }
+
+        let message = &dlq_message.original_message;
+
+        if redis_client
+            .check_idempotency(&message.idempotency_key)
+            .await?
+            == IdempotencyStatus::Failed
+        {
+            redis_client.release(&message.idempotency_key).await?;
+        }
+
+        // A message that died mid-flight may still have the message and some
+        // of its devices marked as processing
+        redis_client
+            .release_unfinished(&message.idempotency_key)
+            .await?;
+
+        browser.replay(&delivery, message).await?;
+        replayed += 1;
+    }
+
+    browser.finish().await?;
+
+    info!(replayed, "DLQ messages replayed");
+
+    Ok(DlqReplayResult {
+        replayed,
+        remaining: total.saturating_sub(replayed),
+    })
+}
+
+/// Drops failed messages that have been in the DLQ for longer than
+/// `older_than`. Messages whose failure time cannot be read are kept.
+pub async fn purge(
+    rabbitmq_client: &RabbitMqClient,
+    older_than: Duration,
+) -> Result<DlqPurgeResult, Error> {
+    let cutoff = Utc::now() - older_than;
+
+    let mut browser = rabbitmq_client.dlq_browser().await?;
+    let total = browser.total() as usize;
+
+    let mut purged = 0;
+
+    while let Some(delivery) = browser.next().await? {
+        let failed_at = serde_json::from_slice::<DlqMessage>(&delivery.data)
+            .ok()
+            .and_then(|message| DateTime::parse_from_rfc3339(&message.failed_at).ok());
+
+        if failed_at.is_some_and(|failed_at| failed_at < cutoff) {
+            browser.remove(&delivery).await?;
+            purged += 1;
+        }
+    }
+
+    browser.finish().await?;
+
+    info!(purged, "DLQ messages purged");
+
+    Ok(DlqPurgeResult {
+        purged,
+        remaining: total.saturating_sub(purged),
+    })
+}
@@ -1,5 +1,8 @@
 pub mod api;
 pub mod clients;
 pub mod config;
+pub mod dlq;
 pub mod models;
+pub mod scheduler;
+pub mod shutdown;
 pub mod utils;
@@ -1,20 +1,30 @@
-use std::sync::Arc;
+use std::{io::Write, sync::Arc, time::Duration};
 
 use anyhow::{Error, Result, anyhow};
 use chrono::{SecondsFormat, Utc};
 use push_service::{
     api::run_api_server,
     clients::{
-        circuit_breaker::CircuitBreaker, database::DatabaseClient, fcm::FcmClient,
-        rbmq::RabbitMqClient, redis::RedisClient, template::TemplateServiceClient,
+        apns::ApnsClient, circuit_breaker::CircuitBreaker, database::DatabaseClient,
+        fcm::FcmClient, provider::ProviderRegistry, rbmq::RabbitMqClient, redis::RedisClient,
+        template::TemplateServiceClient, user::UserServiceClient, webpush::WebPushClient,
     },
     config::Config,
-    models::message::{DlqMessage, NotificationMessage},
-    utils::process_message,
+    models::{
+        event::PushOutcomeEvent,
+        message::{DlqMessage, Envelope},
+        status::NotificationStatus,
+    },
+    scheduler::run_scheduler,
+    shutdown::{InFlight, wait_for_signal},
+    utils::{ProcessingError, ProcessingOutcome, is_retryable, process_message},
 };
 
 use futures_util::StreamExt;
-use tokio::sync::{Mutex, Semaphore};
+use tokio::{
+    sync::{Semaphore, oneshot},
+    time::{MissedTickBehavior, interval},
+};
 use tracing::{error, info, warn};
 
 #[tokio::main]
@@ -37,39 +47,94 @@ async fn main() -> Result<(), Error> {
 
     let database_client = Arc::new(DatabaseClient::connect(&config.database_url).await?);
 
+    let redis_client = redis::Client::open(config.redis_url.as_str())?;
+    let redis_conn = redis_client.get_multiplexed_async_connection().await?;
+
+    let fcm_circuit_breaker = CircuitBreaker::new(
+        "fcm".to_string(),
+        redis_conn.clone(),
+        config.circuit_breaker_config(),
+    );
+
+    let fcm_client = FcmClient::new(&config, fcm_circuit_breaker.clone()).await?;
+
+    let rabbitmq_client = Arc::new(RabbitMqClient::connect(&config).await?);
+    let idempotency_client = RedisClient::connect(&config).await?;
+
     let health_config = config.clone();
     let database_for_api = Arc::clone(&database_client);
-    tokio::spawn(async move {
-        if let Err(e) = run_api_server(health_config, database_for_api).await {
+    let fcm_for_api = fcm_client.clone();
+    let rabbitmq_for_api = Arc::clone(&rabbitmq_client);
+    let redis_for_api = idempotency_client.clone();
+    let (stop_api, api_stopped) = oneshot::channel::<()>();
+    let api_server = tokio::spawn(async move {
+        if let Err(e) = run_api_server(
+            health_config,
+            database_for_api,
+            fcm_for_api,
+            rabbitmq_for_api,
+            redis_for_api,
+            async {
+                let _ = api_stopped.await;
+            },
+        )
+        .await
+        {
             error!(error = %e, "Health check server failed");
         }
     });
 
-    let rabbitmq_client = Arc::new(RabbitMqClient::connect(&config).await?);
     let mut consumer = rabbitmq_client.create_consumer().await?;
 
-    let redis_client = redis::Client::open(config.redis_url.as_str())?;
-    let redis_conn = redis_client.get_multiplexed_async_connection().await?;
+    let (stop_scheduler, scheduler_stopped) = oneshot::channel::<()>();
+    let scheduler = tokio::spawn(run_scheduler(
+        config.clone(),
+        Arc::clone(&database_client),
+        Arc::clone(&rabbitmq_client),
+        async {
+            let _ = scheduler_stopped.await;
+        },
+    ));
 
-    let fcm_circuit_breaker = CircuitBreaker::new(
-        "fcm".to_string(),
+    let template_circuit_breaker = CircuitBreaker::new(
+        "template_service".to_string(),
         redis_conn.clone(),
         config.circuit_breaker_config(),
     );
 
-    let template_circuit_breaker = CircuitBreaker::new(
-        "template_service".to_string(),
-        redis_conn,
+    let template_service_client =
+        TemplateServiceClient::new(&config, template_circuit_breaker).await?;
+
+    let user_circuit_breaker = CircuitBreaker::new(
+        "user_service".to_string(),
+        redis_conn.clone(),
         config.circuit_breaker_config(),
     );
 
-    let template_service_client = Arc::new(Mutex::new(
-        TemplateServiceClient::new(&config, template_circuit_breaker).await?,
-    ));
+    let user_service_client =
+        UserServiceClient::new(&config, user_circuit_breaker, redis_conn.clone())?;
 
-    let fcm_client = Arc::new(Mutex::new(
-        FcmClient::new(&config, fcm_circuit_breaker).await,
-    ));
+    let mut providers = ProviderRegistry::new().register(fcm_client);
+
+    if config.apns_key_path.is_some() {
+        let apns_circuit_breaker = CircuitBreaker::new(
+            "apns".to_string(),
+            redis_conn.clone(),
+            config.circuit_breaker_config(),
+        );
+
+        providers = providers.register(ApnsClient::new(&config, apns_circuit_breaker).await?);
+    }
+
+    if config.vapid_private_key.is_some() {
+        let webpush_circuit_breaker = CircuitBreaker::new(
+            "webpush".to_string(),
+            redis_conn,
+            config.circuit_breaker_config(),
+        );
+
+        providers = providers.register(WebPushClient::new(&config, webpush_circuit_breaker)?);
+    }
 
     let semaphore = Arc::new(Semaphore::new(config.worker_concurrency));
 
@@ -78,96 +143,306 @@ async fn main() -> Result<(), Error> {
         "Worker started with concurrency limit"
     );
 
-    while let Some(delivery) = consumer.next().await {
-        match delivery {
-            Ok(delivery) => {
-                let delivery_tag = delivery.delivery_tag;
-                let payload = String::from_utf8_lossy(&delivery.data).to_string();
+    let shutdown = wait_for_signal();
+    tokio::pin!(shutdown);
 
-                let rabbitmq_client = Arc::clone(&rabbitmq_client);
-                let template_service_client = Arc::clone(&template_service_client);
-                let fcm_client = Arc::clone(&fcm_client);
-                let database_client = Arc::clone(&database_client);
-                let semaphore = Arc::clone(&semaphore);
-                let config = config.clone();
+    let mut in_flight = InFlight::new();
 
-                tokio::spawn(async move {
-                    let _permit = semaphore.acquire().await.unwrap();
+    // While FCM is unreachable every delivery would fail fast and end up in
+    // the DLQ, so messages are left on the queue until the breaker lets a
+    // trial request through
+    let mut breaker_check = interval(Duration::from_millis(
+        config.circuit_breaker_poll_interval_ms,
+    ));
+    breaker_check.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut paused = false;
 
-                    let mut redis_client = match RedisClient::connect(&config).await {
-                        Ok(client) => client,
+    'consume: loop {
+        loop {
+            let delivery = tokio::select! {
+                biased;
+                _ = &mut shutdown => break 'consume,
+                Some(()) = in_flight.reap(), if !in_flight.is_empty() => continue,
+                _ = breaker_check.tick() => {
+                    let state = match fcm_circuit_breaker.current_state().await {
+                        Ok(state) => state,
                         Err(e) => {
-                            error!(error = %e, "Failed to connect to Redis");
-                            if let Err(reject_err) =
-                                rabbitmq_client.reject(delivery_tag, true).await
-                            {
-                                error!(error = %reject_err, "Failed to requeue message");
-                            }
-                            return;
+                            warn!(error = %e, "Failed to read FCM circuit breaker state");
+                            continue;
                         }
                     };
 
-                    let mut template_client = template_service_client.lock().await;
-                    let mut fcm = fcm_client.lock().await;
-
-                    match process_message(
-                        &payload,
-                        &mut redis_client,
-                        &mut template_client,
-                        &mut fcm,
-                        &database_client,
-                    )
-                    .await
-                    {
-                        Ok(_) => {
-                            info!("Message processed successfully");
-                            if let Err(ack_err) = rabbitmq_client.acknowledge(delivery_tag).await {
-                                error!(error = %ack_err, "Failed to acknowledge message");
+                    if !paused && !state.admits_requests() {
+                        match rabbitmq_client.pause_consumer(&mut consumer).await {
+                            Ok(requeued) => {
+                                warn!(
+                                    requeued,
+                                    "FCM circuit breaker is open, pausing consumption"
+                                );
+                                paused = true;
                             }
+                            Err(e) => error!(error = %e, "Failed to pause consumer"),
                         }
-                        Err(e) => {
-                            error!(error = %e, "Failed to process message");
-
-                            match serde_json::from_str::<NotificationMessage>(&payload) {
-                                Ok(original_message) => {
-                                    let dlq_message = DlqMessage {
-                                        original_message,
-                                        failure_reason: e.to_string(),
-                                        failed_at: Utc::now()
-                                            .to_rfc3339_opts(SecondsFormat::Millis, true),
-                                    };
-
-                                    if let Err(dlq_err) =
-                                        rabbitmq_client.publish_to_dlq(&dlq_message).await
-                                    {
-                                        error!(error = %dlq_err, "Failed to publish to DLQ");
+                    } else if paused && state.admits_requests() {
+                        paused = false;
+
+                        match rabbitmq_client.create_consumer().await {
+                            Ok(resumed) => {
+                                info!(
+                                    state = state.as_str(),
+                                    "FCM circuit breaker admits requests, resuming consumption"
+                                );
+                                consumer = resumed;
+                            }
+                            Err(e) => {
+                                error!(error = %e, "Failed to resume consumer");
+                                break;
+                            }
+                        }
+                    }
+
+                    continue;
+                }
+                delivery = consumer.next(), if !paused => delivery,
+            };
+
+            let Some(delivery) = delivery else {
+                break;
+            };
+
+            match delivery {
+                Ok(delivery) => {
+                    let acker = delivery.acker.clone();
+                    let payload = String::from_utf8_lossy(&delivery.data).to_string();
+                    let headers = delivery.properties.headers().clone();
+
+                    let message = serde_json::from_str::<Envelope>(&payload)
+                        .ok()
+                        .map(|envelope| envelope.data);
+                    let idempotency_key = message
+                        .as_ref()
+                        .map(|message| message.idempotency_key.clone());
+
+                    let rabbitmq_client = Arc::clone(&rabbitmq_client);
+                    let redis_client = idempotency_client.clone();
+                    let template_service_client = template_service_client.clone();
+                    let user_service_client = user_service_client.clone();
+                    let providers = providers.clone();
+                    let database_client = Arc::clone(&database_client);
+                    let semaphore = Arc::clone(&semaphore);
+                    let config = config.clone();
+
+                    in_flight.spawn(delivery.acker, idempotency_key, async move {
+                        // The semaphore is closed on shutdown; a message still
+                        // waiting for its turn goes straight back to the queue
+                        let Ok(_permit) = semaphore.acquire().await else {
+                            if let Err(reject_err) = rabbitmq_client.reject(&acker, true).await {
+                                error!(error = %reject_err, "Failed to requeue message");
+                            }
+                            return;
+                        };
+
+                        let attempt = rabbitmq_client.delivery_attempt(headers.as_ref());
+
+                        match process_message(
+                            &payload,
+                            &redis_client,
+                            &template_service_client,
+                            &user_service_client,
+                            &providers,
+                            &database_client,
+                            rabbitmq_client.as_ref(),
+                            &config,
+                        )
+                        .await
+                        {
+                            Ok(outcome) => {
+                                info!("Message processed successfully");
+
+                                let event = match (outcome, &message) {
+                                    (
+                                        ProcessingOutcome::Delivered {
+                                            provider_message_ids,
+                                        },
+                                        Some(message),
+                                    ) => Some(
+                                        PushOutcomeEvent::new(
+                                            message,
+                                            NotificationStatus::Sent,
+                                            attempt,
+                                        )
+                                        .with_provider_message_ids(provider_message_ids),
+                                    ),
+                                    (ProcessingOutcome::Expired, Some(message)) => {
+                                        Some(PushOutcomeEvent::new(
+                                            message,
+                                            NotificationStatus::Expired,
+                                            attempt,
+                                        ))
                                     }
+                                    _ => None,
+                                };
+
+                                if let Some(event) = event
+                                    && let Err(event_err) =
+                                        rabbitmq_client.publish_outcome(&event).await
+                                {
+                                    error!(error = %event_err, "Failed to publish delivery outcome");
                                 }
-                                Err(parse_err) => {
-                                    error!(
-                                        error = %parse_err,
-                                        payload = %payload,
-                                        "Cannot parse message as JSON"
-                                    );
+
+                                if let Err(ack_err) = rabbitmq_client.acknowledge(&acker).await {
+                                    error!(error = %ack_err, "Failed to acknowledge message");
                                 }
                             }
+                            Err(e) => {
+                                error!(error = %e, "Failed to process message");
+
+                                if is_retryable(&e) {
+                                    match rabbitmq_client
+                                        .publish_for_retry(payload.as_bytes(), headers.as_ref())
+                                        .await
+                                    {
+                                        Ok(true) => {
+                                            if let Err(ack_err) =
+                                                rabbitmq_client.acknowledge(&acker).await
+                                            {
+                                                error!(error = %ack_err, "Failed to acknowledge message");
+                                            }
+                                            return;
+                                        }
+                                        Ok(false) => {
+                                            warn!("Retry tiers exhausted, sending message to DLQ");
+                                        }
+                                        Err(retry_err) => {
+                                            error!(error = %retry_err, "Failed to schedule retry");
+                                        }
+                                    }
+                                }
+
+                                // Dropping the original is only safe once the DLQ
+                                // copy has been confirmed; otherwise it goes back on
+                                // the push queue
+                                let requeue = match message {
+                                    Some(original_message) => {
+                                        let mut event = PushOutcomeEvent::new(
+                                            &original_message,
+                                            NotificationStatus::Failed,
+                                            attempt,
+                                        )
+                                        .with_error(e.to_string());
+
+                                        if let Some(code) = e
+                                            .downcast_ref::<ProcessingError>()
+                                            .and_then(|e| e.error_code.clone())
+                                        {
+                                            event = event.with_error_code(code);
+                                        }
+
+                                        let dlq_message = DlqMessage {
+                                            original_message,
+                                            failure_reason: e.to_string(),
+                                            failed_at: Utc::now()
+                                                .to_rfc3339_opts(SecondsFormat::Millis, true),
+                                        };
+
+                                        match rabbitmq_client.publish_to_dlq(&dlq_message).await {
+                                            Ok(()) => {
+                                                if let Err(event_err) =
+                                                    rabbitmq_client.publish_outcome(&event).await
+                                                {
+                                                    error!(error = %event_err, "Failed to publish delivery outcome");
+                                                }
+                                                false
+                                            }
+                                            Err(dlq_err) => {
+                                                error!(error = %dlq_err, "Failed to publish to DLQ, requeueing message");
+                                                true
+                                            }
+                                        }
+                                    }
+                                    None => {
+                                        error!(payload = %payload, "Cannot parse message as JSON");
+                                        false
+                                    }
+                                };
 
-                            if let Err(reject_err) =
-                                rabbitmq_client.reject(delivery_tag, false).await
-                            {
-                                error!(error = %reject_err, "Failed to reject message");
+                                if let Err(reject_err) =
+                                    rabbitmq_client.reject(&acker, requeue).await
+                                {
+                                    error!(error = %reject_err, "Failed to reject message");
+                                }
                             }
                         }
-                    }
-                });
-            }
-            Err(e) => {
-                error!(error = ?e, "Error receiving message from queue");
+                    });
+                }
+                Err(e) => {
+                    error!(error = ?e, "Error receiving message from queue");
+                }
             }
         }
+
+        // The channel died, most likely because the broker went away. Once
+        // it is back the consumer is recreated and picks up where it left
+        warn!("Consumer closed, reconnecting to RabbitMQ");
+
+        consumer = tokio::select! {
+            _ = &mut shutdown => break 'consume,
+            consumer = async {
+                loop {
+                    rabbitmq_client.reconnect().await;
+
+                    match rabbitmq_client.create_consumer().await {
+                        Ok(consumer) => break consumer,
+                        Err(e) => error!(error = %e, "Failed to recreate consumer"),
+                    }
+                }
+            } => consumer,
+        };
+    }
+
+    info!("Shutting down, no longer accepting messages");
+
+    semaphore.close();
+    let _ = stop_scheduler.send(());
+
+    if !paused && let Err(e) = rabbitmq_client.cancel_consumer().await {
+        warn!(error = %e, "Failed to cancel consumer");
     }
 
-    warn!("Consumer closed, shutting down");
+    let report = in_flight
+        .drain(
+            Duration::from_millis(config.shutdown_grace_period_ms),
+            &rabbitmq_client,
+            &idempotency_client,
+        )
+        .await;
+
+    info!(
+        completed = report.completed,
+        requeued = report.requeued,
+        "In-flight messages drained"
+    );
+
+    // A batch the scheduler already claimed is published before it stops
+    if let Err(e) = scheduler.await {
+        error!(error = %e, "Scheduler task failed");
+    }
+
+    // Prefetched messages that were never handed out go back to the queue
+    // with the connection
+    if let Err(e) = rabbitmq_client.close().await {
+        warn!(error = %e, "Failed to close RabbitMQ connection");
+    }
+
+    let _ = stop_api.send(());
+
+    if let Err(e) = api_server.await {
+        error!(error = %e, "Health check server task failed");
+    }
+
+    info!("Push service stopped");
+
+    std::io::stdout().flush()?;
 
     Ok(())
 }
@@ -0,0 +1,127 @@
+use std::{
+    collections::HashMap,
+    fmt::{Display, Formatter, Result as FmtResult},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::models::provider::DeliveryErrorKind;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApnsPayload {
+    pub aps: Aps,
+
+    #[serde(flatten)]
+    pub data: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Aps {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alert: Option<ApnsAlert>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub badge: Option<u32>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sound: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub category: Option<String>,
+
+    #[serde(rename = "mutable-content", skip_serializing_if = "Option::is_none")]
+    pub mutable_content: Option<u8>,
+
+    #[serde(rename = "content-available", skip_serializing_if = "Option::is_none")]
+    pub content_available: Option<u8>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApnsAlert {
+    pub title: String,
+    pub body: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApnsClaims {
+    pub iss: String,
+    pub iat: i64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ApnsErrorResponse {
+    pub reason: String,
+    pub timestamp: Option<i64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ApnsError {
+    BadDeviceToken,
+    DeviceTokenNotForTopic,
+    Unregistered { timestamp: Option<i64> },
+    TooManyRequests,
+    ExpiredProviderToken,
+    InvalidProviderToken,
+    ServiceUnavailable { reason: String },
+    Rejected { status: u16, reason: String },
+}
+
+impl ApnsError {
+    pub fn from_response(status: u16, body: &str) -> Self {
+        let response = serde_json::from_str::<ApnsErrorResponse>(body).ok();
+        let reason = response
+            .as_ref()
+            .map(|r| r.reason.clone())
+            .unwrap_or_else(|| body.to_string());
+
+        match reason.as_str() {
+            "BadDeviceToken" => ApnsError::BadDeviceToken,
+            "DeviceTokenNotForTopic" => ApnsError::DeviceTokenNotForTopic,
+            "Unregistered" => ApnsError::Unregistered {
+                timestamp: response.and_then(|r| r.timestamp),
+            },
+            "TooManyRequests" => ApnsError::TooManyRequests,
+            "ExpiredProviderToken" => ApnsError::ExpiredProviderToken,
+            "InvalidProviderToken" => ApnsError::InvalidProviderToken,
+            _ if status >= 500 => ApnsError::ServiceUnavailable { reason },
+            _ => ApnsError::Rejected { status, reason },
+        }
+    }
+
+    pub fn kind(&self) -> DeliveryErrorKind {
+        match self {
+            ApnsError::BadDeviceToken | ApnsError::Unregistered { .. } => {
+                DeliveryErrorKind::InvalidToken
+            }
+            ApnsError::TooManyRequests
+            | ApnsError::ExpiredProviderToken
+            | ApnsError::ServiceUnavailable { .. } => DeliveryErrorKind::Retryable,
+            // The token is fine, our APNS_TOPIC does not match the app that
+            // registered it; pruning would wipe every iOS device
+            ApnsError::DeviceTokenNotForTopic
+            | ApnsError::InvalidProviderToken
+            | ApnsError::Rejected { .. } => DeliveryErrorKind::Permanent,
+        }
+    }
+
+    pub fn reason(&self) -> &str {
+        match self {
+            ApnsError::BadDeviceToken => "BadDeviceToken",
+            ApnsError::DeviceTokenNotForTopic => "DeviceTokenNotForTopic",
+            ApnsError::Unregistered { .. } => "Unregistered",
+            ApnsError::TooManyRequests => "TooManyRequests",
+            ApnsError::ExpiredProviderToken => "ExpiredProviderToken",
+            ApnsError::InvalidProviderToken => "InvalidProviderToken",
+            ApnsError::ServiceUnavailable { reason } => reason,
+            ApnsError::Rejected { reason, .. } => reason,
+        }
+    }
+}
+
+impl Display for ApnsError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "APNs request failed: {}", self.reason())
+    }
+}
+
+impl std::error::Error for ApnsError {}
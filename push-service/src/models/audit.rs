@@ -14,6 +14,7 @@ pub struct AuditLog {
     pub template_code: String,
     pub status: NotificationStatus,
     pub error_message: Option<String>,
+    pub error_code: Option<String>,
     pub metadata: JsonValue,
     pub created_at: DateTime<Utc>,
 }
@@ -26,6 +27,7 @@ pub struct CreateAuditLog {
     pub template_code: String,
     pub status: NotificationStatus,
     pub error_message: Option<String>,
+    pub error_code: Option<String>,
     pub metadata: JsonValue,
 }
 
@@ -44,6 +46,7 @@ impl CreateAuditLog {
             template_code,
             status,
             error_message: None,
+            error_code: None,
             metadata: serde_json::json!({}),
         }
     }
@@ -53,6 +56,11 @@ impl CreateAuditLog {
         self
     }
 
+    pub fn with_error_code(mut self, code: String) -> Self {
+        self.error_code = Some(code);
+        self
+    }
+
     pub fn with_metadata(mut self, metadata: JsonValue) -> Self {
         self.metadata = metadata;
         self
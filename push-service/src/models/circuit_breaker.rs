@@ -17,6 +17,11 @@ impl CircuitState {
         }
     }
 
+    /// Whether calls are let through to the service at all
+    pub fn admits_requests(&self) -> bool {
+        *self != CircuitState::Open
+    }
+
     pub fn as_str(&self) -> &str {
         match self {
             CircuitState::Closed => "closed",
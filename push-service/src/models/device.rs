@@ -0,0 +1,223 @@
+use anyhow::{Error, Result, anyhow};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::models::{
+    provider::{DeliveryErrorKind, PushTarget},
+    status::NotificationStatus,
+    webpush::WebPushSubscription,
+};
+
+/// A single destination a message fans out to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceTarget {
+    /// Stable identifier used for per-device idempotency and audit entries
+    pub device_id: String,
+    pub provider: Option<String>,
+    pub target: PushTarget,
+}
+
+impl DeviceTarget {
+    pub fn new(target: PushTarget, provider: Option<String>) -> Self {
+        let digest = Sha256::digest(target.identifier().as_bytes());
+        let device_id = digest[..8].iter().map(|b| format!("{:02x}", b)).collect();
+
+        Self {
+            device_id,
+            provider,
+            target,
+        }
+    }
+}
+
+/// A device registered in the `device_tokens` table. For Web Push the token
+/// column holds the subscription serialized as JSON.
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceToken {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub token: String,
+    pub platform: DevicePlatform,
+    pub provider: String,
+    pub app_id: Option<String>,
+    pub locale: Option<String>,
+    pub timezone: Option<String>,
+    pub last_seen: DateTime<Utc>,
+    pub invalidated_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl DeviceToken {
+    pub fn target(&self) -> Result<PushTarget, Error> {
+        if self.provider == "webpush" {
+            let subscription = serde_json::from_str(&self.token)
+                .map_err(|e| anyhow!("Invalid stored Web Push subscription: {}", e))?;
+            return Ok(PushTarget::WebPush(subscription));
+        }
+
+        Ok(PushTarget::Token(self.token.clone()))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DevicePlatform {
+    Android,
+    Ios,
+    Web,
+}
+
+impl DevicePlatform {
+    pub fn as_str(&self) -> &str {
+        match self {
+            DevicePlatform::Android => "android",
+            DevicePlatform::Ios => "ios",
+            DevicePlatform::Web => "web",
+        }
+    }
+
+    pub fn from_string(s: &str) -> Result<Self, Error> {
+        match s {
+            "android" => Ok(DevicePlatform::Android),
+            "ios" => Ok(DevicePlatform::Ios),
+            "web" => Ok(DevicePlatform::Web),
+            other => Err(anyhow!("Unknown device platform: {}", other)),
+        }
+    }
+
+    /// Provider used when a registration does not name one
+    pub fn default_provider(&self) -> &str {
+        match self {
+            DevicePlatform::Android | DevicePlatform::Ios => "fcm",
+            DevicePlatform::Web => "webpush",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RegisterDeviceRequest {
+    pub token: String,
+    pub platform: DevicePlatform,
+    pub provider: Option<String>,
+    pub app_id: Option<String>,
+    pub locale: Option<String>,
+    pub timezone: Option<String>,
+}
+
+impl RegisterDeviceRequest {
+    pub fn provider(&self) -> &str {
+        self.provider
+            .as_deref()
+            .unwrap_or(self.platform.default_provider())
+    }
+}
+
+/// Marks a device as seen, optionally rotating its token.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct RefreshDeviceRequest {
+    pub token: Option<String>,
+    pub locale: Option<String>,
+    pub timezone: Option<String>,
+}
+
+/// How many registered tokens have been invalidated after providers
+/// reported them dead.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PrunedTokenStats {
+    pub total: i64,
+    pub last_24h: i64,
+}
+
+/// Device entry accepted in `metadata.devices`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct MetadataDevice {
+    pub push_token: Option<String>,
+    pub push_subscription: Option<WebPushSubscription>,
+    pub push_provider: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeviceDeliveryStatus {
+    Sent {
+        /// Id the provider assigned to the push, if it returns one
+        message_id: Option<String>,
+    },
+    /// Delivered by an earlier attempt at this message
+    AlreadySent,
+    /// Another worker currently owns this device
+    InProgress,
+    Failed {
+        kind: DeliveryErrorKind,
+        error: String,
+        error_code: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct DeviceOutcome {
+    pub device_id: String,
+    pub status: DeviceDeliveryStatus,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct DeliverySummary {
+    pub total: usize,
+    pub sent: usize,
+    pub failed: usize,
+    /// Devices another attempt claimed but never settled, e.g. a worker
+    /// that crashed mid-send. They may or may not have been delivered.
+    pub in_progress: usize,
+    /// Failed devices whose token was dead and has been invalidated
+    pub pruned: usize,
+}
+
+impl DeliverySummary {
+    pub fn from_outcomes(outcomes: &[DeviceOutcome]) -> Self {
+        let failed = outcomes
+            .iter()
+            .filter(|o| matches!(o.status, DeviceDeliveryStatus::Failed { .. }))
+            .count();
+
+        let in_progress = outcomes
+            .iter()
+            .filter(|o| matches!(o.status, DeviceDeliveryStatus::InProgress))
+            .count();
+
+        let pruned = outcomes
+            .iter()
+            .filter(|o| {
+                matches!(
+                    o.status,
+                    DeviceDeliveryStatus::Failed {
+                        kind: DeliveryErrorKind::InvalidToken,
+                        ..
+                    }
+                )
+            })
+            .count();
+
+        Self {
+            total: outcomes.len(),
+            sent: outcomes.len() - failed - in_progress,
+            failed,
+            in_progress,
+            pruned,
+        }
+    }
+
+    /// Whether every device is accounted for as delivered
+    pub fn is_complete(&self) -> bool {
+        self.failed == 0 && self.in_progress == 0
+    }
+
+    pub fn status(&self) -> NotificationStatus {
+        match (self.sent, self.failed + self.in_progress) {
+            (_, 0) => NotificationStatus::Sent,
+            (0, _) => NotificationStatus::Failed,
+            _ => NotificationStatus::PartiallySent,
+        }
+    }
+}
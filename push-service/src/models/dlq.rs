@@ -0,0 +1,94 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::models::message::DlqMessage;
+
+/// What an operator sees when peeking at the failed queue.
+#[derive(Debug, Clone, Serialize)]
+pub struct DlqInspection {
+    /// Messages in the failed queue when the peek started
+    pub total: u32,
+    pub inspected: usize,
+    /// Messages that are not a `DlqMessage` and can only be purged by hand
+    pub unparseable: usize,
+    pub by_reason: Vec<FailureReasonCount>,
+    pub messages: Vec<DlqMessage>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FailureReasonCount {
+    pub failure_reason: String,
+    pub count: usize,
+}
+
+impl FailureReasonCount {
+    /// Counts messages per failure reason, most common first
+    pub fn group(messages: &[DlqMessage]) -> Vec<Self> {
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+
+        for message in messages {
+            *counts.entry(message.failure_reason.as_str()).or_default() += 1;
+        }
+
+        let mut groups = counts
+            .into_iter()
+            .map(|(failure_reason, count)| Self {
+                failure_reason: failure_reason.to_string(),
+                count,
+            })
+            .collect::<Vec<_>>();
+
+        groups.sort_by(|a, b| {
+            b.count
+                .cmp(&a.count)
+                .then_with(|| a.failure_reason.cmp(&b.failure_reason))
+        });
+
+        groups
+    }
+}
+
+/// Selects which failed messages go back to the push queue. Without any
+/// filter every message is replayed.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct DlqReplayRequest {
+    pub idempotency_keys: Option<Vec<String>>,
+    pub failure_reason: Option<String>,
+    pub limit: Option<usize>,
+}
+
+impl DlqReplayRequest {
+    pub fn matches(&self, message: &DlqMessage) -> bool {
+        let key_matches = self.idempotency_keys.as_ref().is_none_or(|keys| {
+            keys.iter()
+                .any(|key| key == &message.original_message.idempotency_key)
+        });
+
+        let reason_matches = self
+            .failure_reason
+            .as_ref()
+            .is_none_or(|reason| reason == &message.failure_reason);
+
+        key_matches && reason_matches
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DlqReplayResult {
+    pub replayed: usize,
+    /// Messages left in the failed queue
+    pub remaining: usize,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DlqPurgeQuery {
+    pub older_than_seconds: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DlqPurgeResult {
+    pub purged: usize,
+    /// Messages left in the failed queue
+    pub remaining: usize,
+}
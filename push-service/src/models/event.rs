@@ -0,0 +1,107 @@
+use chrono::{SecondsFormat, Utc};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::models::{message::NotificationMessage, status::NotificationStatus};
+
+pub const TOKEN_INVALIDATED: &str = "push.token.invalidated";
+pub const PUSH_DELIVERED: &str = "notification.push.delivered";
+pub const PUSH_FAILED: &str = "notification.push.failed";
+pub const PUSH_EXPIRED: &str = "notification.push.expired";
+
+/// Bumped whenever a field of `PushOutcomeEvent` changes meaning or goes away
+pub const PUSH_OUTCOME_VERSION: u32 = 1;
+
+/// Outbound events use the same `{pattern, data}` shape as the messages we
+/// consume, so Nest services can subscribe to them with `@EventPattern`.
+#[derive(Debug, Serialize)]
+pub struct EventEnvelope<'a, T> {
+    pub pattern: &'a str,
+    pub data: &'a T,
+}
+
+/// Published when a provider reports a token as dead so other services can
+/// drop their copy of it.
+#[derive(Debug, Clone, Serialize)]
+pub struct TokenInvalidatedEvent {
+    pub user_id: String,
+    pub token: String,
+    pub provider: Option<String>,
+    /// Registry rows that were invalidated; empty when the token only came
+    /// from message metadata
+    pub device_ids: Vec<Uuid>,
+    pub reason: String,
+    pub error_code: Option<String>,
+    pub trace_id: String,
+    pub invalidated_at: String,
+}
+
+/// Published once a message reaches a terminal outcome: delivered to every
+/// device, dropped as expired, or given up on after its last attempt.
+/// Duplicates, suppressed and deferred messages publish nothing; the attempt
+/// that owns the message reports its outcome.
+#[derive(Debug, Clone, Serialize)]
+pub struct PushOutcomeEvent {
+    pub version: u32,
+    pub request_id: String,
+    pub notification_id: String,
+    pub idempotency_key: String,
+    pub user_id: String,
+    pub status: NotificationStatus,
+    /// Ids the providers assigned, one per device delivered on this attempt
+    pub provider_message_ids: Vec<String>,
+    pub error: Option<String>,
+    pub error_code: Option<String>,
+    /// 1 for the first delivery, counting every retry tier the message went
+    /// through
+    pub attempt: u32,
+    /// Time from the message being created (or becoming due) to its outcome
+    pub latency_ms: Option<i64>,
+    pub occurred_at: String,
+}
+
+impl PushOutcomeEvent {
+    pub fn new(message: &NotificationMessage, status: NotificationStatus, attempt: u32) -> Self {
+        let now = Utc::now();
+        let started_at = message.send_at.or_else(|| message.created_at());
+
+        Self {
+            version: PUSH_OUTCOME_VERSION,
+            request_id: message.request_id.clone(),
+            notification_id: message.notification_id.clone(),
+            idempotency_key: message.idempotency_key.clone(),
+            user_id: message.user_id.clone(),
+            status,
+            provider_message_ids: Vec::new(),
+            error: None,
+            error_code: None,
+            attempt,
+            latency_ms: started_at.map(|started_at| (now - started_at).num_milliseconds().max(0)),
+            occurred_at: now.to_rfc3339_opts(SecondsFormat::Millis, true),
+        }
+    }
+
+    pub fn with_provider_message_ids(mut self, provider_message_ids: Vec<String>) -> Self {
+        self.provider_message_ids = provider_message_ids;
+        self
+    }
+
+    pub fn with_error(mut self, error: String) -> Self {
+        self.error = Some(error);
+        self
+    }
+
+    pub fn with_error_code(mut self, error_code: String) -> Self {
+        self.error_code = Some(error_code);
+        self
+    }
+
+    /// Routing key on the events exchange
+    pub fn pattern(&self) -> &'static str {
+        match self.status {
+            NotificationStatus::Sent => PUSH_DELIVERED,
+            NotificationStatus::Expired => PUSH_EXPIRED,
+            _ => PUSH_FAILED,
+        }
+    }
+}
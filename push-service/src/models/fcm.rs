@@ -1,7 +1,27 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    fmt::{Display, Formatter, Result as FmtResult},
+    time::Duration,
+};
 
 use serde::{Deserialize, Serialize};
 
+use crate::models::provider::DeliveryErrorKind;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FcmAuthMode {
+    /// Discover credentials the way gcp_auth does (env, gcloud, metadata server)
+    #[default]
+    ApplicationDefault,
+    /// Load a service-account JSON key from `FCM_SERVICE_ACCOUNT_PATH`
+    ServiceAccount,
+    /// Send `FCM_STATIC_TOKEN` as the bearer token
+    StaticToken,
+    /// Send requests without an Authorization header
+    None,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FcmRequest {
     pub message: FcmMessage,
@@ -9,20 +29,399 @@ pub struct FcmRequest {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FcmMessage {
-    pub token: String,
-    pub notification: FcmNotification,
+    #[serde(flatten)]
+    pub target: FcmTarget,
+
+    /// Left out for data-only messages so the app handles display itself
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notification: Option<FcmNotification>,
 
     #[serde(skip_serializing_if = "Option::is_none")]
     pub data: Option<HashMap<String, String>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub android: Option<AndroidConfig>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub apns: Option<ApnsConfig>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub webpush: Option<WebpushConfig>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fcm_options: Option<FcmOptions>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FcmTarget {
+    Token(String),
+    Topic(String),
+    Condition(String),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FcmNotification {
     pub title: String,
     pub body: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FcmOptions {
+    pub analytics_label: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum AndroidMessagePriority {
+    Normal,
+    High,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AndroidConfig {
+    pub priority: AndroidMessagePriority,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub collapse_key: Option<String>,
+
+    /// Duration string in seconds, e.g. `"3600s"`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notification: Option<AndroidNotification>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AndroidNotification {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub icon: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub color: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sound: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub click_action: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channel_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApnsConfig {
+    pub headers: HashMap<String, String>,
+    pub payload: ApnsConfigPayload,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApnsConfigPayload {
+    pub aps: FcmAps,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FcmAps {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub badge: Option<u32>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sound: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub category: Option<String>,
+
+    #[serde(rename = "mutable-content", skip_serializing_if = "Option::is_none")]
+    pub mutable_content: Option<u8>,
+
+    #[serde(rename = "content-available", skip_serializing_if = "Option::is_none")]
+    pub content_available: Option<u8>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebpushConfig {
+    pub headers: HashMap<String, String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fcm_options: Option<WebpushFcmOptions>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebpushFcmOptions {
+    pub link: String,
 }
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct FcmResponse {
     pub name: Option<String>,
 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TopicAction {
+    Subscribe,
+    Unsubscribe,
+}
+
+impl TopicAction {
+    /// IID batch method backing this action
+    pub fn method(&self) -> &str {
+        match self {
+            TopicAction::Subscribe => "batchAdd",
+            TopicAction::Unsubscribe => "batchRemove",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct IidBatchRequest {
+    pub to: String,
+    pub registration_tokens: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct IidBatchResponse {
+    #[serde(default)]
+    pub results: Vec<IidBatchResult>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct IidBatchResult {
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TopicSubscriptionRequest {
+    pub tokens: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TopicManagementResult {
+    pub success_count: usize,
+    pub failure_count: usize,
+    pub errors: Vec<TopicManagementError>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TopicManagementError {
+    pub index: usize,
+    pub token: String,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct FcmErrorResponse {
+    pub error: FcmErrorBody,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct FcmErrorBody {
+    #[serde(default)]
+    pub message: String,
+
+    #[serde(default)]
+    pub status: Option<String>,
+
+    #[serde(default)]
+    pub details: Vec<FcmErrorDetail>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FcmErrorDetail {
+    #[serde(rename = "@type")]
+    pub type_url: Option<String>,
+    pub error_code: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FcmError {
+    Unregistered {
+        message: String,
+    },
+    InvalidArgument {
+        message: String,
+    },
+    SenderIdMismatch {
+        message: String,
+    },
+    QuotaExceeded {
+        message: String,
+        retry_after: Option<Duration>,
+    },
+    Unavailable {
+        message: String,
+        retry_after: Option<Duration>,
+    },
+    Internal {
+        message: String,
+    },
+    ThirdPartyAuthError {
+        message: String,
+    },
+    /// Our own access token was rejected
+    Unauthenticated {
+        message: String,
+    },
+    /// Generic 403 without an FCM detail, e.g. a missing IAM role
+    PermissionDenied {
+        message: String,
+    },
+    /// Generic 404 without an FCM detail, e.g. a wrong project id
+    NotFound {
+        message: String,
+    },
+    Unknown {
+        status: u16,
+        message: String,
+    },
+}
+
+impl FcmError {
+    pub fn from_response(status: u16, body: &str) -> Self {
+        let response = serde_json::from_str::<FcmErrorResponse>(body).ok();
+
+        let message = response
+            .as_ref()
+            .map(|r| r.error.message.clone())
+            .unwrap_or_else(|| body.to_string());
+
+        // The FCM-specific code lives in the details array; the top-level
+        // status is the generic Google RPC code and only used as a fallback.
+        // Only the FCM codes say anything about the device token itself.
+        let error_code = response.as_ref().and_then(|r| {
+            r.error
+                .details
+                .iter()
+                .find(|d| {
+                    d.type_url
+                        .as_deref()
+                        .is_some_and(|t| t.ends_with("google.firebase.fcm.v1.FcmError"))
+                })
+                .and_then(|d| d.error_code.clone())
+                .or_else(|| r.error.status.clone())
+        });
+
+        match error_code.as_deref() {
+            Some("UNREGISTERED") => FcmError::Unregistered { message },
+            Some("NOT_FOUND") => FcmError::NotFound { message },
+            Some("INVALID_ARGUMENT") => FcmError::InvalidArgument { message },
+            Some("SENDER_ID_MISMATCH") => FcmError::SenderIdMismatch { message },
+            Some("PERMISSION_DENIED") => FcmError::PermissionDenied { message },
+            Some("QUOTA_EXCEEDED") | Some("RESOURCE_EXHAUSTED") => FcmError::QuotaExceeded {
+                message,
+                retry_after: None,
+            },
+            Some("UNAVAILABLE") => FcmError::Unavailable {
+                message,
+                retry_after: None,
+            },
+            Some("INTERNAL") => FcmError::Internal { message },
+            Some("THIRD_PARTY_AUTH_ERROR") => FcmError::ThirdPartyAuthError { message },
+            Some("UNAUTHENTICATED") => FcmError::Unauthenticated { message },
+            _ => match status {
+                400 => FcmError::InvalidArgument { message },
+                401 => FcmError::Unauthenticated { message },
+                403 => FcmError::PermissionDenied { message },
+                404 => FcmError::NotFound { message },
+                429 => FcmError::QuotaExceeded {
+                    message,
+                    retry_after: None,
+                },
+                500 => FcmError::Internal { message },
+                503 => FcmError::Unavailable {
+                    message,
+                    retry_after: None,
+                },
+                _ => FcmError::Unknown { status, message },
+            },
+        }
+    }
+
+    /// Attaches the server-supplied `Retry-After` delay to throttling errors.
+    pub fn with_retry_after(mut self, delay: Option<Duration>) -> Self {
+        if let FcmError::QuotaExceeded { retry_after, .. }
+        | FcmError::Unavailable { retry_after, .. } = &mut self
+        {
+            *retry_after = delay;
+        }
+        self
+    }
+
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            FcmError::QuotaExceeded { retry_after, .. }
+            | FcmError::Unavailable { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+
+    pub fn kind(&self) -> DeliveryErrorKind {
+        match self {
+            FcmError::Unregistered { .. } => DeliveryErrorKind::InvalidToken,
+            // A rejected access token is retried with a fresh one
+            FcmError::QuotaExceeded { .. }
+            | FcmError::Unavailable { .. }
+            | FcmError::Internal { .. }
+            | FcmError::Unauthenticated { .. } => DeliveryErrorKind::Retryable,
+            // The token is fine, our project or sender ID is wrong; pruning
+            // would wipe every Android device
+            FcmError::SenderIdMismatch { .. }
+            | FcmError::InvalidArgument { .. }
+            | FcmError::ThirdPartyAuthError { .. }
+            | FcmError::PermissionDenied { .. }
+            | FcmError::NotFound { .. } => DeliveryErrorKind::Permanent,
+            FcmError::Unknown { status, .. } if *status >= 500 => DeliveryErrorKind::Retryable,
+            FcmError::Unknown { .. } => DeliveryErrorKind::Permanent,
+        }
+    }
+
+    pub fn code(&self) -> &str {
+        match self {
+            FcmError::Unregistered { .. } => "UNREGISTERED",
+            FcmError::InvalidArgument { .. } => "INVALID_ARGUMENT",
+            FcmError::SenderIdMismatch { .. } => "SENDER_ID_MISMATCH",
+            FcmError::QuotaExceeded { .. } => "QUOTA_EXCEEDED",
+            FcmError::Unavailable { .. } => "UNAVAILABLE",
+            FcmError::Internal { .. } => "INTERNAL",
+            FcmError::ThirdPartyAuthError { .. } => "THIRD_PARTY_AUTH_ERROR",
+            FcmError::Unauthenticated { .. } => "UNAUTHENTICATED",
+            FcmError::PermissionDenied { .. } => "PERMISSION_DENIED",
+            FcmError::NotFound { .. } => "NOT_FOUND",
+            FcmError::Unknown { .. } => "UNSPECIFIED_ERROR",
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            FcmError::Unregistered { message }
+            | FcmError::InvalidArgument { message }
+            | FcmError::SenderIdMismatch { message }
+            | FcmError::QuotaExceeded { message, .. }
+            | FcmError::Unavailable { message, .. }
+            | FcmError::Internal { message }
+            | FcmError::ThirdPartyAuthError { message }
+            | FcmError::Unauthenticated { message }
+            | FcmError::PermissionDenied { message }
+            | FcmError::NotFound { message }
+            | FcmError::Unknown { message, .. } => message,
+        }
+    }
+}
+
+impl Display for FcmError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(
+            f,
+            "FCM request failed: {} ({})",
+            self.code(),
+            self.message()
+        )
+    }
+}
+
+impl std::error::Error for FcmError {}
@@ -28,6 +28,25 @@ pub struct ServiceHealth {
 
     #[serde(skip_serializing_if = "Option::is_none")]
     pub error: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connection: Option<BrokerConnectionState>,
+}
+
+/// Where the RabbitMQ connection manager is at.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum BrokerConnectionState {
+    Connected {
+        since: String,
+    },
+    /// The connection was lost and is being re-established
+    Reconnecting {
+        since: String,
+        attempt: u32,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        last_error: Option<String>,
+    },
 }
 
 impl ServiceHealth {
@@ -37,6 +56,7 @@ impl ServiceHealth {
             response_time_ms: Some(response_time_ms),
             circuit_breaker: None,
             error: None,
+            connection: None,
         }
     }
 
@@ -46,6 +66,17 @@ impl ServiceHealth {
             response_time_ms: None,
             circuit_breaker: None,
             error: Some(error),
+            connection: None,
+        }
+    }
+
+    pub fn degraded(error: String) -> Self {
+        Self {
+            status: HealthStatus::Degraded,
+            response_time_ms: None,
+            circuit_breaker: None,
+            error: Some(error),
+            connection: None,
         }
     }
 
@@ -54,12 +85,18 @@ impl ServiceHealth {
         self
     }
 
+    pub fn with_connection(mut self, state: BrokerConnectionState) -> Self {
+        self.connection = Some(state);
+        self
+    }
+
     pub fn degraded_circuit_open(circuit_state: String) -> Self {
         Self {
             status: HealthStatus::Degraded,
             response_time_ms: None,
             circuit_breaker: Some(circuit_state),
             error: None,
+            connection: None,
         }
     }
 }
@@ -1,5 +1,6 @@
 use std::collections::HashMap;
 
+use chrono::{DateTime, Duration, Utc};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -15,6 +16,49 @@ pub struct NotificationMessage {
     pub metadata: HashMap<String, serde_json::Value>,
     pub created_by: String,
     pub timestamp: String,
+
+    /// Hold the push back until this time instead of sending it right away
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub send_at: Option<DateTime<Utc>>,
+
+    /// Drop the push instead of sending it after this time
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+
+    /// How long the push stays worth sending once it is due
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl_seconds: Option<u32>,
+}
+
+impl NotificationMessage {
+    /// When the producer created the message, if `timestamp` can be parsed
+    pub fn created_at(&self) -> Option<DateTime<Utc>> {
+        DateTime::parse_from_rfc3339(&self.timestamp)
+            .ok()
+            .map(|timestamp| timestamp.with_timezone(&Utc))
+    }
+
+    /// When the message stops being worth sending. An explicit `expires_at`
+    /// wins; otherwise `ttl_seconds`, or `default_ttl` when the producer
+    /// gave none, is counted from the moment the message became due.
+    pub fn expiry(&self, default_ttl: Duration) -> Option<DateTime<Utc>> {
+        if let Some(expires_at) = self.expires_at {
+            return Some(expires_at);
+        }
+
+        let due = self.send_at.or_else(|| self.created_at())?;
+        let ttl = self
+            .ttl_seconds
+            .map(|seconds| Duration::seconds(seconds.into()))
+            .unwrap_or(default_ttl);
+
+        Some(due + ttl)
+    }
+
+    /// Whether the producer chose the expiry rather than the service default
+    pub fn has_explicit_expiry(&self) -> bool {
+        self.expires_at.is_some() || self.ttl_seconds.is_some()
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -28,4 +72,4 @@ pub struct DlqMessage {
 pub struct Envelope {
     pub pattern: String,
     pub data: NotificationMessage,
-}
\ No newline at end of file
+}
@@ -1,10 +1,18 @@
+pub mod apns;
 pub mod audit;
 pub mod circuit_breaker;
+pub mod device;
+pub mod dlq;
+pub mod event;
 pub mod fcm;
 pub mod health;
 pub mod message;
+pub mod provider;
 pub mod response;
 pub mod retry;
+pub mod schedule;
 pub mod status;
 pub mod template;
+pub mod user;
 pub mod validation;
+pub mod webpush;
@@ -0,0 +1,141 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::models::webpush::WebPushSubscription;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PushNotification {
+    pub target: PushTarget,
+    pub title: String,
+    pub body: String,
+    pub trace_id: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<HashMap<String, String>>,
+
+    #[serde(default)]
+    pub priority: PushPriority,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub collapse_key: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+
+    #[serde(default)]
+    pub options: PushOptions,
+}
+
+/// Presentation hints shared across providers. Each provider maps what it
+/// supports onto its own payload and ignores the rest.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PushOptions {
+    /// Send only `data` and let the app decide how to present it
+    pub data_only: bool,
+
+    /// Ask iOS to run the notification service extension before display
+    pub mutable_content: bool,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub icon: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub color: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sound: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub badge: Option<u32>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub category: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channel_id: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub click_action: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub link: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub analytics_label: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PushTarget {
+    Token(String),
+    WebPush(WebPushSubscription),
+    Topic(String),
+    Condition(String),
+}
+
+impl PushTarget {
+    pub fn identifier(&self) -> &str {
+        match self {
+            PushTarget::Token(token) => token,
+            PushTarget::WebPush(subscription) => &subscription.endpoint,
+            PushTarget::Topic(topic) => topic,
+            PushTarget::Condition(condition) => condition,
+        }
+    }
+
+    pub fn kind(&self) -> &str {
+        match self {
+            PushTarget::Token(_) => "token",
+            PushTarget::WebPush(_) => "web_push",
+            PushTarget::Topic(_) => "topic",
+            PushTarget::Condition(_) => "condition",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PushPriority {
+    #[default]
+    High,
+    Normal,
+}
+
+impl PushPriority {
+    pub fn from_string(s: &str) -> Self {
+        match s {
+            "normal" => PushPriority::Normal,
+            _ => PushPriority::High,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SendReceipt {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryErrorKind {
+    Retryable,
+    Permanent,
+    InvalidToken,
+}
+
+impl DeliveryErrorKind {
+    pub fn as_str(&self) -> &str {
+        match self {
+            DeliveryErrorKind::Retryable => "retryable",
+            DeliveryErrorKind::Permanent => "permanent",
+            DeliveryErrorKind::InvalidToken => "invalid_token",
+        }
+    }
+}
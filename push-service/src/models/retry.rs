@@ -5,3 +5,10 @@ pub struct RetryConfig {
     pub max_delay_ms: u64,
     pub backoff_multiplier: u64,
 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RetryDecision {
+    Retry,
+    RetryAfter(std::time::Duration),
+    Abort,
+}
@@ -0,0 +1,77 @@
+use anyhow::{Error, Result, anyhow};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use uuid::Uuid;
+
+/// A message held back in `scheduled_notifications` until `deliver_at`,
+/// when the scheduler puts the original payload back on the push queue.
+#[derive(Debug, Clone, Serialize)]
+pub struct ScheduledNotification {
+    pub id: Uuid,
+    pub idempotency_key: String,
+    pub trace_id: String,
+    pub user_id: Uuid,
+    pub reason: ScheduleReason,
+    pub payload: JsonValue,
+    pub deliver_at: DateTime<Utc>,
+    pub status: ScheduleStatus,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScheduleReason {
+    QuietHours,
+    /// The producer asked for a later delivery through `send_at`
+    SendAt,
+}
+
+impl ScheduleReason {
+    pub fn as_str(&self) -> &str {
+        match self {
+            ScheduleReason::QuietHours => "quiet_hours",
+            ScheduleReason::SendAt => "send_at",
+        }
+    }
+
+    pub fn from_string(s: &str) -> Result<Self, Error> {
+        match s {
+            "quiet_hours" => Ok(ScheduleReason::QuietHours),
+            "send_at" => Ok(ScheduleReason::SendAt),
+            other => Err(anyhow!("Unknown schedule reason: {}", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScheduleStatus {
+    Pending,
+    /// Claimed by a scheduler that has not confirmed the publish yet
+    Dispatching,
+    Dispatched,
+    Cancelled,
+}
+
+impl ScheduleStatus {
+    pub fn as_str(&self) -> &str {
+        match self {
+            ScheduleStatus::Pending => "pending",
+            ScheduleStatus::Dispatching => "dispatching",
+            ScheduleStatus::Dispatched => "dispatched",
+            ScheduleStatus::Cancelled => "cancelled",
+        }
+    }
+
+    pub fn from_string(s: &str) -> Result<Self, Error> {
+        match s {
+            "pending" => Ok(ScheduleStatus::Pending),
+            "dispatching" => Ok(ScheduleStatus::Dispatching),
+            "dispatched" => Ok(ScheduleStatus::Dispatched),
+            "cancelled" => Ok(ScheduleStatus::Cancelled),
+            other => Err(anyhow!("Unknown schedule status: {}", other)),
+        }
+    }
+}
@@ -8,6 +8,14 @@ pub enum NotificationStatus {
     Queued,
     Processing,
     Sent,
+    #[serde(rename = "partially_sent")]
+    PartiallySent,
+    /// Not sent because the user opted out
+    Suppressed,
+    /// Held back until the user's quiet hours end
+    Deferred,
+    /// Dropped because it was too old to be useful
+    Expired,
     Failed,
     Dlq,
 }
@@ -17,6 +25,8 @@ pub enum IdempotencyStatus {
     NotFound,
     Processing,
     Sent,
+    Suppressed,
+    Expired,
     Failed,
 }
 
@@ -26,6 +36,10 @@ impl Display for NotificationStatus {
             NotificationStatus::Queued => write!(f, "queued"),
             NotificationStatus::Processing => write!(f, "processing"),
             NotificationStatus::Sent => write!(f, "sent"),
+            NotificationStatus::PartiallySent => write!(f, "partially_sent"),
+            NotificationStatus::Suppressed => write!(f, "suppressed"),
+            NotificationStatus::Deferred => write!(f, "deferred"),
+            NotificationStatus::Expired => write!(f, "expired"),
             NotificationStatus::Failed => write!(f, "failed"),
             NotificationStatus::Dlq => write!(f, "dlq"),
         }
@@ -18,4 +18,10 @@ pub struct Template {
 pub struct TemplateContent {
     pub title: String,
     pub body: String,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub link: Option<String>,
 }
@@ -0,0 +1,158 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Duration, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+
+/// The parts of a user-service `User` the push pipeline cares about.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserProfile {
+    pub id: String,
+    pub push_token: Option<String>,
+
+    #[serde(default)]
+    pub preferences: UserPreferences,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UserPreferences {
+    pub email: bool,
+    pub push: bool,
+
+    /// Opt-outs keyed by `notification_type`; a missing entry means allowed
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub notification_types: HashMap<String, bool>,
+
+    /// IANA zone name, e.g. `Europe/Berlin`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quiet_hours: Option<QuietHours>,
+}
+
+impl Default for UserPreferences {
+    fn default() -> Self {
+        Self {
+            email: true,
+            push: true,
+            notification_types: HashMap::new(),
+            timezone: None,
+            quiet_hours: None,
+        }
+    }
+}
+
+impl UserPreferences {
+    /// Why a push of this type must not be sent, if the user opted out
+    pub fn push_blocked(&self, notification_type: &str) -> Option<SuppressionReason> {
+        if !self.push {
+            return Some(SuppressionReason::PushDisabled);
+        }
+
+        match self.notification_types.get(notification_type) {
+            Some(false) => Some(SuppressionReason::NotificationTypeDisabled),
+            _ => None,
+        }
+    }
+
+    /// The zone the quiet window is expressed in, if the user set one
+    pub fn quiet_hours_timezone(&self) -> Option<&str> {
+        self.quiet_hours
+            .as_ref()
+            .and_then(|quiet_hours| quiet_hours.timezone.as_deref())
+            .or(self.timezone.as_deref())
+    }
+}
+
+/// A daily do-not-disturb window in the user's local time. An `end` earlier
+/// than `start` means the window runs past midnight.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuietHours {
+    #[serde(with = "clock_time")]
+    pub start: NaiveTime,
+
+    #[serde(with = "clock_time")]
+    pub end: NaiveTime,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+}
+
+impl QuietHours {
+    /// When the window `now` falls into ends, or `None` outside the window
+    pub fn ends_after(&self, now: DateTime<Utc>, timezone: Tz) -> Option<DateTime<Utc>> {
+        if self.start == self.end {
+            return None;
+        }
+
+        let local = now.with_timezone(&timezone);
+        let time = local.time();
+        let today = local.date_naive();
+
+        let end_date = if self.start < self.end {
+            if time < self.start || time >= self.end {
+                return None;
+            }
+            today
+        } else if time >= self.start {
+            today.succ_opt()?
+        } else if time < self.end {
+            today
+        } else {
+            return None;
+        };
+
+        let end = end_date.and_time(self.end);
+
+        // A window ending inside a DST gap ends once the clocks have jumped
+        let end = timezone.from_local_datetime(&end).earliest().or_else(|| {
+            timezone
+                .from_local_datetime(&(end + Duration::hours(1)))
+                .earliest()
+        })?;
+
+        Some(end.with_timezone(&Utc))
+    }
+}
+
+/// Serializes times as the `HH:MM` strings user-service stores.
+mod clock_time {
+    use chrono::NaiveTime;
+    use serde::{Deserialize, Deserializer, Serializer, de::Error};
+
+    const FORMAT: &str = "%H:%M";
+
+    pub fn serialize<S: Serializer>(time: &NaiveTime, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&time.format(FORMAT).to_string())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<NaiveTime, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        NaiveTime::parse_from_str(&value, FORMAT).map_err(D::Error::custom)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SuppressionReason {
+    PushDisabled,
+    NotificationTypeDisabled,
+}
+
+impl SuppressionReason {
+    pub fn as_str(&self) -> &str {
+        match self {
+            SuppressionReason::PushDisabled => "push_disabled",
+            SuppressionReason::NotificationTypeDisabled => "notification_type_disabled",
+        }
+    }
+}
+
+/// Envelope every user-service endpoint wraps its payload in.
+#[derive(Debug, Deserialize)]
+pub struct UserServiceResponse<T> {
+    pub success: bool,
+    pub data: Option<T>,
+    pub message: String,
+}
@@ -23,3 +23,74 @@ pub fn validate_fcm_token(token: &str) -> Result<()> {
 
     Ok(())
 }
+
+/// APNs device tokens are 32 bytes, hex encoded
+pub fn validate_apns_token(token: &str) -> Result<()> {
+    if token.len() != 64 {
+        return Err(anyhow!("APNs device token must be 64 hex characters"));
+    }
+
+    if !token.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(anyhow!("APNs device token contains invalid characters"));
+    }
+
+    Ok(())
+}
+
+pub fn validate_fcm_topic(topic: &str) -> Result<()> {
+    if topic.is_empty() {
+        return Err(anyhow!("Topic name cannot be empty"));
+    }
+
+    if topic.len() > 900 {
+        return Err(anyhow!("Topic name too long (maximum 900 characters)"));
+    }
+
+    let valid_chars = topic
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '~' | '%'));
+
+    if !valid_chars {
+        return Err(anyhow!("Topic name contains invalid characters"));
+    }
+
+    Ok(())
+}
+
+pub fn validate_fcm_condition(condition: &str) -> Result<()> {
+    if condition.trim().is_empty() {
+        return Err(anyhow!("Condition cannot be empty"));
+    }
+
+    if !condition.matches('\'').count().is_multiple_of(2) {
+        return Err(anyhow!("Condition contains an unterminated topic name"));
+    }
+
+    // Quoted segments are topic names, everything between them is operators
+    let topics: Vec<&str> = condition.split('\'').skip(1).step_by(2).collect();
+
+    if topics.is_empty() {
+        return Err(anyhow!("Condition must reference at least one topic"));
+    }
+
+    if topics.len() > 5 {
+        return Err(anyhow!("Condition references too many topics (maximum 5)"));
+    }
+
+    for topic in topics {
+        validate_fcm_topic(topic)?;
+    }
+
+    let valid_operators = condition.split('\'').step_by(2).all(|operators| {
+        operators
+            .replace("in topics", "")
+            .chars()
+            .all(|c| c.is_whitespace() || matches!(c, '&' | '|' | '!' | '(' | ')'))
+    });
+
+    if !valid_operators {
+        return Err(anyhow!("Condition contains invalid operators"));
+    }
+
+    Ok(())
+}
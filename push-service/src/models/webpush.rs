@@ -0,0 +1,153 @@
+use std::{
+    collections::HashMap,
+    fmt::{Display, Formatter, Result as FmtResult},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::models::provider::DeliveryErrorKind;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WebPushSubscription {
+    pub endpoint: String,
+    pub keys: WebPushKeys,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WebPushKeys {
+    pub p256dh: String,
+    pub auth: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebPushPayload {
+    pub notification: WebPushNotification,
+    pub data: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebPushNotification {
+    pub title: String,
+    pub body: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VapidClaims {
+    pub aud: String,
+    pub exp: i64,
+    pub sub: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WebPushError {
+    SubscriptionExpired {
+        status: u16,
+    },
+    PayloadTooLarge,
+    /// The subscription keys could not be used to encrypt the payload
+    InvalidSubscription {
+        reason: String,
+    },
+    TooManyRequests {
+        retry_after: Option<Duration>,
+    },
+    ServiceUnavailable {
+        status: u16,
+        reason: String,
+        retry_after: Option<Duration>,
+    },
+    Rejected {
+        status: u16,
+        reason: String,
+    },
+}
+
+impl WebPushError {
+    pub fn from_response(status: u16, body: &str) -> Self {
+        match status {
+            404 | 410 => WebPushError::SubscriptionExpired { status },
+            413 => WebPushError::PayloadTooLarge,
+            429 => WebPushError::TooManyRequests { retry_after: None },
+            _ if status >= 500 => WebPushError::ServiceUnavailable {
+                status,
+                reason: body.to_string(),
+                retry_after: None,
+            },
+            _ => WebPushError::Rejected {
+                status,
+                reason: body.to_string(),
+            },
+        }
+    }
+
+    /// Attaches the server-supplied `Retry-After` delay to throttling errors.
+    pub fn with_retry_after(mut self, delay: Option<Duration>) -> Self {
+        if let WebPushError::TooManyRequests { retry_after }
+        | WebPushError::ServiceUnavailable { retry_after, .. } = &mut self
+        {
+            *retry_after = delay;
+        }
+        self
+    }
+
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            WebPushError::TooManyRequests { retry_after }
+            | WebPushError::ServiceUnavailable { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+
+    pub fn kind(&self) -> DeliveryErrorKind {
+        match self {
+            WebPushError::SubscriptionExpired { .. } => DeliveryErrorKind::InvalidToken,
+            WebPushError::TooManyRequests { .. } | WebPushError::ServiceUnavailable { .. } => {
+                DeliveryErrorKind::Retryable
+            }
+            // Found before anything was sent; only the push service's word
+            // prunes a subscription
+            WebPushError::PayloadTooLarge
+            | WebPushError::InvalidSubscription { .. }
+            | WebPushError::Rejected { .. } => DeliveryErrorKind::Permanent,
+        }
+    }
+
+    pub fn code(&self) -> &str {
+        match self {
+            WebPushError::SubscriptionExpired { .. } => "SUBSCRIPTION_EXPIRED",
+            WebPushError::PayloadTooLarge => "PAYLOAD_TOO_LARGE",
+            WebPushError::InvalidSubscription { .. } => "INVALID_SUBSCRIPTION",
+            WebPushError::TooManyRequests { .. } => "TOO_MANY_REQUESTS",
+            WebPushError::ServiceUnavailable { .. } => "SERVICE_UNAVAILABLE",
+            WebPushError::Rejected { .. } => "REJECTED",
+        }
+    }
+}
+
+impl Display for WebPushError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            WebPushError::SubscriptionExpired { status } => {
+                write!(f, "Web push subscription expired (status {})", status)
+            }
+            WebPushError::PayloadTooLarge => write!(f, "Web push payload too large"),
+            WebPushError::InvalidSubscription { reason } => {
+                write!(f, "Web push subscription unusable: {}", reason)
+            }
+            WebPushError::TooManyRequests { .. } => {
+                write!(f, "Web push service rate limited request")
+            }
+            WebPushError::ServiceUnavailable { status, reason, .. }
+            | WebPushError::Rejected { status, reason } => {
+                write!(
+                    f,
+                    "Web push request failed with status {}: {}",
+                    status, reason
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for WebPushError {}
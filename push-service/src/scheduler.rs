@@ -0,0 +1,104 @@
+use std::{future::Future, sync::Arc, time::Duration};
+
+use anyhow::{Error, Result};
+use tokio::time::{MissedTickBehavior, interval};
+use tracing::{debug, error, info, warn};
+
+use crate::{
+    clients::{database::DatabaseClient, rbmq::RabbitMqClient},
+    config::Config,
+};
+
+/// Polls `scheduled_notifications` and puts every message whose time has
+/// come back on the push queue. Safe to run on several replicas at once.
+/// Returns once `shutdown` resolves, never in the middle of a batch.
+pub async fn run_scheduler(
+    config: Config,
+    database_client: Arc<DatabaseClient>,
+    rabbitmq_client: Arc<RabbitMqClient>,
+    shutdown: impl Future<Output = ()>,
+) {
+    let mut ticker = interval(Duration::from_millis(config.scheduler_poll_interval_ms));
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    info!(
+        poll_interval_ms = config.scheduler_poll_interval_ms,
+        batch_size = config.scheduler_batch_size,
+        "Scheduler started"
+    );
+
+    tokio::pin!(shutdown);
+
+    loop {
+        tokio::select! {
+            _ = &mut shutdown => break,
+            _ = ticker.tick() => {}
+        }
+
+        if let Err(e) = dispatch_due(
+            &database_client,
+            &rabbitmq_client,
+            config.scheduler_batch_size,
+            Duration::from_secs(config.scheduler_claim_lease_seconds),
+        )
+        .await
+        {
+            error!(error = %e, "Scheduler poll failed");
+        }
+    }
+
+    info!("Scheduler stopped");
+}
+
+/// Republishes one batch of due messages and returns how many went out.
+/// Messages that could not be published are handed back for the next poll,
+/// and a claim left unconfirmed by a crash is picked up again once `lease`
+/// runs out.
+pub async fn dispatch_due(
+    database_client: &DatabaseClient,
+    rabbitmq_client: &RabbitMqClient,
+    batch_size: i64,
+    lease: Duration,
+) -> Result<usize, Error> {
+    let schedule = database_client.scheduled_notifications();
+    let due = schedule.claim_due(batch_size, lease).await?;
+
+    let mut dispatched = 0;
+
+    for scheduled in due {
+        let published = match serde_json::to_vec(&scheduled.payload) {
+            Ok(payload) => rabbitmq_client.publish_to_push_queue(&payload).await,
+            Err(e) => Err(e.into()),
+        };
+
+        match published {
+            Ok(()) => {
+                // The message is already queued; if this fails the lease runs
+                // out and the copy sent again is caught by idempotency
+                if let Err(e) = schedule.mark_dispatched(scheduled.id).await {
+                    error!(id = %scheduled.id, error = %e, "Failed to mark scheduled notification as dispatched");
+                }
+
+                dispatched += 1;
+                debug!(
+                    id = %scheduled.id,
+                    idempotency_key = %scheduled.idempotency_key,
+                    "Scheduled notification dispatched"
+                );
+            }
+            Err(e) => {
+                warn!(id = %scheduled.id, error = %e, "Failed to dispatch scheduled notification");
+
+                if let Err(e) = schedule.release(scheduled.id).await {
+                    error!(id = %scheduled.id, error = %e, "Failed to release scheduled notification");
+                }
+            }
+        }
+    }
+
+    if dispatched > 0 {
+        info!(dispatched, "Scheduled notifications dispatched");
+    }
+
+    Ok(dispatched)
+}
@@ -0,0 +1,164 @@
+use std::{collections::HashMap, future::Future, time::Duration};
+
+use lapin::acker::Acker;
+use tokio::{
+    signal::unix::{SignalKind, signal},
+    task::{Id, JoinSet},
+    time::timeout,
+};
+use tracing::{error, info, warn};
+
+use crate::clients::{rbmq::RabbitMqClient, redis::RedisClient};
+
+/// Resolves on the first SIGTERM or SIGINT.
+pub async fn wait_for_signal() {
+    let mut terminate = match signal(SignalKind::terminate()) {
+        Ok(terminate) => terminate,
+        Err(e) => {
+            error!(error = %e, "Failed to install SIGTERM handler");
+            let _ = tokio::signal::ctrl_c().await;
+            return;
+        }
+    };
+
+    tokio::select! {
+        _ = terminate.recv() => info!("Received SIGTERM"),
+        _ = tokio::signal::ctrl_c() => info!("Received SIGINT"),
+    }
+}
+
+/// A delivery whose processing task has not finished yet
+struct Tracked {
+    acker: Acker,
+    idempotency_key: Option<String>,
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct DrainReport {
+    pub completed: usize,
+    pub requeued: usize,
+}
+
+/// The processing tasks spawned for deliveries, so a shutdown can wait for
+/// them and hand back to the broker whatever does not finish in time.
+#[derive(Default)]
+pub struct InFlight {
+    tasks: JoinSet<()>,
+    deliveries: HashMap<Id, Tracked>,
+}
+
+impl InFlight {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.deliveries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.deliveries.is_empty()
+    }
+
+    /// Runs `task` for a delivery. The task is expected to settle the
+    /// delivery itself; `acker` is only used if it gets cut short.
+    pub fn spawn<F>(&mut self, acker: Acker, idempotency_key: Option<String>, task: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let handle = self.tasks.spawn(task);
+
+        self.deliveries.insert(
+            handle.id(),
+            Tracked {
+                acker,
+                idempotency_key,
+            },
+        );
+    }
+
+    /// Waits for the next task to finish and forgets its delivery. Returns
+    /// `None` when nothing is in flight.
+    pub async fn reap(&mut self) -> Option<()> {
+        let id = match self.tasks.join_next_with_id().await? {
+            Ok((id, ())) => id,
+            Err(e) => {
+                if e.is_panic() {
+                    error!(error = %e, "Message processing task panicked");
+                }
+                e.id()
+            }
+        };
+
+        self.deliveries.remove(&id);
+
+        Some(())
+    }
+
+    /// Gives the running tasks up to `grace_period` to finish. The rest are
+    /// aborted and their deliveries requeued, after releasing the
+    /// idempotency keys they left in `processing` so the redelivery is not
+    /// mistaken for a duplicate.
+    pub async fn drain(
+        mut self,
+        grace_period: Duration,
+        rabbitmq_client: &RabbitMqClient,
+        redis_client: &RedisClient,
+    ) -> DrainReport {
+        let mut report = DrainReport::default();
+
+        info!(
+            in_flight = self.len(),
+            grace_period_ms = grace_period.as_millis() as u64,
+            "Waiting for in-flight messages"
+        );
+
+        let _ = timeout(grace_period, async {
+            while self.reap().await.is_some() {
+                report.completed += 1;
+            }
+        })
+        .await;
+
+        if self.is_empty() {
+            return report;
+        }
+
+        warn!(
+            remaining = self.len(),
+            "Grace period elapsed, requeueing unfinished messages"
+        );
+
+        self.tasks.abort_all();
+
+        while let Some(result) = self.tasks.join_next_with_id().await {
+            let (id, cancelled) = match result {
+                Ok((id, ())) => (id, false),
+                Err(e) => (e.id(), e.is_cancelled()),
+            };
+
+            let Some(tracked) = self.deliveries.remove(&id) else {
+                continue;
+            };
+
+            if !cancelled {
+                report.completed += 1;
+                continue;
+            }
+
+            if let Some(idempotency_key) = &tracked.idempotency_key
+                && let Err(e) = redis_client.release_unfinished(idempotency_key).await
+            {
+                error!(idempotency_key, error = %e, "Failed to release idempotency keys");
+            }
+
+            if let Err(e) = rabbitmq_client.reject(&tracked.acker, true).await {
+                error!(error = %e, "Failed to requeue message");
+            }
+
+            report.requeued += 1;
+        }
+
+        report
+    }
+}
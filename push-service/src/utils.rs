@@ -1,31 +1,55 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+};
+
 use anyhow::{Error, Result, anyhow};
+use chrono::{DateTime, SecondsFormat, Utc};
+use chrono_tz::Tz;
+use futures_util::future::join_all;
 use tokio::time::{Duration, sleep};
 use tracing::{debug, info, warn};
+use uuid::Uuid;
 
 use crate::{
     clients::{
-        database::DatabaseClient, fcm::FcmClient, redis::RedisClient,
+        database::DatabaseClient,
+        events::{EventPublisher, publish_event},
+        provider::ProviderRegistry,
+        redis::{RedisClient, device_idempotency_key},
         template::TemplateServiceClient,
+        user::UserServiceClient,
     },
     config::Config,
     models::{
         audit::CreateAuditLog,
-        message::{Envelope},
-        retry::RetryConfig,
+        device::{
+            DeliverySummary, DeviceDeliveryStatus, DeviceOutcome, DeviceTarget, MetadataDevice,
+        },
+        event::{TOKEN_INVALIDATED, TokenInvalidatedEvent},
+        message::{Envelope, NotificationMessage},
+        provider::{DeliveryErrorKind, PushNotification, PushOptions, PushPriority, PushTarget},
+        retry::{RetryConfig, RetryDecision},
+        schedule::ScheduleReason,
         status::{IdempotencyStatus, NotificationStatus},
-        validation::validate_fcm_token,
+        user::{SuppressionReason, UserProfile},
     },
 };
 
+#[allow(clippy::too_many_arguments)]
 pub async fn process_message(
     payload: &str,
-    redis_client: &mut RedisClient,
-    template_service_client: &mut TemplateServiceClient,
-    fcm_client: &mut FcmClient,
+    redis_client: &RedisClient,
+    template_service_client: &TemplateServiceClient,
+    user_service_client: &UserServiceClient,
+    providers: &ProviderRegistry,
     database_client: &DatabaseClient,
-) -> Result<(), Error> {
+    events: &dyn EventPublisher,
+    config: &Config,
+) -> Result<ProcessingOutcome, Error> {
     info!("Raw payload: {}", payload);
-    let enveloped = serde_json::from_str::<Envelope>(payload)?;
+    let enveloped = serde_json::from_str::<Envelope>(payload)
+        .map_err(|e| ProcessingError::permanent(format!("Invalid message payload: {}", e)))?;
     let message = enveloped.data;
 
     info!(
@@ -44,14 +68,28 @@ pub async fn process_message(
                 idempotency_key = %message.idempotency_key,
                 "Message already processed, skipping"
             );
-            return Ok(());
+            return Ok(ProcessingOutcome::Settled);
         }
         Ok(IdempotencyStatus::Processing) => {
             info!(
                 idempotency_key = %message.idempotency_key,
                 "Message is being processed elsewhere, skipping"
             );
-            return Ok(());
+            return Ok(ProcessingOutcome::Settled);
+        }
+        Ok(IdempotencyStatus::Suppressed) => {
+            info!(
+                idempotency_key = %message.idempotency_key,
+                "Message was suppressed by user preferences, skipping"
+            );
+            return Ok(ProcessingOutcome::Settled);
+        }
+        Ok(IdempotencyStatus::Expired) => {
+            info!(
+                idempotency_key = %message.idempotency_key,
+                "Message already expired, skipping"
+            );
+            return Ok(ProcessingOutcome::Settled);
         }
         _ => {}
     }
@@ -60,34 +98,126 @@ pub async fn process_message(
         .mark_as_processing(&message.idempotency_key)
         .await?;
 
-    let device_token = message
-        .metadata
-        .get("push_token")
-        .and_then(|v| v.as_str())
-        .ok_or_else(|| anyhow!("Missing push_token in metadata"))?;
+    let context = DeliveryContext {
+        message: &message,
+        redis_client,
+        providers,
+        database_client,
+        user_service_client,
+        events,
+    };
 
-    if let Err(e) = validate_fcm_token(device_token) {
-        redis_client
-            .mark_as_failed(&message.idempotency_key)
-            .await?;
+    let expires_at = message.expiry(config.default_message_ttl());
 
-        let audit_log = CreateAuditLog::new(
-            message.request_id.clone(),
-            message.user_id.clone(),
-            message.notification_type.clone(),
-            message.template_code.clone(),
-            NotificationStatus::Failed,
-        )
-        .with_error(format!("Invalid device token: {}", e))
-        .with_metadata(serde_json::to_value(message.metadata.clone())?);
+    if expires_at.is_none() {
+        warn!(
+            timestamp = %message.timestamp,
+            "Unparseable message timestamp, message will not expire"
+        );
+    }
 
-        if let Err(log_err) = database_client.log_notification(audit_log).await {
-            warn!(error = %log_err, "Failed to write audit log");
+    if let Some(expires_at) = expires_at
+        && expires_at <= Utc::now()
+    {
+        return context.expire_message(expires_at).await;
+    }
+
+    if let Some(send_at) = message.send_at
+        && send_at > Utc::now()
+    {
+        return context
+            .defer_message(payload, ScheduleReason::SendAt, send_at)
+            .await;
+    }
+
+    let user = UserLookup::fetch(&message, user_service_client).await;
+
+    if let UserLookup::Found(profile) = &user
+        && let Some(reason) = profile.preferences.push_blocked(&message.notification_type)
+    {
+        return context.suppress_message(reason).await;
+    }
+
+    // Urgent messages go out even while the user does not want to be disturbed
+    if message.priority <= config.quiet_hours_bypass_priority
+        && let UserLookup::Found(profile) = &user
+        && let Some(quiet_hours) = &profile.preferences.quiet_hours
+    {
+        let timezone = user_timezone(&message, profile, database_client).await;
+
+        if let Some(deliver_at) = quiet_hours.ends_after(Utc::now(), timezone) {
+            // An expiry the producer chose wins over waiting for the morning
+            if message.has_explicit_expiry()
+                && let Some(expires_at) = expires_at
+                && expires_at <= deliver_at
+            {
+                return context.expire_message(expires_at).await;
+            }
+
+            return context
+                .defer_message(payload, ScheduleReason::QuietHours, deliver_at)
+                .await;
+        }
+    }
+
+    let devices = match resolve_devices(&message) {
+        Ok(devices) if devices.is_empty() => {
+            match current_devices(&message, &user, database_client).await {
+                Ok(devices) => devices,
+                Err(e) => {
+                    return context
+                        .fail_message(ProcessingError::retryable(format!(
+                            "Device lookup failed: {}",
+                            e
+                        )))
+                        .await;
+                }
+            }
         }
+        Ok(devices) => devices,
+        Err(e) => {
+            return context
+                .fail_message(ProcessingError::permanent(format!(
+                    "Device resolution failed: {}",
+                    e
+                )))
+                .await;
+        }
+    };
 
-        return Err(anyhow!("Invalid device token: {}", e));
+    if devices.is_empty() {
+        return context
+            .fail_message(ProcessingError::permanent(
+                "No push targets in metadata, user-service or registry".to_string(),
+            ))
+            .await;
     }
 
+    let devices = without_invalidated(devices, database_client).await;
+
+    if devices.is_empty() {
+        return context
+            .fail_message(ProcessingError::permanent(
+                "All push targets have been invalidated".to_string(),
+            ))
+            .await;
+    }
+
+    let mut options = match message.metadata.get("push_options") {
+        Some(options) => match serde_json::from_value::<PushOptions>(options.clone()) {
+            Ok(options) => options,
+            Err(e) => {
+                return context
+                    .fail_message(ProcessingError::permanent(format!(
+                        "Invalid push_options in metadata: {}",
+                        e
+                    )))
+                    .await;
+            }
+        },
+        None => PushOptions::default(),
+    };
+
     // Use English as default language for now
     let language = "en";
 
@@ -97,25 +227,12 @@ pub async fn process_message(
     {
         Ok(template) => template,
         Err(e) => {
-            redis_client
-                .mark_as_failed(&message.idempotency_key)
-                .await?;
-
-            let audit_log = CreateAuditLog::new(
-                message.request_id.clone(),
-                message.user_id.clone(),
-                message.notification_type.clone(),
-                message.template_code.clone(),
-                NotificationStatus::Failed,
-            )
-            .with_error(format!("Template fetch failed: {}", e))
-            .with_metadata(serde_json::to_value(message.metadata.clone())?);
-
-            if let Err(log_err) = database_client.log_notification(audit_log).await {
-                warn!(error = %log_err, "Failed to write audit log");
-            }
-
-            return Err(anyhow!("Failed to fetch template: {}", e));
+            return context
+                .fail_message(ProcessingError::retryable(format!(
+                    "Template fetch failed: {}",
+                    e
+                )))
+                .await;
         }
     };
 
@@ -125,83 +242,912 @@ pub async fn process_message(
             rendered
         }
         Err(e) => {
-            redis_client
-                .mark_as_failed(&message.idempotency_key)
-                .await?;
-
-            let audit_log = CreateAuditLog::new(
-                message.request_id.clone(),
-                message.user_id.clone(),
-                message.notification_type.clone(),
-                message.template_code.clone(),
-                NotificationStatus::Failed,
+            return context
+                .fail_message(ProcessingError::permanent(format!(
+                    "Template render failed: {}",
+                    e
+                )))
+                .await;
+        }
+    };
+
+    // Per-message options win over whatever the template ships with
+    options.image = options.image.or(rendered.image);
+    options.link = options.link.or(rendered.link);
+
+    let data: Option<HashMap<String, String>> = message
+        .metadata
+        .get("push_data")
+        .and_then(|v| v.as_object())
+        .map(|fields| {
+            fields
+                .iter()
+                .map(|(key, value)| {
+                    let value = match value {
+                        serde_json::Value::String(s) => s.clone(),
+                        other => other.to_string(),
+                    };
+                    (key.clone(), value)
+                })
+                .collect()
+        });
+
+    let priority = message
+        .metadata
+        .get("push_priority")
+        .and_then(|v| v.as_str())
+        .map(PushPriority::from_string)
+        .unwrap_or_default();
+
+    let collapse_key = message
+        .metadata
+        .get("collapse_key")
+        .and_then(|v| v.as_str())
+        .map(|v| v.to_string());
+
+    let outcomes = join_all(devices.iter().map(|device| {
+        let notification = PushNotification {
+            target: device.target.clone(),
+            title: rendered.title.clone(),
+            body: rendered.body.clone(),
+            trace_id: message.request_id.clone(),
+            data: data.clone(),
+            priority,
+            collapse_key: collapse_key.clone(),
+            expires_at,
+            options: options.clone(),
+        };
+
+        context.deliver_to_device(device, notification)
+    }))
+    .await;
+
+    let summary = DeliverySummary::from_outcomes(&outcomes);
+    let provider_message_ids = outcomes
+        .iter()
+        .filter_map(|outcome| match &outcome.status {
+            DeviceDeliveryStatus::Sent { message_id } => message_id.clone(),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    let status = summary.status();
+
+    // Only a full success settles the message; anything else leaves it
+    // retryable, and devices that already got it are skipped next time.
+    // Devices still claimed by another attempt count as unsettled.
+    if summary.is_complete() {
+        redis_client.mark_as_sent(&message.idempotency_key).await?;
+    } else {
+        redis_client
+            .mark_as_failed(&message.idempotency_key)
+            .await?;
+    }
+
+    let mut audit_log = CreateAuditLog::new(
+        message.request_id.clone(),
+        message.user_id.clone(),
+        message.notification_type.clone(),
+        message.template_code.clone(),
+        status.clone(),
+    )
+    .with_metadata(context.audit_metadata("delivery_summary", serde_json::to_value(&summary)?));
+
+    if summary.failed > 0 {
+        audit_log = audit_log.with_error(format!(
+            "{} of {} devices failed",
+            summary.failed, summary.total
+        ));
+    } else if summary.in_progress > 0 {
+        audit_log = audit_log.with_error(format!(
+            "{} of {} devices still in progress",
+            summary.in_progress, summary.total
+        ));
+    }
+
+    if let Err(log_err) = database_client.log_notification(audit_log).await {
+        warn!(error = %log_err, "Failed to write audit log");
+    }
+
+    info!(
+        request_id = %message.request_id,
+        idempotency_key = %message.idempotency_key,
+        status = %status,
+        total = summary.total,
+        sent = summary.sent,
+        failed = summary.failed,
+        in_progress = summary.in_progress,
+        pruned = summary.pruned,
+        "Notification fan-out completed"
+    );
+
+    if !summary.is_complete() {
+        let errors = outcomes
+            .iter()
+            .filter_map(|outcome| match &outcome.status {
+                DeviceDeliveryStatus::Failed { error, .. } => {
+                    Some(format!("{}: {}", outcome.device_id, error))
+                }
+                DeviceDeliveryStatus::InProgress => {
+                    Some(format!("{}: still in progress", outcome.device_id))
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .join("; ");
+
+        // Dead tokens and rejected payloads will not start working later,
+        // but an unsettled device is worth checking on again
+        let retryable = outcomes.iter().any(|outcome| {
+            matches!(
+                outcome.status,
+                DeviceDeliveryStatus::InProgress
+                    | DeviceDeliveryStatus::Failed {
+                        kind: DeliveryErrorKind::Retryable,
+                        ..
+                    }
             )
-            .with_error(format!("Template render failed: {}", e))
-            .with_metadata(serde_json::to_value(message.metadata.clone())?);
+        });
+
+        let error_code = outcomes.iter().find_map(|outcome| match &outcome.status {
+            DeviceDeliveryStatus::Failed { error_code, .. } => error_code.clone(),
+            _ => None,
+        });
+
+        return Err(ProcessingError {
+            retryable,
+            message: format!(
+                "Notification failed for {} of {} devices: {}",
+                summary.failed + summary.in_progress,
+                summary.total,
+                errors
+            ),
+            error_code,
+        }
+        .into());
+    }
+
+    Ok(ProcessingOutcome::Delivered {
+        provider_message_ids,
+    })
+}
+
+/// What became of a message that was handled without an error.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProcessingOutcome {
+    /// Every device got the push
+    Delivered { provider_message_ids: Vec<String> },
+    /// Dropped because it was too old to be useful
+    Expired,
+    /// Skipped as a duplicate, suppressed or deferred; nothing was sent
+    Settled,
+}
+
+/// A message that could not be handled, split by whether redelivering it
+/// later could help.
+#[derive(Debug)]
+pub struct ProcessingError {
+    pub retryable: bool,
+    message: String,
+    /// Provider error code of the first failed device, if any
+    pub error_code: Option<String>,
+}
+
+impl ProcessingError {
+    pub fn retryable(message: String) -> Self {
+        Self {
+            retryable: true,
+            message,
+            error_code: None,
+        }
+    }
+
+    pub fn permanent(message: String) -> Self {
+        Self {
+            retryable: false,
+            message,
+            error_code: None,
+        }
+    }
+}
+
+impl fmt::Display for ProcessingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for ProcessingError {}
+
+/// Whether a failed message is worth another attempt later. Failures that
+/// were not classified, e.g. a Redis outage, are assumed to be transient.
+pub fn is_retryable(error: &Error) -> bool {
+    error
+        .downcast_ref::<ProcessingError>()
+        .is_none_or(|error| error.retryable)
+}
+
+struct DeliveryContext<'a> {
+    message: &'a NotificationMessage,
+    redis_client: &'a RedisClient,
+    providers: &'a ProviderRegistry,
+    database_client: &'a DatabaseClient,
+    user_service_client: &'a UserServiceClient,
+    events: &'a dyn EventPublisher,
+}
+
+impl DeliveryContext<'_> {
+    async fn fail_message(&self, error: ProcessingError) -> Result<ProcessingOutcome, Error> {
+        self.redis_client
+            .mark_as_failed(&self.message.idempotency_key)
+            .await?;
+
+        let audit_log = self
+            .audit_log(NotificationStatus::Failed)
+            .with_error(error.to_string())
+            .with_metadata(self.message_metadata());
+
+        if let Err(log_err) = self.database_client.log_notification(audit_log).await {
+            warn!(error = %log_err, "Failed to write audit log");
+        }
+
+        Err(error.into())
+    }
+
+    /// Settles a message the user opted out of without contacting any
+    /// provider.
+    async fn suppress_message(
+        &self,
+        reason: SuppressionReason,
+    ) -> Result<ProcessingOutcome, Error> {
+        self.redis_client
+            .mark_as_suppressed(&self.message.idempotency_key)
+            .await?;
+
+        let audit_log = self
+            .audit_log(NotificationStatus::Suppressed)
+            .with_metadata(
+                self.audit_metadata("suppressed_reason", serde_json::json!(reason.as_str())),
+            );
+
+        if let Err(log_err) = self.database_client.log_notification(audit_log).await {
+            warn!(error = %log_err, "Failed to write audit log");
+        }
+
+        info!(
+            request_id = %self.message.request_id,
+            user_id = %self.message.user_id,
+            reason = reason.as_str(),
+            "Notification suppressed by user preferences"
+        );
 
-            if let Err(log_err) = database_client.log_notification(audit_log).await {
-                warn!(error = %log_err, "Failed to write audit log");
+        Ok(ProcessingOutcome::Settled)
+    }
+
+    /// Parks a message that must not go out yet. The scheduler puts the
+    /// original payload back on the queue at `deliver_at`.
+    async fn defer_message(
+        &self,
+        payload: &str,
+        reason: ScheduleReason,
+        deliver_at: DateTime<Utc>,
+    ) -> Result<ProcessingOutcome, Error> {
+        let scheduled = async {
+            let user_id = Uuid::parse_str(&self.message.user_id)
+                .map_err(|e| anyhow!("Invalid user_id format: {}", e))?;
+            let mut payload: serde_json::Value = serde_json::from_str(payload)?;
+
+            // The republished copy is due at `deliver_at`, which is also where
+            // its default TTL starts counting
+            if let Some(data) = payload
+                .get_mut("data")
+                .and_then(|data| data.as_object_mut())
+            {
+                data.insert(
+                    "send_at".to_string(),
+                    serde_json::json!(deliver_at.to_rfc3339_opts(SecondsFormat::Millis, true)),
+                );
             }
 
-            return Err(anyhow!("Failed to render template: {}", e));
+            self.database_client
+                .scheduled_notifications()
+                .schedule(
+                    &self.message.idempotency_key,
+                    &self.message.request_id,
+                    user_id,
+                    reason,
+                    &payload,
+                    deliver_at,
+                )
+                .await
+        }
+        .await;
+
+        let scheduled = match scheduled {
+            Ok(scheduled) => scheduled,
+            Err(e) => {
+                return self
+                    .fail_message(ProcessingError::retryable(format!(
+                        "Failed to defer notification: {}",
+                        e
+                    )))
+                    .await;
+            }
+        };
+
+        // The republished copy must not be skipped as a duplicate
+        self.redis_client
+            .release(&self.message.idempotency_key)
+            .await?;
+
+        let Some(scheduled) = scheduled else {
+            info!(
+                request_id = %self.message.request_id,
+                idempotency_key = %self.message.idempotency_key,
+                "Scheduled notification was cancelled, dropping redelivery"
+            );
+            return Ok(ProcessingOutcome::Settled);
+        };
+
+        let audit_log =
+            self.audit_log(NotificationStatus::Deferred)
+                .with_metadata(self.audit_metadata(
+                    "deferred",
+                    serde_json::json!({
+                        "reason": reason.as_str(),
+                        "deliver_at": scheduled
+                            .deliver_at
+                            .to_rfc3339_opts(SecondsFormat::Secs, true),
+                    }),
+                ));
+
+        if let Err(log_err) = self.database_client.log_notification(audit_log).await {
+            warn!(error = %log_err, "Failed to write audit log");
+        }
+
+        info!(
+            request_id = %self.message.request_id,
+            user_id = %self.message.user_id,
+            reason = reason.as_str(),
+            deliver_at = %scheduled.deliver_at,
+            "Notification deferred"
+        );
+
+        Ok(ProcessingOutcome::Settled)
+    }
+
+    /// Drops a message that is no longer worth sending.
+    async fn expire_message(&self, expires_at: DateTime<Utc>) -> Result<ProcessingOutcome, Error> {
+        self.redis_client
+            .mark_as_expired(&self.message.idempotency_key)
+            .await?;
+
+        let audit_log =
+            self.audit_log(NotificationStatus::Expired)
+                .with_metadata(self.audit_metadata(
+                    "expires_at",
+                    serde_json::json!(expires_at.to_rfc3339_opts(SecondsFormat::Secs, true)),
+                ));
+
+        if let Err(log_err) = self.database_client.log_notification(audit_log).await {
+            warn!(error = %log_err, "Failed to write audit log");
+        }
+
+        info!(
+            request_id = %self.message.request_id,
+            user_id = %self.message.user_id,
+            expires_at = %expires_at,
+            "Notification expired, dropping"
+        );
+
+        Ok(ProcessingOutcome::Expired)
+    }
+
+    async fn deliver_to_device(
+        &self,
+        device: &DeviceTarget,
+        notification: PushNotification,
+    ) -> DeviceOutcome {
+        let idempotency_key =
+            device_idempotency_key(&self.message.idempotency_key, &device.device_id);
+
+        let status = match self.redis_client.check_idempotency(&idempotency_key).await {
+            Ok(IdempotencyStatus::Sent) => {
+                debug!(
+                    device_id = %device.device_id,
+                    "Device already received this notification, skipping"
+                );
+                DeviceDeliveryStatus::AlreadySent
+            }
+            Ok(IdempotencyStatus::Processing) => {
+                debug!(
+                    device_id = %device.device_id,
+                    "Device is being handled elsewhere, skipping"
+                );
+                DeviceDeliveryStatus::InProgress
+            }
+            _ => {
+                self.send_to_device(device, &notification, &idempotency_key)
+                    .await
+            }
+        };
+
+        DeviceOutcome {
+            device_id: device.device_id.clone(),
+            status,
+        }
+    }
+
+    async fn send_to_device(
+        &self,
+        device: &DeviceTarget,
+        notification: &PushNotification,
+        idempotency_key: &str,
+    ) -> DeviceDeliveryStatus {
+        if let Err(e) = self
+            .redis_client
+            .mark_device_as_processing(&self.message.idempotency_key, &device.device_id)
+            .await
+        {
+            return DeviceDeliveryStatus::Failed {
+                kind: DeliveryErrorKind::Retryable,
+                error: e.to_string(),
+                error_code: None,
+            };
+        }
+
+        let provider = match self.providers.resolve(device.provider.as_deref()) {
+            Ok(provider) => provider,
+            Err(e) => {
+                return self
+                    .fail_device(
+                        device,
+                        idempotency_key,
+                        DeliveryErrorKind::Permanent,
+                        format!("Provider resolution failed: {}", e),
+                        None,
+                    )
+                    .await;
+            }
+        };
+
+        // A malformed target fails this message only; the registry row is
+        // pruned on the provider's word, never on our own checks
+        if let Err(e) = provider.validate_target(&device.target) {
+            let target_label = match &device.target {
+                PushTarget::Token(_) | PushTarget::WebPush(_) => "device token".to_string(),
+                other => format!("{} target", other.kind()),
+            };
+
+            return self
+                .fail_device(
+                    device,
+                    idempotency_key,
+                    DeliveryErrorKind::Permanent,
+                    format!("Invalid {}: {}", target_label, e),
+                    None,
+                )
+                .await;
+        }
+
+        match provider.send(notification).await {
+            Ok(receipt) => {
+                if let Err(e) = self.redis_client.mark_as_sent(idempotency_key).await {
+                    warn!(error = %e, device_id = %device.device_id, "Failed to mark device as sent");
+                }
+
+                let audit_log =
+                    self.audit_log(NotificationStatus::Sent)
+                        .with_metadata(self.audit_metadata(
+                            "device",
+                            serde_json::json!({
+                                "device_id": device.device_id,
+                                "provider": provider.name(),
+                                "target_type": device.target.kind(),
+                                "message_id": receipt.message_id,
+                            }),
+                        ));
+
+                if let Err(log_err) = self.database_client.log_notification(audit_log).await {
+                    warn!(error = %log_err, "Failed to write audit log");
+                }
+
+                info!(
+                    request_id = %self.message.request_id,
+                    idempotency_key = %self.message.idempotency_key,
+                    device_id = %device.device_id,
+                    provider = provider.name(),
+                    message_id = ?receipt.message_id,
+                    "Notification sent successfully"
+                );
+
+                DeviceDeliveryStatus::Sent {
+                    message_id: receipt.message_id,
+                }
+            }
+            Err(e) => {
+                let error_kind = provider.classify_error(&e);
+                let error_code = provider.error_code(&e);
+
+                warn!(
+                    request_id = %self.message.request_id,
+                    device_id = %device.device_id,
+                    provider = provider.name(),
+                    error_kind = error_kind.as_str(),
+                    error_code = ?error_code,
+                    error = %e,
+                    "Push provider send failed"
+                );
+
+                self.fail_device(
+                    device,
+                    idempotency_key,
+                    error_kind,
+                    format!("{} send failed: {}", provider.name(), e),
+                    error_code,
+                )
+                .await
+            }
+        }
+    }
+
+    async fn fail_device(
+        &self,
+        device: &DeviceTarget,
+        idempotency_key: &str,
+        kind: DeliveryErrorKind,
+        error: String,
+        error_code: Option<String>,
+    ) -> DeviceDeliveryStatus {
+        if let Err(e) = self.redis_client.mark_as_failed(idempotency_key).await {
+            warn!(error = %e, device_id = %device.device_id, "Failed to mark device as failed");
+        }
+
+        let mut audit_log = self
+            .audit_log(NotificationStatus::Failed)
+            .with_error(error.clone())
+            .with_metadata(self.audit_metadata(
+                "device",
+                serde_json::json!({
+                    "device_id": device.device_id,
+                    "provider": device.provider,
+                    "target_type": device.target.kind(),
+                    "error_kind": kind.as_str(),
+                }),
+            ));
+
+        if let Some(code) = &error_code {
+            audit_log = audit_log.with_error_code(code.clone());
+        }
+
+        if let Err(log_err) = self.database_client.log_notification(audit_log).await {
+            warn!(error = %log_err, "Failed to write audit log");
+        }
+
+        if kind == DeliveryErrorKind::InvalidToken {
+            self.prune_device(device, &error, error_code.clone()).await;
+        }
+
+        DeviceDeliveryStatus::Failed {
+            kind,
+            error,
+            error_code,
+        }
+    }
+
+    /// Invalidates a dead token in the registry and tells other services to
+    /// forget it. Failures are only logged since the device has already
+    /// failed either way.
+    async fn prune_device(&self, device: &DeviceTarget, reason: &str, error_code: Option<String>) {
+        if !matches!(device.target, PushTarget::Token(_) | PushTarget::WebPush(_)) {
+            return;
+        }
+
+        let device_ids = match self
+            .database_client
+            .device_tokens()
+            .invalidate(&device.target)
+            .await
+        {
+            Ok(devices) => devices.into_iter().map(|d| d.id).collect(),
+            Err(e) => {
+                warn!(error = %e, device_id = %device.device_id, "Failed to invalidate device token");
+                Vec::new()
+            }
+        };
+
+        let event = TokenInvalidatedEvent {
+            user_id: self.message.user_id.clone(),
+            token: device.target.identifier().to_string(),
+            provider: device.provider.clone(),
+            device_ids,
+            reason: reason.to_string(),
+            error_code,
+            trace_id: self.message.request_id.clone(),
+            invalidated_at: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+        };
+
+        // The cached profile may still carry the dead token
+        self.user_service_client.forget(&self.message.user_id).await;
+
+        if let Err(e) = publish_event(self.events, TOKEN_INVALIDATED, &event).await {
+            warn!(error = %e, device_id = %device.device_id, "Failed to publish token invalidation");
         }
-    };
 
-    match fcm_client
-        .send_notification(
-            device_token,
-            &rendered.title,
-            &rendered.body,
-            &message.request_id,
-            None,
+        info!(
+            request_id = %self.message.request_id,
+            device_id = %device.device_id,
+            registry_devices = event.device_ids.len(),
+            "Dead device token pruned"
+        );
+    }
+
+    fn audit_log(&self, status: NotificationStatus) -> CreateAuditLog {
+        CreateAuditLog::new(
+            self.message.request_id.clone(),
+            self.message.user_id.clone(),
+            self.message.notification_type.clone(),
+            self.message.template_code.clone(),
+            status,
         )
+    }
+
+    fn message_metadata(&self) -> serde_json::Value {
+        serde_json::Value::Object(self.message.metadata.clone().into_iter().collect())
+    }
+
+    /// Message metadata with one extra entry describing this audit row
+    fn audit_metadata(&self, key: &str, value: serde_json::Value) -> serde_json::Value {
+        let mut metadata = self.message_metadata();
+
+        if let Some(fields) = metadata.as_object_mut() {
+            fields.insert(key.to_string(), value);
+        }
+
+        metadata
+    }
+}
+
+/// Works out every destination named explicitly in the message metadata. An
+/// explicit `devices` list or `push_tokens` array fans out; the single-target
+/// keys keep working for producers that only know one device. A bare
+/// `push_token` is not trusted here, see `current_devices`.
+fn resolve_devices(message: &NotificationMessage) -> Result<Vec<DeviceTarget>, Error> {
+    let metadata = &message.metadata;
+    let metadata_str = |key: &str| metadata.get(key).and_then(|v| v.as_str());
+    let default_provider = metadata_str("push_provider").map(|v| v.to_string());
+
+    // Subscriptions only make sense for Web Push, so route them there unless
+    // the producer explicitly asked for another provider.
+    let webpush_provider = |provider: Option<String>| provider.or(Some("webpush".to_string()));
+
+    let mut devices = Vec::new();
+
+    if let Some(entries) = metadata.get("devices") {
+        let entries: Vec<MetadataDevice> = serde_json::from_value(entries.clone())
+            .map_err(|e| anyhow!("Invalid devices in metadata: {}", e))?;
+
+        for entry in entries {
+            let provider = entry.push_provider.or(default_provider.clone());
+
+            let device = match (entry.push_subscription, entry.push_token) {
+                (Some(subscription), _) => DeviceTarget::new(
+                    PushTarget::WebPush(subscription),
+                    webpush_provider(provider),
+                ),
+                (None, Some(token)) => DeviceTarget::new(PushTarget::Token(token), provider),
+                (None, None) => {
+                    return Err(anyhow!(
+                        "Device entries need a push_token or push_subscription"
+                    ));
+                }
+            };
+
+            devices.push(device);
+        }
+    } else if let Some(tokens) = metadata.get("push_tokens") {
+        let tokens: Vec<String> = serde_json::from_value(tokens.clone())
+            .map_err(|e| anyhow!("Invalid push_tokens in metadata: {}", e))?;
+
+        devices.extend(
+            tokens
+                .into_iter()
+                .map(|token| DeviceTarget::new(PushTarget::Token(token), default_provider.clone())),
+        );
+    } else if let Some(subscription) = metadata.get("push_subscription") {
+        let subscription = serde_json::from_value(subscription.clone())
+            .map_err(|e| anyhow!("Invalid push_subscription in metadata: {}", e))?;
+
+        devices.push(DeviceTarget::new(
+            PushTarget::WebPush(subscription),
+            webpush_provider(default_provider),
+        ));
+    } else if let Some(topic) = metadata_str("push_topic") {
+        devices.push(DeviceTarget::new(
+            PushTarget::Topic(topic.trim_start_matches("/topics/").to_string()),
+            default_provider,
+        ));
+    } else if let Some(condition) = metadata_str("push_condition") {
+        devices.push(DeviceTarget::new(
+            PushTarget::Condition(condition.to_string()),
+            default_provider,
+        ));
+    }
+
+    // A device listed twice must still only be notified once
+    let mut seen = HashSet::new();
+    devices.retain(|device| seen.insert(device.device_id.clone()));
+
+    Ok(devices)
+}
+
+/// Drops metadata targets whose token the registry has already invalidated.
+/// A failed lookup keeps every target; the provider will reject dead ones.
+async fn without_invalidated(
+    devices: Vec<DeviceTarget>,
+    database_client: &DatabaseClient,
+) -> Vec<DeviceTarget> {
+    let identifiers = devices
+        .iter()
+        .filter(|device| matches!(device.target, PushTarget::Token(_) | PushTarget::WebPush(_)))
+        .map(|device| device.target.identifier().to_string())
+        .collect::<Vec<_>>();
+
+    let invalidated = match database_client
+        .device_tokens()
+        .invalidated_identifiers(&identifiers)
         .await
     {
-        Ok(_) => {
-            redis_client.mark_as_sent(&message.idempotency_key).await?;
-
-            let audit_log = CreateAuditLog::new(
-                message.request_id.clone(),
-                message.user_id.clone(),
-                message.notification_type.clone(),
-                message.template_code.clone(),
-                NotificationStatus::Sent,
-            )
-            .with_metadata(serde_json::to_value(message.metadata.clone())?);
+        Ok(invalidated) => invalidated,
+        Err(e) => {
+            warn!(error = %e, "Failed to check for invalidated tokens");
+            return devices;
+        }
+    };
 
-            if let Err(log_err) = database_client.log_notification(audit_log).await {
-                warn!(error = %log_err, "Failed to write audit log");
+    devices
+        .into_iter()
+        .filter(|device| {
+            let skip = invalidated.contains(device.target.identifier());
+
+            if skip {
+                debug!(device_id = %device.device_id, "Skipping invalidated device token");
             }
 
-            info!(
-                request_id = %message.request_id,
-                idempotency_key = %message.idempotency_key,
-                "Notification sent successfully"
-            );
-            Ok(())
+            !skip
+        })
+        .collect()
+}
+
+/// What user-service said about the recipient.
+enum UserLookup {
+    Found(UserProfile),
+    NotFound,
+    Unavailable,
+}
+
+impl UserLookup {
+    async fn fetch(message: &NotificationMessage, user_service_client: &UserServiceClient) -> Self {
+        match user_service_client.fetch_user(&message.user_id).await {
+            Ok(Some(profile)) => UserLookup::Found(profile),
+            Ok(None) => {
+                warn!(user_id = %message.user_id, "User not found in user-service");
+                UserLookup::NotFound
+            }
+            Err(e) => {
+                warn!(
+                    user_id = %message.user_id,
+                    error = %e,
+                    "User lookup failed, continuing with message metadata"
+                );
+                UserLookup::Unavailable
+            }
         }
+    }
+}
+
+/// Looks up where the user can be reached right now: their registered
+/// devices plus the push token user-service holds. The `push_token` copied
+/// into metadata at enqueue time is only used when user-service cannot be
+/// asked.
+async fn current_devices(
+    message: &NotificationMessage,
+    user: &UserLookup,
+    database_client: &DatabaseClient,
+) -> Result<Vec<DeviceTarget>, Error> {
+    let mut devices = registered_devices(message, database_client).await?;
+
+    let provider = message
+        .metadata
+        .get("push_provider")
+        .and_then(|v| v.as_str())
+        .map(|v| v.to_string());
+
+    let token = match user {
+        UserLookup::Found(profile) => profile.push_token.clone(),
+        UserLookup::NotFound => None,
+        UserLookup::Unavailable => message
+            .metadata
+            .get("push_token")
+            .and_then(|v| v.as_str())
+            .map(|v| v.to_string()),
+    };
+
+    if let Some(token) = token {
+        devices.push(DeviceTarget::new(PushTarget::Token(token), provider));
+    }
+
+    let mut seen = HashSet::new();
+    devices.retain(|device| seen.insert(device.device_id.clone()));
+
+    Ok(devices)
+}
+
+/// The zone the user's quiet hours are read in: their own setting, then the
+/// zone of the device they used most recently, then UTC.
+async fn user_timezone(
+    message: &NotificationMessage,
+    profile: &UserProfile,
+    database_client: &DatabaseClient,
+) -> Tz {
+    let name = match profile.preferences.quiet_hours_timezone() {
+        Some(name) => Some(name.to_string()),
+        None => device_timezone(message, database_client).await,
+    };
+
+    let Some(name) = name else {
+        return Tz::UTC;
+    };
+
+    name.parse().unwrap_or_else(|_| {
+        warn!(user_id = %message.user_id, timezone = %name, "Unknown timezone, using UTC");
+        Tz::UTC
+    })
+}
+
+async fn device_timezone(
+    message: &NotificationMessage,
+    database_client: &DatabaseClient,
+) -> Option<String> {
+    let user_id = Uuid::parse_str(&message.user_id).ok()?;
+
+    match database_client
+        .device_tokens()
+        .list_for_user(user_id, false)
+        .await
+    {
+        Ok(devices) => devices.into_iter().find_map(|device| device.timezone),
         Err(e) => {
-            redis_client
-                .mark_as_failed(&message.idempotency_key)
-                .await?;
-
-            let audit_log = CreateAuditLog::new(
-                message.request_id.clone(),
-                message.user_id.clone(),
-                message.notification_type.clone(),
-                message.template_code.clone(),
-                NotificationStatus::Failed,
-            )
-            .with_error(format!("FCM send failed: {}", e))
-            .with_metadata(serde_json::to_value(message.metadata.clone())?);
+            warn!(error = %e, "Failed to look up device timezone");
+            None
+        }
+    }
+}
 
-            if let Err(log_err) = database_client.log_notification(audit_log).await {
-                warn!(error = %log_err, "Failed to write audit log");
-            }
+/// Devices the user registered through the device API.
+async fn registered_devices(
+    message: &NotificationMessage,
+    database_client: &DatabaseClient,
+) -> Result<Vec<DeviceTarget>, Error> {
+    let user_id =
+        Uuid::parse_str(&message.user_id).map_err(|e| anyhow!("Invalid user_id format: {}", e))?;
 
-            Err(anyhow!("Notification failed: {}", e))
+    let devices = database_client
+        .device_tokens()
+        .list_for_user(user_id, false)
+        .await?;
+
+    let mut targets = Vec::with_capacity(devices.len());
+
+    for device in devices {
+        match device.target() {
+            Ok(target) => targets.push(DeviceTarget::new(target, Some(device.provider))),
+            Err(e) => {
+                warn!(device_id = %device.id, error = %e, "Skipping unusable registered device")
+            }
         }
     }
+
+    Ok(targets)
 }
 
 impl RetryConfig {
@@ -220,6 +1166,47 @@ where
     F: Fn() -> Fut,
     Fut: std::future::Future<Output = Result<T, E>>,
     E: std::fmt::Display,
+{
+    retry_with_backoff_if(config, operation, |_| true).await
+}
+
+/// Like `retry_with_backoff`, but gives up immediately on errors the
+/// predicate reports as not worth retrying.
+pub async fn retry_with_backoff_if<F, Fut, T, E, P>(
+    config: &RetryConfig,
+    operation: F,
+    should_retry: P,
+) -> Result<T, E>
+where
+    F: Fn() -> Fut,
+    Fut: std::future::Future<Output = Result<T, E>>,
+    E: std::fmt::Display,
+    P: Fn(&E) -> bool,
+{
+    retry_with_backoff_policy(config, operation, |e| {
+        if should_retry(e) {
+            RetryDecision::Retry
+        } else {
+            RetryDecision::Abort
+        }
+    })
+    .await
+}
+
+/// Retries according to the decision returned for each error. A
+/// `RetryAfter` delay from the server replaces the computed backoff; if it is
+/// longer than `max_delay_ms` the error is returned instead of holding the
+/// worker that long.
+pub async fn retry_with_backoff_policy<F, Fut, T, E, P>(
+    config: &RetryConfig,
+    operation: F,
+    decide: P,
+) -> Result<T, E>
+where
+    F: Fn() -> Fut,
+    Fut: std::future::Future<Output = Result<T, E>>,
+    E: std::fmt::Display,
+    P: Fn(&E) -> RetryDecision,
 {
     let mut attempt = 0;
     let mut delay_ms = config.initial_delay_ms;
@@ -239,6 +1226,15 @@ where
                 return Ok(result);
             }
             Err(e) => {
+                let server_delay = match decide(&e) {
+                    RetryDecision::Retry => None,
+                    RetryDecision::RetryAfter(delay) => Some(delay),
+                    RetryDecision::Abort => {
+                        debug!(attempt, error = %e, "Error is not retryable, giving up");
+                        return Err(e);
+                    }
+                };
+
                 if attempt >= config.max_attempts {
                     warn!(
                         max_attempts = config.max_attempts,
@@ -248,6 +1244,28 @@ where
                     return Err(e);
                 }
 
+                if let Some(server_delay) = server_delay {
+                    if server_delay.as_millis() as u64 > config.max_delay_ms {
+                        warn!(
+                            retry_after_ms = server_delay.as_millis() as u64,
+                            max_delay_ms = config.max_delay_ms,
+                            error = %e,
+                            "Server requested delay exceeds retry budget, giving up"
+                        );
+                        return Err(e);
+                    }
+
+                    debug!(
+                        attempt,
+                        max_attempts = config.max_attempts,
+                        retry_after_ms = server_delay.as_millis() as u64,
+                        "Retry attempt failed, honoring server requested delay"
+                    );
+
+                    sleep(server_delay).await;
+                    continue;
+                }
+
                 debug!(
                     attempt,
                     max_attempts = config.max_attempts,
@@ -266,3 +1284,18 @@ where
         }
     }
 }
+
+/// Parses a `Retry-After` header given either as delta-seconds or as an
+/// HTTP-date.
+pub fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let retry_at = DateTime::parse_from_rfc2822(value).ok()?;
+    let remaining = retry_at.with_timezone(&Utc) - Utc::now();
+
+    Some(remaining.to_std().unwrap_or(Duration::ZERO))
+}
@@ -0,0 +1,309 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use anyhow::{Error, Result};
+use async_trait::async_trait;
+use push_service::{
+    clients::{
+        circuit_breaker::CircuitBreaker, database::DatabaseClient, events::EventPublisher,
+        fcm::FcmClient, provider::ProviderRegistry, redis::RedisClient,
+        template::TemplateServiceClient, user::UserServiceClient,
+    },
+    config::Config,
+    models::{fcm::FcmAuthMode, message::NotificationMessage},
+    utils::{ProcessingOutcome, process_message},
+};
+use tokio::{sync::Semaphore, time::Instant};
+use uuid::Uuid;
+use wiremock::{
+    Mock, MockServer, ResponseTemplate,
+    matchers::{method, path},
+};
+
+const TEMPLATE_LATENCY_MS: u64 = 100;
+const FCM_LATENCY_MS: u64 = 200;
+const JOB_COUNT: usize = 8;
+
+/// Shared by every worker, like the clients `main` hands to its tasks
+#[derive(Clone)]
+struct Worker {
+    config: Config,
+    redis_client: RedisClient,
+    template_service_client: TemplateServiceClient,
+    user_service_client: UserServiceClient,
+    providers: ProviderRegistry,
+    database_client: Arc<DatabaseClient>,
+}
+
+struct DiscardingPublisher;
+
+#[async_trait]
+impl EventPublisher for DiscardingPublisher {
+    async fn publish(&self, _routing_key: &str, _payload: &[u8]) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Test: Messages are processed in parallel end to end, so throughput scales with concurrency
+#[tokio::test]
+async fn test_throughput_scales_with_worker_concurrency() -> Result<()> {
+    let mock_server = start_slow_services().await;
+    let worker = create_worker(&mock_server).await?;
+
+    let sequential = run_workers(&worker, &mock_server, 1, JOB_COUNT).await?;
+    let concurrent = run_workers(&worker, &mock_server, JOB_COUNT, JOB_COUNT).await?;
+
+    assert!(
+        sequential >= Duration::from_millis(FCM_LATENCY_MS * JOB_COUNT as u64),
+        "Single worker should process messages one after another (took {:?})",
+        sequential
+    );
+
+    assert!(
+        concurrent * 3 < sequential,
+        "{} workers should be at least 3x faster than one (sequential: {:?}, concurrent: {:?})",
+        JOB_COUNT,
+        sequential,
+        concurrent
+    );
+
+    Ok(())
+}
+
+/// Test: Cloned circuit breaker handles share state through Redis
+#[tokio::test]
+async fn test_cloned_circuit_breakers_share_state() -> Result<()> {
+    let mut config = Config::load()?;
+    config.circuit_breaker_failure_threshold = 2;
+
+    let redis_client = redis::Client::open(config.redis_url.as_str())?;
+    let redis_conn = redis_client.get_multiplexed_async_connection().await?;
+
+    let service_name = format!("test_shared_{}", Uuid::new_v4());
+    let breaker = CircuitBreaker::new(
+        service_name.clone(),
+        redis_conn,
+        config.circuit_breaker_config(),
+    );
+    let other_handle = breaker.clone();
+
+    for _ in 0..2 {
+        let _ = breaker
+            .call(|| async { Err::<(), _>(anyhow::anyhow!("Downstream failure")) })
+            .await;
+    }
+
+    let result = other_handle.call(|| async { Ok(()) }).await;
+
+    assert!(
+        result.is_err(),
+        "Circuit opened through one handle should reject calls through another"
+    );
+
+    cleanup_circuit_keys(&config, &service_name).await?;
+
+    Ok(())
+}
+
+async fn start_slow_services() -> MockServer {
+    let mock_server = MockServer::start().await;
+
+    Mock::given(method("GET"))
+        .and(path("/api/v1/templates/THROUGHPUT_TEMPLATE"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_delay(Duration::from_millis(TEMPLATE_LATENCY_MS))
+                .set_body_json(serde_json::json!({
+                    "id": "tpl_throughput",
+                    "code": "THROUGHPUT_TEMPLATE",
+                    "type": "push",
+                    "language": "en",
+                    "version": 1,
+                    "content": {
+                        "title": "Hello {{name}}",
+                        "body": "Throughput check for {{name}}"
+                    },
+                    "variables": ["name"]
+                })),
+        )
+        .mount(&mock_server)
+        .await;
+
+    Mock::given(method("POST"))
+        .and(path("/v1/projects/throughput-project/messages:send"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_delay(Duration::from_millis(FCM_LATENCY_MS))
+                .set_body_json(serde_json::json!({
+                    "name": "projects/throughput-project/messages/0:1500415314455276%31bd1c9631bd1c96"
+                })),
+        )
+        .mount(&mock_server)
+        .await;
+
+    mock_server
+}
+
+async fn create_worker(mock_server: &MockServer) -> Result<Worker> {
+    let mut config = Config::load()?;
+    config.template_service_url = mock_server.uri();
+    config.user_service_url = mock_server.uri();
+    config.fcm_base_url = format!("{}/", mock_server.uri());
+    config.fcm_project_id = "throughput-project".to_string();
+    config.fcm_auth_mode = FcmAuthMode::None;
+
+    let redis_client = redis::Client::open(config.redis_url.as_str())?;
+    let redis_conn = redis_client.get_multiplexed_async_connection().await?;
+
+    let circuit_breaker = |service: &str| {
+        CircuitBreaker::new(
+            format!("{}_throughput_{}", service, Uuid::new_v4()),
+            redis_conn.clone(),
+            config.circuit_breaker_config(),
+        )
+    };
+
+    let template_service_client =
+        TemplateServiceClient::new(&config, circuit_breaker("template")).await?;
+    let user_service_client =
+        UserServiceClient::new(&config, circuit_breaker("user"), redis_conn.clone())?;
+    let fcm_client = FcmClient::new(&config, circuit_breaker("fcm")).await?;
+
+    Ok(Worker {
+        redis_client: RedisClient::connect(&config).await?,
+        database_client: Arc::new(DatabaseClient::connect(&config.database_url).await?),
+        template_service_client,
+        user_service_client,
+        providers: ProviderRegistry::new().register(fcm_client),
+        config,
+    })
+}
+
+/// Processes `jobs` fresh messages with at most `concurrency` in flight, the
+/// way the consumer loop bounds its spawned tasks
+async fn run_workers(
+    worker: &Worker,
+    mock_server: &MockServer,
+    concurrency: usize,
+    jobs: usize,
+) -> Result<Duration> {
+    let mut messages = Vec::with_capacity(jobs);
+
+    for _ in 0..jobs {
+        let message = create_notification_message();
+        mount_user(mock_server, &message.user_id).await;
+        messages.push(message);
+    }
+
+    let semaphore = Arc::new(Semaphore::new(concurrency));
+    let start = Instant::now();
+    let mut handles = vec![];
+
+    for message in &messages {
+        let worker = worker.clone();
+        let semaphore = Arc::clone(&semaphore);
+        let payload = serde_json::to_string(&serde_json::json!({
+            "pattern": "push.queue",
+            "data": message,
+        }))?;
+
+        handles.push(tokio::spawn(async move {
+            let _permit = semaphore.acquire().await.unwrap();
+
+            process_message(
+                &payload,
+                &worker.redis_client,
+                &worker.template_service_client,
+                &worker.user_service_client,
+                &worker.providers,
+                &worker.database_client,
+                &DiscardingPublisher,
+                &worker.config,
+            )
+            .await
+        }));
+    }
+
+    for result in futures_util::future::join_all(handles).await {
+        let outcome = result??;
+        assert!(
+            matches!(outcome, ProcessingOutcome::Delivered { .. }),
+            "Message should be delivered, got {:?}",
+            outcome
+        );
+    }
+
+    let elapsed = start.elapsed();
+
+    for message in &messages {
+        cleanup_idempotency_keys(&worker.config, &message.idempotency_key).await?;
+    }
+
+    Ok(elapsed)
+}
+
+async fn mount_user(mock_server: &MockServer, user_id: &str) {
+    Mock::given(method("GET"))
+        .and(path(format!("/api/v1/users/{}", user_id)))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "success": true,
+            "message": "User retrieved successfully",
+            "data": {
+                "id": user_id,
+                "push_token": format!("device_token_{}", Uuid::new_v4().simple()),
+                "preferences": { "email": true, "push": true }
+            }
+        })))
+        .mount(mock_server)
+        .await;
+}
+
+fn create_notification_message() -> NotificationMessage {
+    let mut variables = HashMap::new();
+    variables.insert("name".to_string(), serde_json::json!("Ada"));
+
+    NotificationMessage {
+        notification_id: format!("cnotif_{}", Uuid::new_v4()),
+        idempotency_key: format!("idem_throughput_{}", Uuid::new_v4()),
+        notification_type: "push".to_string(),
+        user_id: Uuid::new_v4().to_string(),
+        template_code: "THROUGHPUT_TEMPLATE".to_string(),
+        variables,
+        request_id: format!("req_throughput_{}", Uuid::new_v4()),
+        priority: 1,
+        metadata: HashMap::new(),
+        created_by: "550e8400-e29b-41d4-a716-446655440000".to_string(),
+        timestamp: chrono::Utc::now().to_rfc3339(),
+        send_at: None,
+        expires_at: None,
+        ttl_seconds: None,
+    }
+}
+
+async fn cleanup_idempotency_keys(config: &Config, key: &str) -> Result<()> {
+    use redis::AsyncCommands;
+
+    let client = redis::Client::open(config.redis_url.as_str())?;
+    let mut conn = client.get_multiplexed_async_connection().await?;
+    conn.del::<_, ()>(format!("idempotency:{}", key)).await?;
+
+    let device_keys: Vec<String> = conn.keys(format!("idempotency:{}:*", key)).await?;
+    if !device_keys.is_empty() {
+        conn.del::<_, ()>(device_keys).await?;
+    }
+
+    Ok(())
+}
+
+async fn cleanup_circuit_keys(config: &Config, service_name: &str) -> Result<()> {
+    use redis::AsyncCommands;
+
+    let client = redis::Client::open(config.redis_url.as_str())?;
+    let mut conn = client.get_multiplexed_async_connection().await?;
+
+    for suffix in ["state", "failures", "successes", "opened_at"] {
+        conn.del::<_, ()>(format!("circuit:{}:{}", service_name, suffix))
+            .await?;
+    }
+
+    Ok(())
+}
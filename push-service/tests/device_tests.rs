@@ -0,0 +1,114 @@
+use anyhow::Result;
+use push_service::{
+    clients::database::DatabaseClient,
+    config::Config,
+    models::{
+        device::{DevicePlatform, RefreshDeviceRequest, RegisterDeviceRequest},
+        provider::PushTarget,
+    },
+};
+use uuid::Uuid;
+
+/// Test: Registering a known token moves it to the new user instead of duplicating it
+#[tokio::test]
+async fn test_register_device_upserts_by_token() -> Result<()> {
+    let database_client = connect().await?;
+    let repository = database_client.device_tokens();
+
+    let first_user = Uuid::new_v4();
+    let second_user = Uuid::new_v4();
+    let request = create_register_request();
+
+    let device = repository.register(first_user, &request).await?;
+    assert_eq!(device.user_id, first_user);
+    assert_eq!(device.provider, "fcm");
+    assert_eq!(device.target()?, PushTarget::Token(request.token.clone()));
+
+    let moved = repository.register(second_user, &request).await?;
+    assert_eq!(moved.id, device.id, "Same token should keep its row");
+    assert_eq!(moved.user_id, second_user);
+
+    assert!(repository.list_for_user(first_user, true).await?.is_empty());
+    assert_eq!(repository.list_for_user(second_user, false).await?.len(), 1);
+
+    repository.delete(second_user, device.id).await?;
+
+    Ok(())
+}
+
+/// Test: Refreshing a device bumps last_seen and can rotate its token
+#[tokio::test]
+async fn test_refresh_device_rotates_token() -> Result<()> {
+    let database_client = connect().await?;
+    let repository = database_client.device_tokens();
+
+    let user_id = Uuid::new_v4();
+    let device = repository
+        .register(user_id, &create_register_request())
+        .await?;
+
+    let rotated_token = format!("device_token_{}", Uuid::new_v4().simple());
+    let refreshed = repository
+        .refresh(
+            user_id,
+            device.id,
+            &RefreshDeviceRequest {
+                token: Some(rotated_token.clone()),
+                timezone: Some("Africa/Lagos".to_string()),
+                ..Default::default()
+            },
+        )
+        .await?
+        .expect("Device should exist");
+
+    assert_eq!(refreshed.token, rotated_token);
+    assert_eq!(refreshed.timezone.as_deref(), Some("Africa/Lagos"));
+    assert_eq!(refreshed.locale.as_deref(), Some("en-NG"));
+    assert!(refreshed.last_seen >= device.last_seen);
+
+    assert!(
+        repository
+            .refresh(Uuid::new_v4(), device.id, &RefreshDeviceRequest::default())
+            .await?
+            .is_none(),
+        "Devices of other users must not be refreshed"
+    );
+
+    repository.delete(user_id, device.id).await?;
+
+    Ok(())
+}
+
+/// Test: Devices can only be deleted by their owner
+#[tokio::test]
+async fn test_delete_device_is_scoped_to_user() -> Result<()> {
+    let database_client = connect().await?;
+    let repository = database_client.device_tokens();
+
+    let user_id = Uuid::new_v4();
+    let device = repository
+        .register(user_id, &create_register_request())
+        .await?;
+
+    assert!(!repository.delete(Uuid::new_v4(), device.id).await?);
+    assert!(repository.delete(user_id, device.id).await?);
+    assert!(repository.find(user_id, device.id).await?.is_none());
+
+    Ok(())
+}
+
+async fn connect() -> Result<DatabaseClient> {
+    let config = Config::load()?;
+    DatabaseClient::connect(&config.database_url).await
+}
+
+fn create_register_request() -> RegisterDeviceRequest {
+    RegisterDeviceRequest {
+        token: format!("device_token_{}", Uuid::new_v4().simple()),
+        platform: DevicePlatform::Android,
+        provider: None,
+        app_id: Some("com.example.shop".to_string()),
+        locale: Some("en-NG".to_string()),
+        timezone: None,
+    }
+}
@@ -1,13 +1,16 @@
-use anyhow::Result;
+use anyhow::{Error, Result};
+use async_trait::async_trait;
 use push_service::{
     clients::{
-        circuit_breaker::CircuitBreaker, database::DatabaseClient, fcm::FcmClient,
-        rbmq::RabbitMqClient, redis::RedisClient, template::TemplateServiceClient,
+        circuit_breaker::CircuitBreaker, database::DatabaseClient, events::EventPublisher,
+        fcm::FcmClient, provider::ProviderRegistry, rbmq::RabbitMqClient, redis::RedisClient,
+        template::TemplateServiceClient, user::UserServiceClient,
     },
     config::Config,
     models::{message::NotificationMessage, status::IdempotencyStatus},
     utils::process_message,
 };
+use redis::aio::MultiplexedConnection;
 use std::collections::HashMap;
 use uuid::Uuid;
 
@@ -22,6 +25,16 @@ fn setup() {
     });
 }
 
+/// Outbound events are not under test here
+struct DiscardEvents;
+
+#[async_trait]
+impl EventPublisher for DiscardEvents {
+    async fn publish(&self, _routing_key: &str, _payload: &[u8]) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
 /// Test: Complete notification flow from queue to success
 #[tokio::test]
 async fn test_end_to_end_notification_success_flow() -> Result<()> {
@@ -29,7 +42,7 @@ async fn test_end_to_end_notification_success_flow() -> Result<()> {
 
     let config = Config::load()?;
     RabbitMqClient::connect(&config).await?;
-    let mut redis_client = RedisClient::connect(&config).await?;
+    let redis_client = RedisClient::connect(&config).await?;
     let database_client = DatabaseClient::connect(&config.database_url).await?;
 
     let redis_for_cb = redis::Client::open(config.redis_url.as_str())?;
@@ -43,12 +56,13 @@ async fn test_end_to_end_notification_success_flow() -> Result<()> {
 
     let template_cb = CircuitBreaker::new(
         "template_service".to_string(),
-        redis_conn,
+        redis_conn.clone(),
         config.circuit_breaker_config(),
     );
 
-    let mut template_service_client = TemplateServiceClient::new(&config, template_cb).await?;
-    let mut fcm_client = FcmClient::new(&config, fcm_cb).await;
+    let template_service_client = TemplateServiceClient::new(&config, template_cb).await?;
+    let user_service_client = create_user_client(&config, redis_conn)?;
+    let providers = ProviderRegistry::new().register(FcmClient::new(&config, fcm_cb).await?);
 
     let message = create_notification_message("e2e_success");
     let payload = serde_json::to_string(&message)?;
@@ -57,10 +71,13 @@ async fn test_end_to_end_notification_success_flow() -> Result<()> {
 
     let result = process_message(
         &payload,
-        &mut redis_client,
-        &mut template_service_client,
-        &mut fcm_client,
+        &redis_client,
+        &template_service_client,
+        &user_service_client,
+        &providers,
         &database_client,
+        &DiscardEvents,
+        &config,
     )
     .await;
 
@@ -97,7 +114,7 @@ async fn test_end_to_end_duplicate_rejection() -> Result<()> {
     setup();
 
     let config = Config::load()?;
-    let mut redis_client = RedisClient::connect(&config).await?;
+    let redis_client = RedisClient::connect(&config).await?;
     let database_client = DatabaseClient::connect(&config.database_url).await?;
 
     let redis_for_cb = redis::Client::open(config.redis_url.as_str())?;
@@ -111,22 +128,26 @@ async fn test_end_to_end_duplicate_rejection() -> Result<()> {
 
     let template_cb = CircuitBreaker::new(
         "template_service".to_string(),
-        redis_conn,
+        redis_conn.clone(),
         config.circuit_breaker_config(),
     );
 
-    let mut template_service_client = TemplateServiceClient::new(&config, template_cb).await?;
-    let mut fcm_client = FcmClient::new(&config, fcm_cb).await;
+    let template_service_client = TemplateServiceClient::new(&config, template_cb).await?;
+    let user_service_client = create_user_client(&config, redis_conn)?;
+    let providers = ProviderRegistry::new().register(FcmClient::new(&config, fcm_cb).await?);
 
     let message = create_notification_message("e2e_duplicate");
     let payload = serde_json::to_string(&message)?;
 
     let first_result = process_message(
         &payload,
-        &mut redis_client,
-        &mut template_service_client,
-        &mut fcm_client,
+        &redis_client,
+        &template_service_client,
+        &user_service_client,
+        &providers,
         &database_client,
+        &DiscardEvents,
+        &config,
     )
     .await;
 
@@ -142,10 +163,13 @@ async fn test_end_to_end_duplicate_rejection() -> Result<()> {
     if first_result.is_ok() {
         let result2 = process_message(
             &payload,
-            &mut redis_client,
-            &mut template_service_client,
-            &mut fcm_client,
+            &redis_client,
+            &template_service_client,
+            &user_service_client,
+            &providers,
             &database_client,
+            &DiscardEvents,
+            &config,
         )
         .await;
         assert!(result2.is_ok(), "Duplicate should be silently handled");
@@ -177,7 +201,7 @@ async fn test_end_to_end_duplicate_rejection() -> Result<()> {
 #[tokio::test]
 async fn test_end_to_end_invalid_json_rejection() -> Result<()> {
     let config = Config::load()?;
-    let mut redis_client = RedisClient::connect(&config).await?;
+    let redis_client = RedisClient::connect(&config).await?;
     let database_client = DatabaseClient::connect(&config.database_url).await?;
 
     let redis_for_cb = redis::Client::open(config.redis_url.as_str())?;
@@ -191,21 +215,25 @@ async fn test_end_to_end_invalid_json_rejection() -> Result<()> {
 
     let template_cb = CircuitBreaker::new(
         "template_service".to_string(),
-        redis_conn,
+        redis_conn.clone(),
         config.circuit_breaker_config(),
     );
 
-    let mut fcm_client = FcmClient::new(&config, fcm_cb).await;
-    let mut template_service_client = TemplateServiceClient::new(&config, template_cb).await?;
+    let providers = ProviderRegistry::new().register(FcmClient::new(&config, fcm_cb).await?);
+    let template_service_client = TemplateServiceClient::new(&config, template_cb).await?;
+    let user_service_client = create_user_client(&config, redis_conn)?;
 
     let invalid_payload = "{ invalid json }";
 
     let result = process_message(
         invalid_payload,
-        &mut redis_client,
-        &mut template_service_client,
-        &mut fcm_client,
+        &redis_client,
+        &template_service_client,
+        &user_service_client,
+        &providers,
         &database_client,
+        &DiscardEvents,
+        &config,
     )
     .await;
 
@@ -218,7 +246,7 @@ async fn test_end_to_end_invalid_json_rejection() -> Result<()> {
 #[tokio::test]
 async fn test_end_to_end_complete_message_processing() -> Result<()> {
     let config = Config::load()?;
-    let mut redis_client = RedisClient::connect(&config).await?;
+    let redis_client = RedisClient::connect(&config).await?;
     let database_client = DatabaseClient::connect(&config.database_url).await?;
 
     let redis_for_cb = redis::Client::open(config.redis_url.as_str())?;
@@ -232,12 +260,13 @@ async fn test_end_to_end_complete_message_processing() -> Result<()> {
 
     let template_cb = CircuitBreaker::new(
         "template_service".to_string(),
-        redis_conn,
+        redis_conn.clone(),
         config.circuit_breaker_config(),
     );
 
-    let mut template_service_client = TemplateServiceClient::new(&config, template_cb).await?;
-    let mut fcm_client = FcmClient::new(&config, fcm_cb).await;
+    let template_service_client = TemplateServiceClient::new(&config, template_cb).await?;
+    let user_service_client = create_user_client(&config, redis_conn)?;
+    let providers = ProviderRegistry::new().register(FcmClient::new(&config, fcm_cb).await?);
 
     let mut variables = HashMap::new();
     variables.insert("user_name".to_string(), serde_json::json!("Alice"));
@@ -263,16 +292,22 @@ async fn test_end_to_end_complete_message_processing() -> Result<()> {
         metadata,
         created_by: "550e8400-e29b-41d4-a716-446655440000".to_string(),
         timestamp: chrono::Utc::now().to_rfc3339(),
+        send_at: None,
+        expires_at: None,
+        ttl_seconds: None,
     };
 
     let payload = serde_json::to_string(&message)?;
 
     let _ = process_message(
         &payload,
-        &mut redis_client,
-        &mut template_service_client,
-        &mut fcm_client,
+        &redis_client,
+        &template_service_client,
+        &user_service_client,
+        &providers,
         &database_client,
+        &DiscardEvents,
+        &config,
     )
     .await;
 
@@ -296,7 +331,7 @@ async fn test_end_to_end_graceful_degradation() -> Result<()> {
     setup();
 
     let config = Config::load()?;
-    let mut redis_client = RedisClient::connect(&config).await?;
+    let redis_client = RedisClient::connect(&config).await?;
     let database_client = DatabaseClient::connect(&config.database_url).await?;
 
     let redis_for_cb = redis::Client::open(config.redis_url.as_str())?;
@@ -310,22 +345,26 @@ async fn test_end_to_end_graceful_degradation() -> Result<()> {
 
     let template_cb = CircuitBreaker::new(
         "template_service".to_string(),
-        redis_conn,
+        redis_conn.clone(),
         config.circuit_breaker_config(),
     );
 
-    let mut template_service_client = TemplateServiceClient::new(&config, template_cb).await?;
-    let mut fcm_client = FcmClient::new(&config, fcm_cb).await;
+    let template_service_client = TemplateServiceClient::new(&config, template_cb).await?;
+    let user_service_client = create_user_client(&config, redis_conn)?;
+    let providers = ProviderRegistry::new().register(FcmClient::new(&config, fcm_cb).await?);
 
     let message = create_notification_message("e2e_degradation");
     let payload = serde_json::to_string(&message)?;
 
     let _ = process_message(
         &payload,
-        &mut redis_client,
-        &mut template_service_client,
-        &mut fcm_client,
+        &redis_client,
+        &template_service_client,
+        &user_service_client,
+        &providers,
         &database_client,
+        &DiscardEvents,
+        &config,
     )
     .await;
 
@@ -357,7 +396,7 @@ async fn test_end_to_end_high_throughput() -> Result<()> {
         let config_clone = config.clone();
 
         let handle = tokio::spawn(async move {
-            let mut redis = RedisClient::connect(&config_clone).await.unwrap();
+            let redis = RedisClient::connect(&config_clone).await.unwrap();
             let database = DatabaseClient::connect(&config_clone.database_url)
                 .await
                 .unwrap();
@@ -376,24 +415,29 @@ async fn test_end_to_end_high_throughput() -> Result<()> {
 
             let template_cb = CircuitBreaker::new(
                 format!("template_service_{}", i),
-                redis_conn,
+                redis_conn.clone(),
                 config_clone.circuit_breaker_config(),
             );
 
-            let mut template_service = TemplateServiceClient::new(&config_clone, template_cb)
+            let template_service = TemplateServiceClient::new(&config_clone, template_cb)
                 .await
                 .unwrap();
-            let mut fcm_client = FcmClient::new(&config_clone, fcm_cb).await;
+            let user_service = create_user_client(&config_clone, redis_conn).unwrap();
+            let providers = ProviderRegistry::new()
+                .register(FcmClient::new(&config_clone, fcm_cb).await.unwrap());
 
             let message = create_notification_message(&format!("throughput_{}", i));
             let payload = serde_json::to_string(&message).unwrap();
 
             let _ = process_message(
                 &payload,
-                &mut redis,
-                &mut template_service,
-                &mut fcm_client,
+                &redis,
+                &template_service,
+                &user_service,
+                &providers,
                 &database,
+                &DiscardEvents,
+                &config_clone,
             )
             .await;
 
@@ -453,7 +497,7 @@ async fn test_end_to_end_message_ordering() -> Result<()> {
         if let Some(Ok(delivery)) = consumer.next().await {
             let message: NotificationMessage = serde_json::from_slice(&delivery.data)?;
             received_ids.push(message.idempotency_key.clone());
-            rabbitmq.acknowledge(delivery.delivery_tag).await?;
+            rabbitmq.acknowledge(&delivery.acker).await?;
         }
     }
 
@@ -481,24 +525,28 @@ async fn test_end_to_end_redis_resilience() -> Result<()> {
 
     let template_cb = CircuitBreaker::new(
         "template_service".to_string(),
-        redis_conn,
+        redis_conn.clone(),
         config.circuit_breaker_config(),
     );
 
-    let mut template_service_client = TemplateServiceClient::new(&config, template_cb).await?;
-    let mut fcm_client = FcmClient::new(&config, fcm_cb).await;
+    let template_service_client = TemplateServiceClient::new(&config, template_cb).await?;
+    let user_service_client = create_user_client(&config, redis_conn)?;
+    let providers = ProviderRegistry::new().register(FcmClient::new(&config, fcm_cb).await?);
 
     let message = create_notification_message("redis_resilience");
     let payload = serde_json::to_string(&message)?;
 
-    let mut redis_client = RedisClient::connect(&config).await?;
+    let redis_client = RedisClient::connect(&config).await?;
 
     let _ = process_message(
         &payload,
-        &mut redis_client,
-        &mut template_service_client,
-        &mut fcm_client,
+        &redis_client,
+        &template_service_client,
+        &user_service_client,
+        &providers,
         &database_client,
+        &DiscardEvents,
+        &config,
     )
     .await;
 
@@ -547,9 +595,25 @@ fn create_notification_message(suffix: &str) -> NotificationMessage {
         metadata,
         created_by: "550e8400-e29b-41d4-a716-446655440000".to_string(),
         timestamp: chrono::Utc::now().to_rfc3339(),
+        send_at: None,
+        expires_at: None,
+        ttl_seconds: None,
     }
 }
 
+fn create_user_client(
+    config: &Config,
+    redis_conn: MultiplexedConnection,
+) -> Result<UserServiceClient> {
+    let user_cb = CircuitBreaker::new(
+        "user_service".to_string(),
+        redis_conn.clone(),
+        config.circuit_breaker_config(),
+    );
+
+    UserServiceClient::new(config, user_cb, redis_conn)
+}
+
 async fn publish_message(config: &Config, message: &NotificationMessage) -> Result<()> {
     let client =
         lapin::Connection::connect(&config.rabbitmq_url, lapin::ConnectionProperties::default())
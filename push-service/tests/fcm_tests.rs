@@ -0,0 +1,1022 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicU32, Ordering},
+    },
+    time::Duration,
+};
+
+use anyhow::{Error, Result};
+use async_trait::async_trait;
+use chrono::Utc;
+use gcp_auth::{Token, TokenProvider};
+use push_service::{
+    clients::{
+        circuit_breaker::CircuitBreaker, fcm::FcmClient, fcm_auth::FcmCredentials,
+        provider::PushProvider,
+    },
+    config::Config,
+    models::validation::{validate_fcm_condition, validate_fcm_topic},
+    models::{
+        circuit_breaker::CircuitState,
+        fcm::{FcmAuthMode, FcmError},
+        health::HealthStatus,
+        provider::{DeliveryErrorKind, PushNotification, PushOptions, PushPriority, PushTarget},
+    },
+    utils::parse_retry_after,
+};
+use uuid::Uuid;
+use wiremock::{
+    Mock, MockServer, ResponseTemplate,
+    matchers::{body_json, body_partial_json, header, method, path},
+};
+
+struct FakeTokenProvider {
+    expires_in_seconds: u64,
+    fail: AtomicBool,
+    calls: AtomicU32,
+}
+
+impl FakeTokenProvider {
+    fn new(expires_in_seconds: u64) -> Arc<Self> {
+        Arc::new(Self {
+            expires_in_seconds,
+            fail: AtomicBool::new(false),
+            calls: AtomicU32::new(0),
+        })
+    }
+}
+
+#[async_trait]
+impl TokenProvider for FakeTokenProvider {
+    async fn token(&self, _scopes: &[&str]) -> Result<Arc<Token>, gcp_auth::Error> {
+        let call = self.calls.fetch_add(1, Ordering::SeqCst);
+
+        if self.fail.load(Ordering::SeqCst) {
+            return Err(gcp_auth::Error::Str("credentials revoked"));
+        }
+
+        let token = serde_json::from_value(serde_json::json!({
+            "access_token": format!("token-{}", call),
+            "expires_in": self.expires_in_seconds
+        }))
+        .unwrap();
+
+        Ok(Arc::new(token))
+    }
+
+    async fn project_id(&self) -> Result<Arc<str>, gcp_auth::Error> {
+        Ok(Arc::from("test-project"))
+    }
+}
+
+/// Test: FCM v1 error details map onto typed errors and delivery kinds
+#[test]
+fn test_fcm_error_codes_map_to_error_kinds() {
+    let cases = [
+        (
+            404,
+            "NOT_FOUND",
+            "UNREGISTERED",
+            DeliveryErrorKind::InvalidToken,
+        ),
+        (
+            400,
+            "INVALID_ARGUMENT",
+            "INVALID_ARGUMENT",
+            DeliveryErrorKind::Permanent,
+        ),
+        (
+            403,
+            "PERMISSION_DENIED",
+            "SENDER_ID_MISMATCH",
+            DeliveryErrorKind::Permanent,
+        ),
+        (
+            429,
+            "RESOURCE_EXHAUSTED",
+            "QUOTA_EXCEEDED",
+            DeliveryErrorKind::Retryable,
+        ),
+        (
+            503,
+            "UNAVAILABLE",
+            "UNAVAILABLE",
+            DeliveryErrorKind::Retryable,
+        ),
+        (500, "INTERNAL", "INTERNAL", DeliveryErrorKind::Retryable),
+        (
+            401,
+            "UNAUTHENTICATED",
+            "THIRD_PARTY_AUTH_ERROR",
+            DeliveryErrorKind::Permanent,
+        ),
+    ];
+
+    for (status, rpc_status, error_code, expected) in cases {
+        let body = fcm_error_body(status, rpc_status, Some(error_code));
+        let error = FcmError::from_response(status, &body);
+
+        assert_eq!(error.code(), error_code, "Unexpected code for {}", body);
+        assert_eq!(error.kind(), expected, "Unexpected kind for {}", body);
+
+        // A misconfigured sender must not wipe the token registry
+        assert_eq!(
+            error.kind() == DeliveryErrorKind::InvalidToken,
+            error_code == "UNREGISTERED",
+            "Only UNREGISTERED should prune, got {}",
+            body
+        );
+    }
+}
+
+/// Test: FCM errors without details fall back to the RPC status and HTTP code
+#[test]
+fn test_fcm_error_falls_back_without_details() {
+    let body = fcm_error_body(400, "INVALID_ARGUMENT", None);
+    assert_eq!(
+        FcmError::from_response(400, &body),
+        FcmError::InvalidArgument {
+            message: "Request failed".to_string()
+        }
+    );
+
+    let error = FcmError::from_response(502, "<html>Bad Gateway</html>");
+    assert_eq!(error.code(), "UNSPECIFIED_ERROR");
+    assert_eq!(error.kind(), DeliveryErrorKind::Retryable);
+
+    let error = FcmError::from_response(404, "");
+    assert_eq!(error.code(), "NOT_FOUND");
+}
+
+/// Test: Generic RPC statuses never prune tokens; only FCM detail codes do
+#[test]
+fn test_generic_fcm_errors_do_not_invalidate_tokens() {
+    let cases = [
+        (404, "NOT_FOUND", "NOT_FOUND", DeliveryErrorKind::Permanent),
+        (
+            403,
+            "PERMISSION_DENIED",
+            "PERMISSION_DENIED",
+            DeliveryErrorKind::Permanent,
+        ),
+        (
+            401,
+            "UNAUTHENTICATED",
+            "UNAUTHENTICATED",
+            DeliveryErrorKind::Retryable,
+        ),
+    ];
+
+    for (status, rpc_status, code, expected) in cases {
+        let error = FcmError::from_response(status, &fcm_error_body(status, rpc_status, None));
+        assert_eq!(error.code(), code, "Unexpected code for {}", rpc_status);
+        assert_eq!(error.kind(), expected, "Unexpected kind for {}", rpc_status);
+    }
+
+    let bare = [
+        (404, "NOT_FOUND", DeliveryErrorKind::Permanent),
+        (403, "PERMISSION_DENIED", DeliveryErrorKind::Permanent),
+        (401, "UNAUTHENTICATED", DeliveryErrorKind::Retryable),
+    ];
+
+    for (status, code, expected) in bare {
+        let error = FcmError::from_response(status, "<html>Error</html>");
+        assert_eq!(error.code(), code, "Unexpected code for bare {}", status);
+        assert_eq!(
+            error.kind(),
+            expected,
+            "Unexpected kind for bare {}",
+            status
+        );
+    }
+}
+
+/// Test: Permanent FCM errors do not count towards opening the circuit
+#[tokio::test]
+async fn test_permanent_fcm_errors_do_not_trip_circuit() -> Result<()> {
+    let mut config = Config::load()?;
+    config.circuit_breaker_failure_threshold = 2;
+
+    let redis_client = redis::Client::open(config.redis_url.as_str())?;
+    let redis_conn = redis_client.get_multiplexed_async_connection().await?;
+
+    let breaker = CircuitBreaker::new(
+        format!("fcm_test_{}", Uuid::new_v4()),
+        redis_conn,
+        config.circuit_breaker_config(),
+    );
+
+    let is_retryable = |e: &Error| {
+        e.downcast_ref::<FcmError>()
+            .is_none_or(|e| e.kind() == DeliveryErrorKind::Retryable)
+    };
+
+    for _ in 0..3 {
+        let unregistered =
+            FcmError::from_response(404, &fcm_error_body(404, "NOT_FOUND", Some("UNREGISTERED")));
+
+        let result = breaker
+            .call_with_filter(|| async { Err::<(), _>(unregistered.into()) }, is_retryable)
+            .await;
+
+        assert!(result.unwrap_err().downcast_ref::<FcmError>().is_some());
+    }
+
+    assert!(
+        breaker
+            .call_with_filter(|| async { Ok(()) }, is_retryable)
+            .await
+            .is_ok(),
+        "Circuit should stay closed after permanent errors"
+    );
+
+    for _ in 0..2 {
+        let unavailable = FcmError::from_response(503, &fcm_error_body(503, "UNAVAILABLE", None));
+
+        let _ = breaker
+            .call_with_filter(|| async { Err::<(), _>(unavailable.into()) }, is_retryable)
+            .await;
+    }
+
+    assert!(
+        breaker
+            .call_with_filter(|| async { Ok(()) }, is_retryable)
+            .await
+            .is_err(),
+        "Circuit should open after retryable errors"
+    );
+
+    Ok(())
+}
+
+/// Test: An open circuit reports half-open once its timeout has passed
+#[tokio::test]
+async fn test_open_circuit_reports_half_open_after_timeout() -> Result<()> {
+    let mut config = Config::load()?;
+    config.circuit_breaker_failure_threshold = 2;
+    config.circuit_breaker_timeout_seconds = 1;
+
+    let redis_client = redis::Client::open(config.redis_url.as_str())?;
+    let redis_conn = redis_client.get_multiplexed_async_connection().await?;
+
+    let breaker = CircuitBreaker::new(
+        format!("fcm_test_{}", Uuid::new_v4()),
+        redis_conn,
+        config.circuit_breaker_config(),
+    );
+
+    assert_eq!(breaker.current_state().await?, CircuitState::Closed);
+
+    for _ in 0..2 {
+        let _ = breaker
+            .call(|| async { Err::<(), _>(anyhow::anyhow!("FCM unavailable")) })
+            .await;
+    }
+
+    let state = breaker.current_state().await?;
+    assert_eq!(state, CircuitState::Open);
+    assert!(!state.admits_requests());
+
+    tokio::time::sleep(Duration::from_millis(1_100)).await;
+
+    let state = breaker.current_state().await?;
+    assert_eq!(state, CircuitState::HalfOpen);
+    assert!(state.admits_requests());
+
+    Ok(())
+}
+
+/// Test: Retry-After is accepted as delta-seconds or an HTTP-date
+#[test]
+fn test_retry_after_header_is_parsed() {
+    assert_eq!(parse_retry_after("120"), Some(Duration::from_secs(120)));
+    assert_eq!(parse_retry_after("not a delay"), None);
+
+    let retry_at = (Utc::now() + chrono::Duration::seconds(30)).to_rfc2822();
+    let delay = parse_retry_after(&retry_at).expect("HTTP-date should parse");
+    assert!(delay > Duration::from_secs(25) && delay <= Duration::from_secs(30));
+
+    let past = (Utc::now() - chrono::Duration::seconds(30)).to_rfc2822();
+    assert_eq!(parse_retry_after(&past), Some(Duration::ZERO));
+}
+
+/// Test: Only throttling errors carry a server supplied delay
+#[test]
+fn test_retry_after_attaches_to_throttling_errors() {
+    let delay = Some(Duration::from_secs(30));
+
+    let quota = FcmError::from_response(429, "").with_retry_after(delay);
+    assert_eq!(quota.retry_after(), delay);
+
+    let unavailable = FcmError::from_response(503, "").with_retry_after(delay);
+    assert_eq!(unavailable.retry_after(), delay);
+
+    let unregistered = FcmError::from_response(404, "").with_retry_after(delay);
+    assert_eq!(unregistered.retry_after(), None);
+}
+
+/// Test: A backoff set by one worker is visible to every other worker
+#[tokio::test]
+async fn test_backoff_is_shared_across_workers() -> Result<()> {
+    let config = Config::load()?;
+
+    let redis_client = redis::Client::open(config.redis_url.as_str())?;
+    let redis_conn = redis_client.get_multiplexed_async_connection().await?;
+
+    let breaker = CircuitBreaker::new(
+        format!("fcm_test_{}", Uuid::new_v4()),
+        redis_conn,
+        config.circuit_breaker_config(),
+    );
+    let other_worker = breaker.clone();
+
+    assert_eq!(other_worker.backoff_remaining().await?, None);
+
+    breaker.extend_backoff(Duration::from_secs(30)).await?;
+
+    let remaining = other_worker
+        .backoff_remaining()
+        .await?
+        .expect("Backoff should be visible to other workers");
+    assert!(remaining > Duration::from_secs(25));
+
+    other_worker.extend_backoff(Duration::from_secs(1)).await?;
+    let remaining = breaker.backoff_remaining().await?.unwrap();
+    assert!(
+        remaining > Duration::from_secs(25),
+        "A shorter backoff should not cut an existing one short"
+    );
+
+    Ok(())
+}
+
+/// Test: Access tokens are cached instead of fetched on every send
+#[tokio::test]
+async fn test_access_token_is_cached_until_near_expiry() -> Result<()> {
+    let provider = FakeTokenProvider::new(3600);
+    let credentials = FcmCredentials::with_provider(provider.clone(), Duration::from_secs(60));
+
+    let first = credentials.token().await?;
+    let second = credentials.token().await?;
+
+    assert_eq!(first, second);
+    assert_eq!(provider.calls.load(Ordering::SeqCst), 1);
+
+    let short_lived = FakeTokenProvider::new(5);
+    let credentials = FcmCredentials::with_provider(short_lived.clone(), Duration::from_secs(60));
+
+    credentials.token().await?;
+    credentials.token().await?;
+
+    assert_eq!(
+        short_lived.calls.load(Ordering::SeqCst),
+        2,
+        "Tokens about to expire should not be reused"
+    );
+
+    Ok(())
+}
+
+/// Test: An invalidated access token is fetched again on the next send
+#[tokio::test]
+async fn test_invalidated_access_token_is_refetched() -> Result<()> {
+    let provider = FakeTokenProvider::new(3600);
+    let credentials = FcmCredentials::with_provider(provider.clone(), Duration::from_secs(60));
+
+    let first = credentials.token().await?;
+    credentials.invalidate();
+    let second = credentials.token().await?;
+
+    assert_eq!(provider.calls.load(Ordering::SeqCst), 2);
+    assert_ne!(first, second);
+
+    Ok(())
+}
+
+/// Test: A rejected access token is retried instead of failing the message
+#[tokio::test]
+async fn test_fcm_unauthenticated_response_is_retried() -> Result<()> {
+    let mock_server = MockServer::start().await;
+
+    let mut config = create_mock_config(&mock_server)?;
+    config.max_retry_attempts = 3;
+
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(401).set_body_string(fcm_error_body(
+            401,
+            "UNAUTHENTICATED",
+            None,
+        )))
+        .expect(3)
+        .mount(&mock_server)
+        .await;
+
+    let client = create_fcm_client(&config).await?;
+
+    let error = client
+        .send(&create_notification())
+        .await
+        .expect_err("Rejected access token should fail");
+
+    assert_eq!(client.classify_error(&error), DeliveryErrorKind::Retryable);
+    assert_eq!(
+        client.error_code(&error).as_deref(),
+        Some("UNAUTHENTICATED")
+    );
+
+    Ok(())
+}
+
+/// Test: Background refresh warms the cache before the first send
+#[tokio::test]
+async fn test_background_refresh_fetches_token() -> Result<()> {
+    let provider = FakeTokenProvider::new(3600);
+    let credentials = FcmCredentials::with_provider(provider.clone(), Duration::from_secs(60));
+
+    assert_eq!(credentials.health().status, HealthStatus::Degraded);
+
+    let refresh = credentials.spawn_refresh().unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    assert_eq!(provider.calls.load(Ordering::SeqCst), 1);
+    assert_eq!(credentials.health().status, HealthStatus::Healthy);
+
+    credentials.token().await?;
+    assert_eq!(
+        provider.calls.load(Ordering::SeqCst),
+        1,
+        "Send path should reuse the refreshed token"
+    );
+
+    refresh.abort();
+
+    Ok(())
+}
+
+/// Test: Credential failures surface in the fcm_auth health check
+#[tokio::test]
+async fn test_credential_failures_are_reported_in_health() -> Result<()> {
+    let provider = FakeTokenProvider::new(3600);
+    provider.fail.store(true, Ordering::SeqCst);
+
+    let credentials = FcmCredentials::with_provider(provider.clone(), Duration::from_secs(60));
+
+    assert!(credentials.token().await.is_err());
+
+    let health = credentials.health();
+    assert_eq!(health.status, HealthStatus::Unhealthy);
+    assert!(health.error.unwrap().contains("credentials revoked"));
+
+    provider.fail.store(false, Ordering::SeqCst);
+    credentials.token().await?;
+
+    assert_eq!(credentials.health().status, HealthStatus::Healthy);
+
+    Ok(())
+}
+
+/// Test: Requests go to the configured base URL with the exact v1 payload
+#[tokio::test]
+async fn test_fcm_send_posts_v1_payload_to_base_url() -> Result<()> {
+    let mock_server = MockServer::start().await;
+
+    let mut config = create_mock_config(&mock_server)?;
+    config.fcm_auth_mode = FcmAuthMode::StaticToken;
+    config.fcm_static_token = Some("static-test-token".to_string());
+
+    Mock::given(method("POST"))
+        .and(path(format!(
+            "/v1/projects/{}/messages:send",
+            config.fcm_project_id
+        )))
+        .and(header("authorization", "Bearer static-test-token"))
+        .and(body_json(serde_json::json!({
+            "message": {
+                "token": "device_token_abc123",
+                "notification": {
+                    "title": "Order shipped",
+                    "body": "Arriving today"
+                },
+                "data": {
+                    "order_id": "42",
+                    "trace_id": "req_fcm_001"
+                },
+                "android": {
+                    "priority": "HIGH"
+                },
+                "apns": {
+                    "headers": {
+                        "apns-push-type": "alert",
+                        "apns-priority": "10"
+                    },
+                    "payload": {
+                        "aps": {}
+                    }
+                },
+                "webpush": {
+                    "headers": {
+                        "Urgency": "high"
+                    }
+                }
+            }
+        })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "name": "projects/test-project/messages/0:1"
+        })))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let client = create_fcm_client(&config).await?;
+
+    let receipt = client.send(&create_notification()).await?;
+
+    assert_eq!(
+        receipt.message_id.as_deref(),
+        Some("projects/test-project/messages/0:1")
+    );
+
+    Ok(())
+}
+
+/// Test: An accepted message with an unreadable body is not sent again
+#[tokio::test]
+async fn test_fcm_success_with_unparseable_body_is_not_retried() -> Result<()> {
+    let mock_server = MockServer::start().await;
+
+    let mut config = create_mock_config(&mock_server)?;
+    config.max_retry_attempts = 3;
+
+    Mock::given(method("POST"))
+        .and(path(format!(
+            "/v1/projects/{}/messages:send",
+            config.fcm_project_id
+        )))
+        .respond_with(ResponseTemplate::new(200).set_body_string("<html>ok</html>"))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let client = create_fcm_client(&config).await?;
+
+    let receipt = client.send(&create_notification()).await?;
+
+    assert_eq!(receipt.message_id, None);
+
+    Ok(())
+}
+
+/// Test: Unregistered tokens from the stand-in are classified without retrying
+#[tokio::test]
+async fn test_fcm_unregistered_response_is_invalid_token() -> Result<()> {
+    let mock_server = MockServer::start().await;
+
+    let mut config = create_mock_config(&mock_server)?;
+    config.max_retry_attempts = 3;
+
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(404).set_body_string(fcm_error_body(
+            404,
+            "NOT_FOUND",
+            Some("UNREGISTERED"),
+        )))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let client = create_fcm_client(&config).await?;
+
+    let error = client
+        .send(&create_notification())
+        .await
+        .expect_err("Unregistered token should fail");
+
+    assert_eq!(
+        client.classify_error(&error),
+        DeliveryErrorKind::InvalidToken
+    );
+    assert_eq!(client.error_code(&error).as_deref(), Some("UNREGISTERED"));
+
+    Ok(())
+}
+
+/// Test: Auth mode none sends requests without an Authorization header
+#[tokio::test]
+async fn test_fcm_auth_mode_none_omits_authorization() -> Result<()> {
+    let mock_server = MockServer::start().await;
+
+    let config = create_mock_config(&mock_server)?;
+
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "name": "projects/test-project/messages/0:2"
+        })))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let client = create_fcm_client(&config).await?;
+    client.send(&create_notification()).await?;
+
+    let requests = mock_server.received_requests().await.unwrap();
+    assert!(!requests[0].headers.contains_key("authorization"));
+
+    Ok(())
+}
+
+/// Test: Per-platform overrides are mapped onto the v1 message
+#[tokio::test]
+async fn test_fcm_message_includes_platform_overrides() -> Result<()> {
+    let mock_server = MockServer::start().await;
+    let config = create_mock_config(&mock_server)?;
+
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "name": "projects/test-project/messages/0:3"
+        })))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let client = create_fcm_client(&config).await?;
+
+    let mut notification = create_notification();
+    notification.priority = PushPriority::Normal;
+    notification.collapse_key = Some("order42".to_string());
+    notification.options = PushOptions {
+        image: Some("https://cdn.example.com/order42.png".to_string()),
+        icon: Some("ic_shipping".to_string()),
+        color: Some("#ff6600".to_string()),
+        sound: Some("chime.caf".to_string()),
+        badge: Some(3),
+        category: Some("ORDER_UPDATE".to_string()),
+        channel_id: Some("orders".to_string()),
+        click_action: Some("OPEN_ORDER".to_string()),
+        link: Some("https://shop.example.com/orders/42".to_string()),
+        analytics_label: Some("order_shipped".to_string()),
+        ..Default::default()
+    };
+
+    client.send(&notification).await?;
+
+    let requests = mock_server.received_requests().await.unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&requests[0].body)?;
+
+    assert_eq!(
+        body["message"],
+        serde_json::json!({
+            "token": "device_token_abc123",
+            "notification": {
+                "title": "Order shipped",
+                "body": "Arriving today",
+                "image": "https://cdn.example.com/order42.png"
+            },
+            "data": {
+                "order_id": "42",
+                "trace_id": "req_fcm_001"
+            },
+            "android": {
+                "priority": "NORMAL",
+                "collapse_key": "order42",
+                "notification": {
+                    "icon": "ic_shipping",
+                    "color": "#ff6600",
+                    "sound": "chime.caf",
+                    "click_action": "OPEN_ORDER",
+                    "channel_id": "orders"
+                }
+            },
+            "apns": {
+                "headers": {
+                    "apns-push-type": "alert",
+                    "apns-priority": "5",
+                    "apns-collapse-id": "order42"
+                },
+                "payload": {
+                    "aps": {
+                        "badge": 3,
+                        "sound": "chime.caf",
+                        "category": "ORDER_UPDATE",
+                        "mutable-content": 1
+                    }
+                }
+            },
+            "webpush": {
+                "headers": {
+                    "Urgency": "normal"
+                },
+                "fcm_options": {
+                    "link": "https://shop.example.com/orders/42"
+                }
+            },
+            "fcm_options": {
+                "analytics_label": "order_shipped"
+            }
+        })
+    );
+
+    Ok(())
+}
+
+/// Test: Data-only messages drop the notification block and go out silently
+#[tokio::test]
+async fn test_fcm_data_only_message_is_silent() -> Result<()> {
+    let mock_server = MockServer::start().await;
+    let config = create_mock_config(&mock_server)?;
+
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "name": "projects/test-project/messages/0:4"
+        })))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let client = create_fcm_client(&config).await?;
+
+    let mut notification = create_notification();
+    notification.expires_at = Some(Utc::now() + chrono::Duration::seconds(600));
+    notification.options = PushOptions {
+        data_only: true,
+        sound: Some("chime.caf".to_string()),
+        ..Default::default()
+    };
+
+    client.send(&notification).await?;
+
+    let requests = mock_server.received_requests().await.unwrap();
+    let message = serde_json::from_slice::<serde_json::Value>(&requests[0].body)?["message"].take();
+
+    assert!(message.get("notification").is_none());
+    assert_eq!(message["data"]["title"], "Order shipped");
+    assert_eq!(message["data"]["body"], "Arriving today");
+    assert!(message["android"].get("notification").is_none());
+    assert!(
+        matches!(message["android"]["ttl"].as_str(), Some("599s" | "600s")),
+        "TTL should follow expires_at"
+    );
+    assert_eq!(message["apns"]["headers"]["apns-push-type"], "background");
+    assert_eq!(message["apns"]["headers"]["apns-priority"], "5");
+    assert_eq!(
+        message["apns"]["payload"]["aps"],
+        serde_json::json!({ "content-available": 1 })
+    );
+
+    Ok(())
+}
+
+/// Test: Topic and condition targets replace the token in the v1 message
+#[tokio::test]
+async fn test_fcm_sends_to_topics_and_conditions() -> Result<()> {
+    let mock_server = MockServer::start().await;
+    let config = create_mock_config(&mock_server)?;
+
+    Mock::given(method("POST"))
+        .and(body_partial_json(serde_json::json!({
+            "message": { "topic": "promo_ng" }
+        })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "name": "projects/test-project/messages/5"
+        })))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let condition = "'sports' in topics && 'ng' in topics";
+
+    Mock::given(method("POST"))
+        .and(body_partial_json(serde_json::json!({
+            "message": { "condition": condition }
+        })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "name": "projects/test-project/messages/6"
+        })))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let client = create_fcm_client(&config).await?;
+
+    let mut notification = create_notification();
+    notification.target = PushTarget::Topic("promo_ng".to_string());
+    client.validate_target(&notification.target)?;
+    client.send(&notification).await?;
+
+    notification.target = PushTarget::Condition(condition.to_string());
+    client.validate_target(&notification.target)?;
+    client.send(&notification).await?;
+
+    for request in mock_server.received_requests().await.unwrap() {
+        let body: serde_json::Value = serde_json::from_slice(&request.body)?;
+        assert!(
+            body["message"].get("token").is_none(),
+            "Broadcasts should not carry a device token"
+        );
+    }
+
+    Ok(())
+}
+
+/// Test: Malformed topic names and conditions are rejected up front
+#[test]
+fn test_topic_and_condition_validation() {
+    assert!(validate_fcm_topic("promo_ng").is_ok());
+    assert!(validate_fcm_topic("").is_err());
+    assert!(validate_fcm_topic("promo ng").is_err());
+    assert!(validate_fcm_topic(&"a".repeat(901)).is_err());
+
+    assert!(validate_fcm_condition("'sports' in topics && 'ng' in topics").is_ok());
+    assert!(validate_fcm_condition("!('a' in topics) || ('b' in topics && 'c' in topics)").is_ok());
+    assert!(validate_fcm_condition("").is_err());
+    assert!(validate_fcm_condition("'sports in topics").is_err());
+    assert!(validate_fcm_condition("'sports' in topics; DROP").is_err());
+    assert!(
+        validate_fcm_condition(
+            "'a' in topics || 'b' in topics || 'c' in topics || 'd' in topics || 'e' in topics || 'f' in topics"
+        )
+        .is_err()
+    );
+}
+
+/// Test: Topic subscriptions go through IID batchAdd/batchRemove in chunks
+#[tokio::test]
+async fn test_topic_subscriptions_use_iid_batches() -> Result<()> {
+    let mock_server = MockServer::start().await;
+
+    let mut config = create_mock_config(&mock_server)?;
+    config.fcm_iid_base_url = mock_server.uri();
+
+    let tokens: Vec<String> = (0..1001)
+        .map(|i| format!("device_token_{:08}", i))
+        .collect();
+
+    Mock::given(method("POST"))
+        .and(path("/iid/v1:batchAdd"))
+        .and(header("access_token_auth", "true"))
+        .and(body_partial_json(
+            serde_json::json!({ "to": "/topics/promo_ng" }),
+        ))
+        .respond_with(|request: &wiremock::Request| {
+            let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+            let results: Vec<serde_json::Value> = body["registration_tokens"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|token| match token.as_str() {
+                    Some("device_token_00001000") => serde_json::json!({ "error": "NOT_FOUND" }),
+                    _ => serde_json::json!({}),
+                })
+                .collect();
+
+            ResponseTemplate::new(200).set_body_json(serde_json::json!({ "results": results }))
+        })
+        .expect(2)
+        .mount(&mock_server)
+        .await;
+
+    Mock::given(method("POST"))
+        .and(path("/iid/v1:batchRemove"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "results": [{}]
+        })))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let client = create_fcm_client(&config).await?;
+
+    let result = client.subscribe_to_topic("promo_ng", &tokens).await?;
+
+    assert_eq!(result.success_count, 1000);
+    assert_eq!(result.failure_count, 1);
+    assert_eq!(result.errors[0].index, 1000);
+    assert_eq!(result.errors[0].token, "device_token_00001000");
+    assert_eq!(result.errors[0].reason, "NOT_FOUND");
+
+    let result = client
+        .unsubscribe_from_topic("promo_ng", &tokens[..1])
+        .await?;
+    assert_eq!(result.success_count, 1);
+
+    assert!(
+        client
+            .subscribe_to_topic("promo ng", &tokens[..1])
+            .await
+            .is_err()
+    );
+
+    Ok(())
+}
+
+/// Test: A throttled IID batch carries Retry-After and shares the backoff
+#[tokio::test]
+async fn test_topic_subscriptions_honor_retry_after() -> Result<()> {
+    let mock_server = MockServer::start().await;
+
+    let mut config = create_mock_config(&mock_server)?;
+    config.fcm_iid_base_url = mock_server.uri();
+
+    Mock::given(method("POST"))
+        .and(path("/iid/v1:batchAdd"))
+        .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "30"))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let redis_client = redis::Client::open(config.redis_url.as_str())?;
+    let redis_conn = redis_client.get_multiplexed_async_connection().await?;
+    let breaker = CircuitBreaker::new(
+        format!("fcm_test_{}", Uuid::new_v4()),
+        redis_conn,
+        config.circuit_breaker_config(),
+    );
+    let client = FcmClient::new(&config, breaker.clone()).await?;
+
+    let error = client
+        .subscribe_to_topic("promo", &["device_token_abc123".to_string()])
+        .await
+        .expect_err("A throttled batch should fail once retries run out");
+
+    let fcm_error = error
+        .downcast_ref::<FcmError>()
+        .expect("IID rejections should surface as FcmError");
+    assert_eq!(fcm_error.retry_after(), Some(Duration::from_secs(30)));
+
+    let remaining = breaker
+        .backoff_remaining()
+        .await?
+        .expect("The IID quota backoff should be shared with other workers");
+    assert!(remaining > Duration::from_secs(25));
+
+    Ok(())
+}
+
+/// Test: Static token mode refuses to start without a token
+#[test]
+fn test_static_token_mode_requires_token() -> Result<()> {
+    let mut config = Config::load()?;
+    config.fcm_auth_mode = FcmAuthMode::StaticToken;
+    config.fcm_static_token = None;
+
+    assert!(FcmCredentials::from_config(&config).is_err());
+
+    Ok(())
+}
+
+fn create_mock_config(mock_server: &MockServer) -> Result<Config> {
+    let mut config = Config::load()?;
+    config.fcm_base_url = format!("{}/", mock_server.uri());
+    config.fcm_auth_mode = FcmAuthMode::None;
+    config.max_retry_attempts = 1;
+
+    Ok(config)
+}
+
+async fn create_fcm_client(config: &Config) -> Result<FcmClient> {
+    let redis_client = redis::Client::open(config.redis_url.as_str())?;
+    let redis_conn = redis_client.get_multiplexed_async_connection().await?;
+
+    let circuit_breaker = CircuitBreaker::new(
+        format!("fcm_test_{}", Uuid::new_v4()),
+        redis_conn,
+        config.circuit_breaker_config(),
+    );
+
+    FcmClient::new(config, circuit_breaker).await
+}
+
+fn create_notification() -> PushNotification {
+    PushNotification {
+        target: PushTarget::Token("device_token_abc123".to_string()),
+        title: "Order shipped".to_string(),
+        body: "Arriving today".to_string(),
+        trace_id: "req_fcm_001".to_string(),
+        data: Some(HashMap::from([("order_id".to_string(), "42".to_string())])),
+        priority: PushPriority::High,
+        collapse_key: None,
+        expires_at: None,
+        options: PushOptions::default(),
+    }
+}
+
+fn fcm_error_body(status: u16, rpc_status: &str, error_code: Option<&str>) -> String {
+    let details = match error_code {
+        Some(code) => serde_json::json!([{
+            "@type": "type.googleapis.com/google.firebase.fcm.v1.FcmError",
+            "errorCode": code
+        }]),
+        None => serde_json::json!([]),
+    };
+
+    serde_json::json!({
+        "error": {
+            "code": status,
+            "message": "Request failed",
+            "status": rpc_status,
+            "details": details
+        }
+    })
+    .to_string()
+}
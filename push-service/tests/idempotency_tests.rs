@@ -2,7 +2,9 @@ use std::sync::Arc;
 
 use anyhow::Result;
 use push_service::{
-    clients::redis::RedisClient, config::Config, models::status::IdempotencyStatus,
+    clients::redis::{RedisClient, device_idempotency_key},
+    config::Config,
+    models::status::IdempotencyStatus,
 };
 use redis::AsyncCommands;
 use tokio::time::sleep;
@@ -11,7 +13,7 @@ use tokio::time::sleep;
 #[tokio::test]
 async fn test_duplicate_messages_are_deduplicated() -> Result<()> {
     let config = Config::load()?;
-    let mut redis_client = RedisClient::connect(&config).await?;
+    let redis_client = RedisClient::connect(&config).await?;
 
     let idempotency_key = format!("test_dedup_{}", uuid::Uuid::new_v4());
 
@@ -35,7 +37,7 @@ async fn test_duplicate_messages_are_deduplicated() -> Result<()> {
 #[tokio::test]
 async fn test_message_state_transitions() -> Result<()> {
     let config = Config::load()?;
-    let mut redis_client = RedisClient::connect(&config).await?;
+    let redis_client = RedisClient::connect(&config).await?;
 
     let idempotency_key = format!("test_states_{}", uuid::Uuid::new_v4());
 
@@ -61,7 +63,7 @@ async fn test_idempotency_keys_expire_after_ttl() -> Result<()> {
     let mut config = Config::load()?;
     config.idempotency_ttl_seconds = 2;
 
-    let mut redis_client = RedisClient::connect(&config).await?;
+    let redis_client = RedisClient::connect(&config).await?;
     let idempotency_key = format!("test_ttl_{}", uuid::Uuid::new_v4());
 
     redis_client.mark_as_sent(&idempotency_key).await?;
@@ -90,7 +92,7 @@ async fn test_concurrent_idempotency_checks_are_safe() -> Result<()> {
         let key_clone = idempotency_key.clone();
 
         let handle = tokio::spawn(async move {
-            let mut redis_client = RedisClient::connect(&config_clone).await.unwrap();
+            let redis_client = RedisClient::connect(&config_clone).await.unwrap();
 
             let status = redis_client.check_idempotency(&key_clone).await.unwrap();
 
@@ -119,11 +121,52 @@ async fn test_concurrent_idempotency_checks_are_safe() -> Result<()> {
     Ok(())
 }
 
+/// Test: Releasing an interrupted message only clears keys still in processing
+#[tokio::test]
+async fn test_release_unfinished_keeps_settled_devices() -> Result<()> {
+    let config = Config::load()?;
+    let redis_client = RedisClient::connect(&config).await?;
+
+    let idempotency_key = format!("test_release_{}", uuid::Uuid::new_v4());
+    let interrupted_device = device_idempotency_key(&idempotency_key, "device_1");
+    let delivered_device = device_idempotency_key(&idempotency_key, "device_2");
+
+    redis_client.mark_as_processing(&idempotency_key).await?;
+    redis_client
+        .mark_device_as_processing(&idempotency_key, "device_1")
+        .await?;
+    redis_client
+        .mark_device_as_processing(&idempotency_key, "device_2")
+        .await?;
+    redis_client.mark_as_sent(&delivered_device).await?;
+
+    let released = redis_client.release_unfinished(&idempotency_key).await?;
+    assert_eq!(released, 2);
+
+    assert_eq!(
+        redis_client.check_idempotency(&idempotency_key).await?,
+        IdempotencyStatus::NotFound
+    );
+    assert_eq!(
+        redis_client.check_idempotency(&interrupted_device).await?,
+        IdempotencyStatus::NotFound
+    );
+    assert_eq!(
+        redis_client.check_idempotency(&delivered_device).await?,
+        IdempotencyStatus::Sent
+    );
+
+    cleanup_redis_key(&config, &delivered_device).await?;
+    cleanup_redis_key(&config, &format!("{}:devices", idempotency_key)).await?;
+
+    Ok(())
+}
+
 /// Test: System correctly marks failed messages
 #[tokio::test]
 async fn test_failed_message_tracking() -> Result<()> {
     let config = Config::load()?;
-    let mut redis_client = RedisClient::connect(&config).await?;
+    let redis_client = RedisClient::connect(&config).await?;
 
     let idempotency_key = format!("test_failed_{}", uuid::Uuid::new_v4());
 
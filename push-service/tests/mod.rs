@@ -1,4 +1,12 @@
+pub mod apns_tests;
+pub mod concurrency_tests;
+pub mod device_tests;
 pub mod e2e_tests;
+pub mod fcm_tests;
 pub mod idempotency_tests;
+pub mod provider_tests;
 pub mod queue_tests;
 pub mod retry_tests;
+pub mod scheduler_tests;
+pub mod user_service_tests;
+pub mod webpush_tests;
@@ -0,0 +1,1585 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{Error, Result, anyhow};
+use async_trait::async_trait;
+use push_service::{
+    clients::{
+        circuit_breaker::CircuitBreaker,
+        database::DatabaseClient,
+        events::EventPublisher,
+        provider::{ProviderRegistry, PushProvider},
+        redis::RedisClient,
+        template::TemplateServiceClient,
+        user::UserServiceClient,
+    },
+    config::Config,
+    models::{
+        device::{DevicePlatform, DeviceTarget, RegisterDeviceRequest},
+        event::{
+            PUSH_DELIVERED, PUSH_EXPIRED, PUSH_FAILED, PUSH_OUTCOME_VERSION, PushOutcomeEvent,
+        },
+        message::NotificationMessage,
+        provider::{DeliveryErrorKind, PushNotification, PushOptions, PushTarget, SendReceipt},
+        schedule::{ScheduleReason, ScheduleStatus},
+        status::{IdempotencyStatus, NotificationStatus},
+        validation::validate_fcm_token,
+    },
+    utils::{ProcessingOutcome, is_retryable, process_message},
+};
+use uuid::Uuid;
+use wiremock::{
+    Mock, MockServer, ResponseTemplate,
+    matchers::{method, path},
+};
+
+#[derive(Clone)]
+struct FakeProvider {
+    name: String,
+    fail: bool,
+    error_kind: DeliveryErrorKind,
+    failing_targets: Arc<Mutex<Vec<String>>>,
+    sent: Arc<Mutex<Vec<PushNotification>>>,
+}
+
+impl FakeProvider {
+    fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            fail: false,
+            error_kind: DeliveryErrorKind::Permanent,
+            failing_targets: Arc::new(Mutex::new(Vec::new())),
+            sent: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    fn failing(name: &str) -> Self {
+        Self {
+            fail: true,
+            ..Self::new(name)
+        }
+    }
+
+    fn with_error_kind(mut self, kind: DeliveryErrorKind) -> Self {
+        self.error_kind = kind;
+        self
+    }
+
+    fn fail_for(&self, targets: &[&str]) {
+        *self.failing_targets.lock().unwrap() = targets.iter().map(|t| t.to_string()).collect();
+    }
+
+    fn sent(&self) -> Vec<PushNotification> {
+        self.sent.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl PushProvider for FakeProvider {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn send(&self, notification: &PushNotification) -> Result<SendReceipt, Error> {
+        let target = notification.target.identifier().to_string();
+
+        if self.fail || self.failing_targets.lock().unwrap().contains(&target) {
+            return Err(anyhow!("Fake provider rejected notification"));
+        }
+
+        self.sent.lock().unwrap().push(notification.clone());
+
+        Ok(SendReceipt {
+            message_id: Some(format!("{}-{}", self.name, Uuid::new_v4())),
+        })
+    }
+
+    fn classify_error(&self, _error: &Error) -> DeliveryErrorKind {
+        self.error_kind
+    }
+
+    fn validate_target(&self, target: &PushTarget) -> Result<(), Error> {
+        match target {
+            PushTarget::Token(token) => validate_fcm_token(token),
+            other => Err(anyhow!(
+                "{} does not support {} targets",
+                self.name,
+                other.kind()
+            )),
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+struct RecordingPublisher {
+    events: Arc<Mutex<Vec<(String, serde_json::Value)>>>,
+}
+
+impl RecordingPublisher {
+    fn events(&self) -> Vec<(String, serde_json::Value)> {
+        self.events.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl EventPublisher for RecordingPublisher {
+    async fn publish(&self, routing_key: &str, payload: &[u8]) -> Result<(), Error> {
+        let event = serde_json::from_slice(payload)?;
+        self.events
+            .lock()
+            .unwrap()
+            .push((routing_key.to_string(), event));
+        Ok(())
+    }
+}
+
+/// Test: The first registered provider is used when none is requested
+#[test]
+fn test_registry_resolves_default_provider() -> Result<()> {
+    let registry = ProviderRegistry::new()
+        .register(FakeProvider::new("fcm"))
+        .register(FakeProvider::new("apns"));
+
+    assert_eq!(registry.resolve(None)?.name(), "fcm");
+    assert_eq!(registry.resolve(Some("apns"))?.name(), "apns");
+
+    Ok(())
+}
+
+/// Test: The default provider can be overridden explicitly
+#[test]
+fn test_registry_default_can_be_overridden() -> Result<()> {
+    let registry = ProviderRegistry::new()
+        .register(FakeProvider::new("fcm"))
+        .register(FakeProvider::new("apns"))
+        .with_default("apns");
+
+    assert_eq!(registry.resolve(None)?.name(), "apns");
+
+    Ok(())
+}
+
+/// Test: Unknown providers and empty registries are rejected
+#[test]
+fn test_registry_rejects_unknown_provider() {
+    let registry = ProviderRegistry::new().register(FakeProvider::new("fcm"));
+
+    assert!(registry.resolve(Some("carrier_pigeon")).is_err());
+    assert!(ProviderRegistry::new().resolve(None).is_err());
+}
+
+/// Test: Messages are delivered through the provider named in metadata
+#[tokio::test]
+async fn test_process_message_uses_injected_provider() -> Result<()> {
+    let (mut config, mock_server) = setup_mock_services().await?;
+    config.template_service_url = mock_server.uri();
+    config.user_service_url = mock_server.uri();
+
+    let redis_client = RedisClient::connect(&config).await?;
+    let database_client = DatabaseClient::connect(&config.database_url).await?;
+    let template_service_client = create_template_client(&config).await?;
+    let user_service_client = create_user_client(&config).await?;
+    let events = RecordingPublisher::default();
+
+    let fcm = FakeProvider::new("fcm");
+    let apns = FakeProvider::new("apns");
+    let providers = ProviderRegistry::new()
+        .register(fcm.clone())
+        .register(apns.clone());
+
+    let message = create_notification_message(Some("apns"));
+    let payload = envelope(&message)?;
+    mount_user(&mock_server, &message.user_id, metadata_token(&message)).await;
+
+    process_message(
+        &payload,
+        &redis_client,
+        &template_service_client,
+        &user_service_client,
+        &providers,
+        &database_client,
+        &events,
+        &config,
+    )
+    .await?;
+
+    let sent = apns.sent();
+    assert_eq!(sent.len(), 1, "APNs fake should receive the notification");
+    assert!(fcm.sent().is_empty(), "FCM fake should not be used");
+    assert_eq!(sent[0].title, "Hello Ada");
+    assert_eq!(sent[0].body, "Your order ord_42 shipped");
+    assert_eq!(sent[0].trace_id, message.request_id);
+
+    let status = redis_client
+        .check_idempotency(&message.idempotency_key)
+        .await?;
+    assert_eq!(status, IdempotencyStatus::Sent);
+
+    cleanup_redis_key(&config, &message.idempotency_key).await?;
+
+    Ok(())
+}
+
+/// Test: Provider failures mark the message as failed
+#[tokio::test]
+async fn test_process_message_marks_failed_on_provider_error() -> Result<()> {
+    let (mut config, mock_server) = setup_mock_services().await?;
+    config.template_service_url = mock_server.uri();
+    config.user_service_url = mock_server.uri();
+
+    let redis_client = RedisClient::connect(&config).await?;
+    let database_client = DatabaseClient::connect(&config.database_url).await?;
+    let template_service_client = create_template_client(&config).await?;
+    let user_service_client = create_user_client(&config).await?;
+    let events = RecordingPublisher::default();
+
+    let providers = ProviderRegistry::new().register(FakeProvider::failing("fcm"));
+
+    let message = create_notification_message(None);
+    let payload = envelope(&message)?;
+    mount_user(&mock_server, &message.user_id, metadata_token(&message)).await;
+
+    let result = process_message(
+        &payload,
+        &redis_client,
+        &template_service_client,
+        &user_service_client,
+        &providers,
+        &database_client,
+        &events,
+        &config,
+    )
+    .await;
+
+    assert!(result.is_err(), "Provider error should fail processing");
+
+    let status = redis_client
+        .check_idempotency(&message.idempotency_key)
+        .await?;
+    assert_eq!(status, IdempotencyStatus::Failed);
+
+    cleanup_redis_key(&config, &message.idempotency_key).await?;
+
+    Ok(())
+}
+
+/// Test: Push options and data come from metadata, falling back to the template
+#[tokio::test]
+async fn test_process_message_builds_push_options() -> Result<()> {
+    let (mut config, mock_server) = setup_mock_services().await?;
+    config.template_service_url = mock_server.uri();
+    config.user_service_url = mock_server.uri();
+
+    let redis_client = RedisClient::connect(&config).await?;
+    let database_client = DatabaseClient::connect(&config.database_url).await?;
+    let template_service_client = create_template_client(&config).await?;
+    let user_service_client = create_user_client(&config).await?;
+    let events = RecordingPublisher::default();
+
+    let fcm = FakeProvider::new("fcm");
+    let providers = ProviderRegistry::new().register(fcm.clone());
+
+    let mut message = create_notification_message(None);
+    mount_user(&mock_server, &message.user_id, metadata_token(&message)).await;
+    message.metadata.insert(
+        "push_options".to_string(),
+        serde_json::json!({
+            "channel_id": "orders",
+            "badge": 2,
+            "link": "https://shop.example.com/track/ord_42"
+        }),
+    );
+    message.metadata.insert(
+        "push_data".to_string(),
+        serde_json::json!({ "order_id": "ord_42", "items": 3 }),
+    );
+
+    process_message(
+        &envelope(&message)?,
+        &redis_client,
+        &template_service_client,
+        &user_service_client,
+        &providers,
+        &database_client,
+        &events,
+        &config,
+    )
+    .await?;
+
+    let sent = fcm.sent();
+    assert_eq!(
+        sent[0].options,
+        PushOptions {
+            image: Some("https://cdn.example.com/orders/ord_42.png".to_string()),
+            link: Some("https://shop.example.com/track/ord_42".to_string()),
+            channel_id: Some("orders".to_string()),
+            badge: Some(2),
+            ..Default::default()
+        }
+    );
+    assert_eq!(
+        sent[0].data,
+        Some(HashMap::from([
+            ("order_id".to_string(), "ord_42".to_string()),
+            ("items".to_string(), "3".to_string()),
+        ]))
+    );
+
+    cleanup_redis_key(&config, &message.idempotency_key).await?;
+
+    Ok(())
+}
+
+/// Test: Every listed device is notified and the outcome is aggregated
+#[tokio::test]
+async fn test_process_message_fans_out_to_all_devices() -> Result<()> {
+    let (mut config, mock_server) = setup_mock_services().await?;
+    config.template_service_url = mock_server.uri();
+    config.user_service_url = mock_server.uri();
+
+    let redis_client = RedisClient::connect(&config).await?;
+    let database_client = DatabaseClient::connect(&config.database_url).await?;
+    let template_service_client = create_template_client(&config).await?;
+    let user_service_client = create_user_client(&config).await?;
+    let events = RecordingPublisher::default();
+
+    let fcm = FakeProvider::new("fcm");
+    let providers = ProviderRegistry::new().register(fcm.clone());
+
+    let tokens: Vec<String> = (0..3)
+        .map(|_| format!("device_token_{}", Uuid::new_v4().simple()))
+        .collect();
+    fcm.fail_for(&[&tokens[2]]);
+
+    let mut message = create_notification_message(None);
+    message.metadata.remove("push_token");
+    message
+        .metadata
+        .insert("push_tokens".to_string(), serde_json::json!(tokens));
+
+    let payload = envelope(&message)?;
+
+    let result = process_message(
+        &payload,
+        &redis_client,
+        &template_service_client,
+        &user_service_client,
+        &providers,
+        &database_client,
+        &events,
+        &config,
+    )
+    .await;
+
+    assert!(result.is_err(), "A failed device should fail the message");
+    assert_eq!(fcm.sent().len(), 2);
+
+    let log = database_client
+        .get_audit_log_by_trace_id(&message.request_id)
+        .await?
+        .expect("Aggregated audit entry should exist");
+    assert!(matches!(log.status, NotificationStatus::PartiallySent));
+    assert_eq!(
+        log.metadata["delivery_summary"],
+        serde_json::json!({ "total": 3, "sent": 2, "failed": 1, "in_progress": 0, "pruned": 0 })
+    );
+
+    // A retry only goes to the device that missed out
+    fcm.fail_for(&[]);
+
+    process_message(
+        &payload,
+        &redis_client,
+        &template_service_client,
+        &user_service_client,
+        &providers,
+        &database_client,
+        &events,
+        &config,
+    )
+    .await?;
+
+    let sent = fcm.sent();
+    assert_eq!(sent.len(), 3);
+    assert_eq!(sent[2].target.identifier(), tokens[2]);
+
+    let log = database_client
+        .get_audit_log_by_trace_id(&message.request_id)
+        .await?
+        .unwrap();
+    assert!(matches!(log.status, NotificationStatus::Sent));
+
+    cleanup_redis_key(&config, &message.idempotency_key).await?;
+
+    Ok(())
+}
+
+/// Test: A device left claimed by an earlier attempt keeps the message unsettled
+#[tokio::test]
+async fn test_process_message_does_not_settle_in_progress_devices() -> Result<()> {
+    let (mut config, mock_server) = setup_mock_services().await?;
+    config.template_service_url = mock_server.uri();
+    config.user_service_url = mock_server.uri();
+
+    let redis_client = RedisClient::connect(&config).await?;
+    let database_client = DatabaseClient::connect(&config.database_url).await?;
+    let template_service_client = create_template_client(&config).await?;
+    let user_service_client = create_user_client(&config).await?;
+    let events = RecordingPublisher::default();
+
+    let fcm = FakeProvider::new("fcm");
+    let providers = ProviderRegistry::new().register(fcm.clone());
+
+    let tokens: Vec<String> = (0..2)
+        .map(|_| format!("device_token_{}", Uuid::new_v4().simple()))
+        .collect();
+
+    let mut message = create_notification_message(None);
+    message.metadata.remove("push_token");
+    message
+        .metadata
+        .insert("push_tokens".to_string(), serde_json::json!(tokens));
+
+    // As if a worker crashed after claiming the second device
+    let stale = DeviceTarget::new(PushTarget::Token(tokens[1].clone()), None);
+    redis_client
+        .mark_device_as_processing(&message.idempotency_key, &stale.device_id)
+        .await?;
+
+    let result = process_message(
+        &envelope(&message)?,
+        &redis_client,
+        &template_service_client,
+        &user_service_client,
+        &providers,
+        &database_client,
+        &events,
+        &config,
+    )
+    .await;
+
+    let err = result.expect_err("An unsettled device should not count as sent");
+    assert!(is_retryable(&err));
+    assert_eq!(fcm.sent().len(), 1);
+
+    let status = redis_client
+        .check_idempotency(&message.idempotency_key)
+        .await?;
+    assert!(matches!(status, IdempotencyStatus::Failed));
+
+    let log = database_client
+        .get_audit_log_by_trace_id(&message.request_id)
+        .await?
+        .expect("Aggregated audit entry should exist");
+    assert!(matches!(log.status, NotificationStatus::PartiallySent));
+    assert_eq!(
+        log.metadata["delivery_summary"],
+        serde_json::json!({ "total": 2, "sent": 1, "failed": 0, "in_progress": 1, "pruned": 0 })
+    );
+
+    cleanup_redis_key(&config, &message.idempotency_key).await?;
+
+    Ok(())
+}
+
+/// Test: Devices listed more than once are only notified once
+#[tokio::test]
+async fn test_process_message_deduplicates_devices() -> Result<()> {
+    let (mut config, mock_server) = setup_mock_services().await?;
+    config.template_service_url = mock_server.uri();
+    config.user_service_url = mock_server.uri();
+
+    let redis_client = RedisClient::connect(&config).await?;
+    let database_client = DatabaseClient::connect(&config.database_url).await?;
+    let template_service_client = create_template_client(&config).await?;
+    let user_service_client = create_user_client(&config).await?;
+    let events = RecordingPublisher::default();
+
+    let fcm = FakeProvider::new("fcm");
+    let apns = FakeProvider::new("apns");
+    let providers = ProviderRegistry::new()
+        .register(fcm.clone())
+        .register(apns.clone());
+
+    let token = format!("device_token_{}", Uuid::new_v4().simple());
+
+    let mut message = create_notification_message(None);
+    message.metadata.insert(
+        "devices".to_string(),
+        serde_json::json!([
+            { "push_token": token },
+            { "push_token": token },
+            { "push_token": format!("ios_token_{}", Uuid::new_v4().simple()), "push_provider": "apns" }
+        ]),
+    );
+
+    process_message(
+        &envelope(&message)?,
+        &redis_client,
+        &template_service_client,
+        &user_service_client,
+        &providers,
+        &database_client,
+        &events,
+        &config,
+    )
+    .await?;
+
+    assert_eq!(fcm.sent().len(), 1);
+    assert_eq!(apns.sent().len(), 1);
+
+    cleanup_redis_key(&config, &message.idempotency_key).await?;
+
+    Ok(())
+}
+
+/// Test: Messages without a target go to the user's registered devices
+#[tokio::test]
+async fn test_process_message_uses_registered_devices() -> Result<()> {
+    let (mut config, mock_server) = setup_mock_services().await?;
+    config.template_service_url = mock_server.uri();
+    config.user_service_url = mock_server.uri();
+
+    let redis_client = RedisClient::connect(&config).await?;
+    let database_client = DatabaseClient::connect(&config.database_url).await?;
+    let template_service_client = create_template_client(&config).await?;
+    let user_service_client = create_user_client(&config).await?;
+    let events = RecordingPublisher::default();
+
+    let fcm = FakeProvider::new("fcm");
+    let providers = ProviderRegistry::new().register(fcm.clone());
+
+    let user_id = Uuid::new_v4();
+    let mut message = create_notification_message(None);
+    message.user_id = user_id.to_string();
+    message.metadata.remove("push_token");
+
+    let device = database_client
+        .device_tokens()
+        .register(
+            user_id,
+            &RegisterDeviceRequest {
+                token: format!("device_token_{}", Uuid::new_v4().simple()),
+                platform: DevicePlatform::Android,
+                provider: None,
+                app_id: None,
+                locale: None,
+                timezone: None,
+            },
+        )
+        .await?;
+
+    process_message(
+        &envelope(&message)?,
+        &redis_client,
+        &template_service_client,
+        &user_service_client,
+        &providers,
+        &database_client,
+        &events,
+        &config,
+    )
+    .await?;
+
+    let sent = fcm.sent();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].target.identifier(), device.token);
+
+    database_client
+        .device_tokens()
+        .delete(user_id, device.id)
+        .await?;
+    cleanup_redis_key(&config, &message.idempotency_key).await?;
+
+    Ok(())
+}
+
+/// Test: The token user-service holds now wins over the one copied into metadata
+#[tokio::test]
+async fn test_process_message_prefers_user_service_token() -> Result<()> {
+    let (mut config, mock_server) = setup_mock_services().await?;
+    config.template_service_url = mock_server.uri();
+    config.user_service_url = mock_server.uri();
+
+    let redis_client = RedisClient::connect(&config).await?;
+    let database_client = DatabaseClient::connect(&config.database_url).await?;
+    let template_service_client = create_template_client(&config).await?;
+    let user_service_client = create_user_client(&config).await?;
+    let events = RecordingPublisher::default();
+
+    let fcm = FakeProvider::new("fcm");
+    let providers = ProviderRegistry::new().register(fcm.clone());
+
+    let message = create_notification_message(None);
+    let current_token = format!("device_token_{}", Uuid::new_v4().simple());
+    mount_user(&mock_server, &message.user_id, Some(&current_token)).await;
+
+    process_message(
+        &envelope(&message)?,
+        &redis_client,
+        &template_service_client,
+        &user_service_client,
+        &providers,
+        &database_client,
+        &events,
+        &config,
+    )
+    .await?;
+
+    let sent = fcm.sent();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].target.identifier(), current_token);
+
+    user_service_client.forget(&message.user_id).await;
+    cleanup_redis_key(&config, &message.idempotency_key).await?;
+
+    Ok(())
+}
+
+/// Test: The metadata token is used when user-service cannot be reached
+#[tokio::test]
+async fn test_process_message_falls_back_to_metadata_token() -> Result<()> {
+    let (mut config, mock_server) = setup_mock_services().await?;
+    config.template_service_url = mock_server.uri();
+    config.user_service_url = mock_server.uri();
+    config.max_retry_attempts = 1;
+
+    let redis_client = RedisClient::connect(&config).await?;
+    let database_client = DatabaseClient::connect(&config.database_url).await?;
+    let template_service_client = create_template_client(&config).await?;
+    let user_service_client = create_user_client(&config).await?;
+    let events = RecordingPublisher::default();
+
+    let fcm = FakeProvider::new("fcm");
+    let providers = ProviderRegistry::new().register(fcm.clone());
+
+    let message = create_notification_message(None);
+
+    Mock::given(method("GET"))
+        .and(path(format!("/api/v1/users/{}", message.user_id)))
+        .respond_with(ResponseTemplate::new(503))
+        .mount(&mock_server)
+        .await;
+
+    process_message(
+        &envelope(&message)?,
+        &redis_client,
+        &template_service_client,
+        &user_service_client,
+        &providers,
+        &database_client,
+        &events,
+        &config,
+    )
+    .await?;
+
+    let sent = fcm.sent();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(Some(sent[0].target.identifier()), metadata_token(&message));
+
+    cleanup_redis_key(&config, &message.idempotency_key).await?;
+
+    Ok(())
+}
+
+/// Test: Users who turned push off, globally or for the type, are not contacted
+#[tokio::test]
+async fn test_process_message_suppresses_opted_out_users() -> Result<()> {
+    let (mut config, mock_server) = setup_mock_services().await?;
+    config.template_service_url = mock_server.uri();
+    config.user_service_url = mock_server.uri();
+
+    let redis_client = RedisClient::connect(&config).await?;
+    let database_client = DatabaseClient::connect(&config.database_url).await?;
+    let template_service_client = create_template_client(&config).await?;
+    let user_service_client = create_user_client(&config).await?;
+    let events = RecordingPublisher::default();
+
+    let fcm = FakeProvider::new("fcm");
+    let providers = ProviderRegistry::new().register(fcm.clone());
+
+    let cases = [
+        (
+            serde_json::json!({ "email": true, "push": false }),
+            "push_disabled",
+        ),
+        (
+            serde_json::json!({ "email": true, "push": true, "notification_types": { "push": false } }),
+            "notification_type_disabled",
+        ),
+    ];
+
+    for (preferences, reason) in cases {
+        let message = create_notification_message(None);
+        mount_user_with_preferences(
+            &mock_server,
+            &message.user_id,
+            metadata_token(&message),
+            preferences,
+        )
+        .await;
+
+        process_message(
+            &envelope(&message)?,
+            &redis_client,
+            &template_service_client,
+            &user_service_client,
+            &providers,
+            &database_client,
+            &events,
+            &config,
+        )
+        .await?;
+
+        let status = redis_client
+            .check_idempotency(&message.idempotency_key)
+            .await?;
+        assert_eq!(status, IdempotencyStatus::Suppressed);
+
+        let log = database_client
+            .get_audit_log_by_trace_id(&message.request_id)
+            .await?
+            .expect("Suppression should be audited");
+        assert!(matches!(log.status, NotificationStatus::Suppressed));
+        assert_eq!(log.metadata["suppressed_reason"], reason);
+
+        user_service_client.forget(&message.user_id).await;
+        cleanup_redis_key(&config, &message.idempotency_key).await?;
+    }
+
+    assert!(
+        fcm.sent().is_empty(),
+        "Suppressed users must not be sent to"
+    );
+
+    Ok(())
+}
+
+/// Test: Non-urgent messages arriving during quiet hours are parked until the window ends
+#[tokio::test]
+async fn test_process_message_defers_during_quiet_hours() -> Result<()> {
+    let (mut config, mock_server) = setup_mock_services().await?;
+    config.template_service_url = mock_server.uri();
+    config.user_service_url = mock_server.uri();
+
+    let redis_client = RedisClient::connect(&config).await?;
+    let database_client = DatabaseClient::connect(&config.database_url).await?;
+    let template_service_client = create_template_client(&config).await?;
+    let user_service_client = create_user_client(&config).await?;
+    let events = RecordingPublisher::default();
+
+    let fcm = FakeProvider::new("fcm");
+    let providers = ProviderRegistry::new().register(fcm.clone());
+
+    let message = create_notification_message(None);
+    mount_user_with_preferences(
+        &mock_server,
+        &message.user_id,
+        metadata_token(&message),
+        quiet_hours_now(),
+    )
+    .await;
+
+    process_message(
+        &envelope(&message)?,
+        &redis_client,
+        &template_service_client,
+        &user_service_client,
+        &providers,
+        &database_client,
+        &events,
+        &config,
+    )
+    .await?;
+
+    assert!(fcm.sent().is_empty(), "Quiet hours must hold the push back");
+
+    let status = redis_client
+        .check_idempotency(&message.idempotency_key)
+        .await?;
+    assert_eq!(
+        status,
+        IdempotencyStatus::NotFound,
+        "The republished copy must not be treated as a duplicate"
+    );
+
+    let scheduled = database_client
+        .scheduled_notifications()
+        .find_by_idempotency_key(&message.idempotency_key)
+        .await?
+        .expect("Deferred message should be scheduled");
+    assert_eq!(scheduled.reason, ScheduleReason::QuietHours);
+    assert_eq!(scheduled.status, ScheduleStatus::Pending);
+    assert!(scheduled.deliver_at > chrono::Utc::now());
+    assert!(scheduled.deliver_at <= chrono::Utc::now() + chrono::Duration::hours(1));
+
+    // The republished copy is due once the window ends
+    let republished: NotificationMessage =
+        serde_json::from_value(scheduled.payload["data"].clone())?;
+    assert_eq!(republished.idempotency_key, message.idempotency_key);
+    assert_eq!(
+        republished
+            .send_at
+            .map(|send_at| send_at.timestamp_millis()),
+        Some(scheduled.deliver_at.timestamp_millis())
+    );
+
+    let log = database_client
+        .get_audit_log_by_trace_id(&message.request_id)
+        .await?
+        .expect("Deferral should be audited");
+    assert!(matches!(log.status, NotificationStatus::Deferred));
+    assert_eq!(log.metadata["deferred"]["reason"], "quiet_hours");
+
+    user_service_client.forget(&message.user_id).await;
+
+    Ok(())
+}
+
+/// Test: Messages with a future send_at are held back without asking user-service
+#[tokio::test]
+async fn test_process_message_schedules_send_at() -> Result<()> {
+    let (mut config, mock_server) = setup_mock_services().await?;
+    config.template_service_url = mock_server.uri();
+    config.user_service_url = mock_server.uri();
+
+    let redis_client = RedisClient::connect(&config).await?;
+    let database_client = DatabaseClient::connect(&config.database_url).await?;
+    let template_service_client = create_template_client(&config).await?;
+    let user_service_client = create_user_client(&config).await?;
+    let events = RecordingPublisher::default();
+
+    let fcm = FakeProvider::new("fcm");
+    let providers = ProviderRegistry::new().register(fcm.clone());
+
+    let send_at = chrono::Utc::now() + chrono::Duration::days(1);
+    let mut message = create_notification_message(None);
+    message.send_at = Some(send_at);
+
+    process_message(
+        &envelope(&message)?,
+        &redis_client,
+        &template_service_client,
+        &user_service_client,
+        &providers,
+        &database_client,
+        &events,
+        &config,
+    )
+    .await?;
+
+    assert!(fcm.sent().is_empty(), "Scheduled pushes must wait");
+
+    let scheduled = database_client
+        .scheduled_notifications()
+        .list_for_request(&message.request_id)
+        .await?;
+    assert_eq!(scheduled.len(), 1);
+    assert_eq!(scheduled[0].reason, ScheduleReason::SendAt);
+    assert_eq!(scheduled[0].status, ScheduleStatus::Pending);
+    assert_eq!(
+        scheduled[0].deliver_at.timestamp_millis(),
+        send_at.timestamp_millis()
+    );
+
+    let log = database_client
+        .get_audit_log_by_trace_id(&message.request_id)
+        .await?
+        .expect("Scheduling should be audited");
+    assert!(matches!(log.status, NotificationStatus::Deferred));
+    assert_eq!(log.metadata["deferred"]["reason"], "send_at");
+
+    database_client
+        .scheduled_notifications()
+        .cancel(&message.request_id)
+        .await?;
+
+    Ok(())
+}
+
+/// Test: Messages above the bypass priority are sent during quiet hours
+#[tokio::test]
+async fn test_process_message_urgent_bypasses_quiet_hours() -> Result<()> {
+    let (mut config, mock_server) = setup_mock_services().await?;
+    config.template_service_url = mock_server.uri();
+    config.user_service_url = mock_server.uri();
+    config.quiet_hours_bypass_priority = 5;
+
+    let redis_client = RedisClient::connect(&config).await?;
+    let database_client = DatabaseClient::connect(&config.database_url).await?;
+    let template_service_client = create_template_client(&config).await?;
+    let user_service_client = create_user_client(&config).await?;
+    let events = RecordingPublisher::default();
+
+    let fcm = FakeProvider::new("fcm");
+    let providers = ProviderRegistry::new().register(fcm.clone());
+
+    let mut message = create_notification_message(None);
+    message.priority = 10;
+    mount_user_with_preferences(
+        &mock_server,
+        &message.user_id,
+        metadata_token(&message),
+        quiet_hours_now(),
+    )
+    .await;
+
+    process_message(
+        &envelope(&message)?,
+        &redis_client,
+        &template_service_client,
+        &user_service_client,
+        &providers,
+        &database_client,
+        &events,
+        &config,
+    )
+    .await?;
+
+    assert_eq!(fcm.sent().len(), 1);
+    assert!(
+        database_client
+            .scheduled_notifications()
+            .find_by_idempotency_key(&message.idempotency_key)
+            .await?
+            .is_none()
+    );
+
+    user_service_client.forget(&message.user_id).await;
+    cleanup_redis_key(&config, &message.idempotency_key).await?;
+
+    Ok(())
+}
+
+/// Test: Messages past their expiry are dropped and audited instead of sent
+#[tokio::test]
+async fn test_process_message_drops_expired_messages() -> Result<()> {
+    let (mut config, mock_server) = setup_mock_services().await?;
+    config.template_service_url = mock_server.uri();
+    config.user_service_url = mock_server.uri();
+    config.push_queue_ttl_ms = 3_600_000;
+    config.retry_queue_delays_seconds = vec![30, 300, 3600];
+
+    let redis_client = RedisClient::connect(&config).await?;
+    let database_client = DatabaseClient::connect(&config.database_url).await?;
+    let template_service_client = create_template_client(&config).await?;
+    let user_service_client = create_user_client(&config).await?;
+    let events = RecordingPublisher::default();
+
+    let fcm = FakeProvider::new("fcm");
+    let providers = ProviderRegistry::new().register(fcm.clone());
+
+    // Too old for the default TTL, even counting every retry delay
+    let mut stale = create_notification_message(None);
+    stale.timestamp = (chrono::Utc::now() - chrono::Duration::hours(3)).to_rfc3339();
+
+    // Explicit expiry in the past
+    let mut expired = create_notification_message(None);
+    expired.expires_at = Some(chrono::Utc::now() - chrono::Duration::minutes(1));
+
+    // Producer-chosen TTL that ran out
+    let mut short_lived = create_notification_message(None);
+    short_lived.timestamp = (chrono::Utc::now() - chrono::Duration::minutes(5)).to_rfc3339();
+    short_lived.ttl_seconds = Some(60);
+
+    for message in [stale, expired, short_lived] {
+        process_message(
+            &envelope(&message)?,
+            &redis_client,
+            &template_service_client,
+            &user_service_client,
+            &providers,
+            &database_client,
+            &events,
+            &config,
+        )
+        .await?;
+
+        let status = redis_client
+            .check_idempotency(&message.idempotency_key)
+            .await?;
+        assert_eq!(status, IdempotencyStatus::Expired);
+
+        let log = database_client
+            .get_audit_log_by_trace_id(&message.request_id)
+            .await?
+            .expect("Expiry should be audited");
+        assert!(matches!(log.status, NotificationStatus::Expired));
+        assert!(log.metadata["expires_at"].is_string());
+
+        cleanup_redis_key(&config, &message.idempotency_key).await?;
+    }
+
+    assert!(fcm.sent().is_empty(), "Expired messages must not be sent");
+
+    Ok(())
+}
+
+/// Test: A message back from the last retry tier is still sent under the default TTL
+#[tokio::test]
+async fn test_process_message_default_ttl_covers_retry_tiers() -> Result<()> {
+    let (mut config, mock_server) = setup_mock_services().await?;
+    config.template_service_url = mock_server.uri();
+    config.user_service_url = mock_server.uri();
+    config.push_queue_ttl_ms = 3_600_000;
+    config.retry_queue_delays_seconds = vec![30, 300, 3600];
+
+    let redis_client = RedisClient::connect(&config).await?;
+    let database_client = DatabaseClient::connect(&config.database_url).await?;
+    let template_service_client = create_template_client(&config).await?;
+    let user_service_client = create_user_client(&config).await?;
+    let events = RecordingPublisher::default();
+
+    let fcm = FakeProvider::new("fcm");
+    let providers = ProviderRegistry::new().register(fcm.clone());
+
+    // Waited out all three tiers, plus a few minutes of processing
+    let mut message = create_notification_message(None);
+    message.timestamp = (chrono::Utc::now() - chrono::Duration::seconds(3_930 + 300)).to_rfc3339();
+    mount_user(&mock_server, &message.user_id, metadata_token(&message)).await;
+
+    let outcome = process_message(
+        &envelope(&message)?,
+        &redis_client,
+        &template_service_client,
+        &user_service_client,
+        &providers,
+        &database_client,
+        &events,
+        &config,
+    )
+    .await?;
+
+    assert!(matches!(outcome, ProcessingOutcome::Delivered { .. }));
+    assert_eq!(fcm.sent().len(), 1);
+
+    cleanup_redis_key(&config, &message.idempotency_key).await?;
+
+    Ok(())
+}
+
+/// Test: Only failures that could clear up on their own are marked for a later retry
+#[tokio::test]
+async fn test_process_message_classifies_failures() -> Result<()> {
+    let (mut config, mock_server) = setup_mock_services().await?;
+    config.template_service_url = mock_server.uri();
+    config.user_service_url = mock_server.uri();
+
+    let redis_client = RedisClient::connect(&config).await?;
+    let database_client = DatabaseClient::connect(&config.database_url).await?;
+    let template_service_client = create_template_client(&config).await?;
+    let user_service_client = create_user_client(&config).await?;
+    let events = RecordingPublisher::default();
+
+    let cases = [
+        (DeliveryErrorKind::Retryable, true),
+        (DeliveryErrorKind::Permanent, false),
+    ];
+
+    for (kind, retryable) in cases {
+        let providers =
+            ProviderRegistry::new().register(FakeProvider::failing("fcm").with_error_kind(kind));
+
+        let message = create_notification_message(None);
+        mount_user(&mock_server, &message.user_id, metadata_token(&message)).await;
+
+        let error = process_message(
+            &envelope(&message)?,
+            &redis_client,
+            &template_service_client,
+            &user_service_client,
+            &providers,
+            &database_client,
+            &events,
+            &config,
+        )
+        .await
+        .expect_err("Delivery should fail");
+
+        assert_eq!(is_retryable(&error), retryable, "{:?} failure", kind);
+
+        user_service_client.forget(&message.user_id).await;
+        cleanup_redis_key(&config, &message.idempotency_key).await?;
+    }
+
+    let error = process_message(
+        "not json",
+        &redis_client,
+        &template_service_client,
+        &user_service_client,
+        &ProviderRegistry::new().register(FakeProvider::new("fcm")),
+        &database_client,
+        &events,
+        &config,
+    )
+    .await
+    .expect_err("Malformed payloads should fail");
+    assert!(!is_retryable(&error), "Malformed payloads never parse");
+
+    Ok(())
+}
+
+/// Test: The remaining lifetime is handed to the provider
+#[tokio::test]
+async fn test_process_message_propagates_expiry() -> Result<()> {
+    let (mut config, mock_server) = setup_mock_services().await?;
+    config.template_service_url = mock_server.uri();
+    config.user_service_url = mock_server.uri();
+
+    let redis_client = RedisClient::connect(&config).await?;
+    let database_client = DatabaseClient::connect(&config.database_url).await?;
+    let template_service_client = create_template_client(&config).await?;
+    let user_service_client = create_user_client(&config).await?;
+    let events = RecordingPublisher::default();
+
+    let fcm = FakeProvider::new("fcm");
+    let providers = ProviderRegistry::new().register(fcm.clone());
+
+    let created_at = chrono::Utc::now();
+    let mut message = create_notification_message(None);
+    message.timestamp = created_at.to_rfc3339();
+    message.ttl_seconds = Some(600);
+    mount_user(&mock_server, &message.user_id, metadata_token(&message)).await;
+
+    process_message(
+        &envelope(&message)?,
+        &redis_client,
+        &template_service_client,
+        &user_service_client,
+        &providers,
+        &database_client,
+        &events,
+        &config,
+    )
+    .await?;
+
+    let sent = fcm.sent();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(
+        sent[0].expires_at.map(|expires_at| expires_at.timestamp()),
+        Some((created_at + chrono::Duration::seconds(600)).timestamp())
+    );
+
+    cleanup_redis_key(&config, &message.idempotency_key).await?;
+
+    Ok(())
+}
+
+/// Test: Delivered messages report the provider ids the outcome event carries
+#[tokio::test]
+async fn test_process_message_reports_delivery_outcome() -> Result<()> {
+    let (mut config, mock_server) = setup_mock_services().await?;
+    config.template_service_url = mock_server.uri();
+    config.user_service_url = mock_server.uri();
+
+    let redis_client = RedisClient::connect(&config).await?;
+    let database_client = DatabaseClient::connect(&config.database_url).await?;
+    let template_service_client = create_template_client(&config).await?;
+    let user_service_client = create_user_client(&config).await?;
+    let events = RecordingPublisher::default();
+
+    let providers = ProviderRegistry::new().register(FakeProvider::new("fcm"));
+
+    let message = create_notification_message(None);
+    mount_user(&mock_server, &message.user_id, metadata_token(&message)).await;
+
+    let outcome = process_message(
+        &envelope(&message)?,
+        &redis_client,
+        &template_service_client,
+        &user_service_client,
+        &providers,
+        &database_client,
+        &events,
+        &config,
+    )
+    .await?;
+
+    let ProcessingOutcome::Delivered {
+        provider_message_ids,
+    } = outcome
+    else {
+        panic!("Message should be delivered, got {:?}", outcome);
+    };
+    assert_eq!(provider_message_ids.len(), 1);
+    assert!(provider_message_ids[0].starts_with("fcm-"));
+
+    let delivered = PushOutcomeEvent::new(&message, NotificationStatus::Sent, 1)
+        .with_provider_message_ids(provider_message_ids.clone());
+    assert_eq!(delivered.pattern(), PUSH_DELIVERED);
+
+    let event = serde_json::to_value(&delivered)?;
+    assert_eq!(event["version"], PUSH_OUTCOME_VERSION);
+    assert_eq!(event["status"], "sent");
+    assert_eq!(event["notification_id"], message.notification_id.as_str());
+    assert_eq!(
+        event["provider_message_ids"][0],
+        provider_message_ids[0].as_str()
+    );
+    assert!(
+        event["latency_ms"]
+            .as_i64()
+            .is_some_and(|latency| latency >= 0)
+    );
+
+    let failed = PushOutcomeEvent::new(&message, NotificationStatus::Failed, 4)
+        .with_error("fcm send failed".to_string())
+        .with_error_code("UNAVAILABLE".to_string());
+    assert_eq!(failed.pattern(), PUSH_FAILED);
+    assert_eq!(failed.error_code.as_deref(), Some("UNAVAILABLE"));
+
+    // Expiry is not a delivery failure and is routed on its own
+    let expired = PushOutcomeEvent::new(&message, NotificationStatus::Expired, 1);
+    assert_eq!(expired.pattern(), PUSH_EXPIRED);
+    assert_eq!(serde_json::to_value(&expired)?["status"], "expired");
+
+    cleanup_redis_key(&config, &message.idempotency_key).await?;
+
+    Ok(())
+}
+
+/// Test: Tokens the provider reports as dead are invalidated and skipped
+#[tokio::test]
+async fn test_process_message_prunes_invalid_tokens() -> Result<()> {
+    let (mut config, mock_server) = setup_mock_services().await?;
+    config.template_service_url = mock_server.uri();
+    config.user_service_url = mock_server.uri();
+
+    let redis_client = RedisClient::connect(&config).await?;
+    let database_client = DatabaseClient::connect(&config.database_url).await?;
+    let template_service_client = create_template_client(&config).await?;
+    let user_service_client = create_user_client(&config).await?;
+    let events = RecordingPublisher::default();
+
+    let fcm = FakeProvider::new("fcm").with_error_kind(DeliveryErrorKind::InvalidToken);
+    let providers = ProviderRegistry::new().register(fcm.clone());
+
+    let user_id = Uuid::new_v4();
+    let device = database_client
+        .device_tokens()
+        .register(
+            user_id,
+            &RegisterDeviceRequest {
+                token: format!("device_token_{}", Uuid::new_v4().simple()),
+                platform: DevicePlatform::Android,
+                provider: None,
+                app_id: None,
+                locale: None,
+                timezone: None,
+            },
+        )
+        .await?;
+    fcm.fail_for(&[&device.token]);
+
+    let mut message = create_notification_message(None);
+    message.user_id = user_id.to_string();
+    message.metadata.remove("push_token");
+
+    let result = process_message(
+        &envelope(&message)?,
+        &redis_client,
+        &template_service_client,
+        &user_service_client,
+        &providers,
+        &database_client,
+        &events,
+        &config,
+    )
+    .await;
+
+    assert!(result.is_err(), "A dead token should fail the device");
+
+    let stored = database_client
+        .device_tokens()
+        .find(user_id, device.id)
+        .await?
+        .expect("Device should still be registered");
+    assert!(stored.invalidated_at.is_some());
+
+    let published = events.events();
+    assert_eq!(published.len(), 1);
+    assert_eq!(published[0].0, "push.token.invalidated");
+    assert_eq!(published[0].1["pattern"], "push.token.invalidated");
+    assert_eq!(published[0].1["data"]["token"], device.token);
+    assert_eq!(published[0].1["data"]["user_id"], user_id.to_string());
+    assert_eq!(
+        published[0].1["data"]["device_ids"],
+        serde_json::json!([device.id])
+    );
+
+    let log = database_client
+        .get_audit_log_by_trace_id(&message.request_id)
+        .await?
+        .unwrap();
+    assert_eq!(log.metadata["delivery_summary"]["pruned"], 1);
+
+    // user-service still holding the dead token does not bring it back
+    fcm.fail_for(&[]);
+
+    let retry = create_notification_message(None);
+    mount_user(&mock_server, &retry.user_id, Some(&device.token)).await;
+
+    let result = process_message(
+        &envelope(&retry)?,
+        &redis_client,
+        &template_service_client,
+        &user_service_client,
+        &providers,
+        &database_client,
+        &events,
+        &config,
+    )
+    .await;
+
+    assert!(
+        result.is_err(),
+        "Only invalidated targets should fail the message"
+    );
+    assert!(fcm.sent().is_empty());
+
+    database_client
+        .device_tokens()
+        .delete(user_id, device.id)
+        .await?;
+    cleanup_redis_key(&config, &message.idempotency_key).await?;
+    cleanup_redis_key(&config, &retry.idempotency_key).await?;
+
+    Ok(())
+}
+
+/// Test: A target our own checks reject fails the message without pruning
+#[tokio::test]
+async fn test_process_message_does_not_prune_locally_invalid_tokens() -> Result<()> {
+    let (mut config, mock_server) = setup_mock_services().await?;
+    config.template_service_url = mock_server.uri();
+    config.user_service_url = mock_server.uri();
+
+    let redis_client = RedisClient::connect(&config).await?;
+    let database_client = DatabaseClient::connect(&config.database_url).await?;
+    let template_service_client = create_template_client(&config).await?;
+    let user_service_client = create_user_client(&config).await?;
+    let events = RecordingPublisher::default();
+
+    let fcm = FakeProvider::new("fcm").with_error_kind(DeliveryErrorKind::InvalidToken);
+    let providers = ProviderRegistry::new().register(fcm.clone());
+
+    let user_id = Uuid::new_v4();
+    let device = database_client
+        .device_tokens()
+        .register(
+            user_id,
+            &RegisterDeviceRequest {
+                token: format!("not a token {}", Uuid::new_v4().simple()),
+                platform: DevicePlatform::Android,
+                provider: None,
+                app_id: None,
+                locale: None,
+                timezone: None,
+            },
+        )
+        .await?;
+
+    let mut message = create_notification_message(None);
+    message.user_id = user_id.to_string();
+    message.metadata.remove("push_token");
+
+    let result = process_message(
+        &envelope(&message)?,
+        &redis_client,
+        &template_service_client,
+        &user_service_client,
+        &providers,
+        &database_client,
+        &events,
+        &config,
+    )
+    .await;
+
+    let err = result.expect_err("A malformed token should fail the device");
+    assert!(!is_retryable(&err));
+    assert!(fcm.sent().is_empty());
+
+    let stored = database_client
+        .device_tokens()
+        .find(user_id, device.id)
+        .await?
+        .expect("Device should still be registered");
+    assert!(stored.invalidated_at.is_none());
+    assert!(events.events().is_empty());
+
+    let log = database_client
+        .get_audit_log_by_trace_id(&message.request_id)
+        .await?
+        .unwrap();
+    assert_eq!(log.metadata["delivery_summary"]["pruned"], 0);
+
+    database_client
+        .device_tokens()
+        .delete(user_id, device.id)
+        .await?;
+    cleanup_redis_key(&config, &message.idempotency_key).await?;
+
+    Ok(())
+}
+
+async fn setup_mock_services() -> Result<(Config, MockServer)> {
+    let config = Config::load()?;
+    let mock_server = MockServer::start().await;
+
+    Mock::given(method("GET"))
+        .and(path("/api/v1/templates/ORDER_SHIPPED"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "id": "tpl_order_shipped",
+            "code": "ORDER_SHIPPED",
+            "type": "push",
+            "language": "en",
+            "version": 1,
+            "content": {
+                "title": "Hello {{name}}",
+                "body": "Your order {{order_id}} shipped",
+                "image": "https://cdn.example.com/orders/{{order_id}}.png",
+                "link": "https://shop.example.com/orders/{{order_id}}"
+            },
+            "variables": ["name", "order_id"]
+        })))
+        .mount(&mock_server)
+        .await;
+
+    Ok((config, mock_server))
+}
+
+async fn create_user_client(config: &Config) -> Result<UserServiceClient> {
+    let redis_client = redis::Client::open(config.redis_url.as_str())?;
+    let redis_conn = redis_client.get_multiplexed_async_connection().await?;
+
+    let circuit_breaker = CircuitBreaker::new(
+        format!("user_provider_{}", Uuid::new_v4()),
+        redis_conn.clone(),
+        config.circuit_breaker_config(),
+    );
+
+    UserServiceClient::new(config, circuit_breaker, redis_conn)
+}
+
+/// Makes the mock user-service report `push_token` as the user's current token
+async fn mount_user(mock_server: &MockServer, user_id: &str, push_token: Option<&str>) {
+    mount_user_with_preferences(
+        mock_server,
+        user_id,
+        push_token,
+        serde_json::json!({ "email": true, "push": true }),
+    )
+    .await;
+}
+
+async fn mount_user_with_preferences(
+    mock_server: &MockServer,
+    user_id: &str,
+    push_token: Option<&str>,
+    preferences: serde_json::Value,
+) {
+    Mock::given(method("GET"))
+        .and(path(format!("/api/v1/users/{}", user_id)))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "success": true,
+            "message": "User retrieved successfully",
+            "data": {
+                "id": user_id,
+                "name": "Ada",
+                "email": "ada@example.com",
+                "push_token": push_token,
+                "preferences": preferences
+            }
+        })))
+        .mount(mock_server)
+        .await;
+}
+
+/// Preferences with a quiet window around the current time
+fn quiet_hours_now() -> serde_json::Value {
+    let now = chrono::Utc::now();
+
+    serde_json::json!({
+        "email": true,
+        "push": true,
+        "timezone": "UTC",
+        "quiet_hours": {
+            "start": (now - chrono::Duration::hours(1)).format("%H:%M").to_string(),
+            "end": (now + chrono::Duration::hours(1)).format("%H:%M").to_string()
+        }
+    })
+}
+
+async fn create_template_client(config: &Config) -> Result<TemplateServiceClient> {
+    let redis_client = redis::Client::open(config.redis_url.as_str())?;
+    let redis_conn = redis_client.get_multiplexed_async_connection().await?;
+
+    let circuit_breaker = CircuitBreaker::new(
+        format!("template_provider_{}", Uuid::new_v4()),
+        redis_conn,
+        config.circuit_breaker_config(),
+    );
+
+    TemplateServiceClient::new(config, circuit_breaker).await
+}
+
+fn create_notification_message(provider: Option<&str>) -> NotificationMessage {
+    let mut variables = HashMap::new();
+    variables.insert("name".to_string(), serde_json::json!("Ada"));
+    variables.insert("order_id".to_string(), serde_json::json!("ord_42"));
+
+    let mut metadata = HashMap::new();
+    metadata.insert(
+        "push_token".to_string(),
+        serde_json::json!(format!("device_token_{}", Uuid::new_v4().simple())),
+    );
+
+    if let Some(provider) = provider {
+        metadata.insert("push_provider".to_string(), serde_json::json!(provider));
+    }
+
+    NotificationMessage {
+        notification_id: format!("cnotif_{}", Uuid::new_v4()),
+        idempotency_key: format!("idem_provider_{}", Uuid::new_v4()),
+        notification_type: "push".to_string(),
+        user_id: Uuid::new_v4().to_string(),
+        template_code: "ORDER_SHIPPED".to_string(),
+        variables,
+        request_id: format!("req_provider_{}", Uuid::new_v4()),
+        priority: 1,
+        metadata,
+        created_by: "550e8400-e29b-41d4-a716-446655440000".to_string(),
+        timestamp: chrono::Utc::now().to_rfc3339(),
+        send_at: None,
+        expires_at: None,
+        ttl_seconds: None,
+    }
+}
+
+fn metadata_token(message: &NotificationMessage) -> Option<&str> {
+    message.metadata.get("push_token").and_then(|v| v.as_str())
+}
+
+fn envelope(message: &NotificationMessage) -> Result<String> {
+    Ok(serde_json::to_string(&serde_json::json!({
+        "pattern": "push.queue",
+        "data": message,
+    }))?)
+}
+
+async fn cleanup_redis_key(config: &Config, key: &str) -> Result<()> {
+    use redis::AsyncCommands;
+
+    let client = redis::Client::open(config.redis_url.as_str())?;
+    let mut conn = client.get_multiplexed_async_connection().await?;
+    conn.del::<_, ()>(format!("idempotency:{}", key)).await?;
+
+    let device_keys: Vec<String> = conn.keys(format!("idempotency:{}:*", key)).await?;
+    if !device_keys.is_empty() {
+        conn.del::<_, ()>(device_keys).await?;
+    }
+
+    Ok(())
+}
@@ -1,18 +1,29 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, sync::Arc};
 
-use chrono::{SecondsFormat, Utc};
+use chrono::{DateTime, SecondsFormat, Utc};
 use futures_util::StreamExt;
 
 use anyhow::Result;
 use lapin::{
     Connection, ConnectionProperties,
     options::{BasicAckOptions, BasicConsumeOptions, QueueDeclareOptions},
-    types::FieldTable,
+    types::{AMQPValue, FieldArray, FieldTable},
 };
 use push_service::{
-    clients::rbmq::RabbitMqClient,
+    clients::{
+        events::EventPublisher,
+        rbmq::{RabbitMqClient, retry_attempts, retry_queue_name},
+        redis::{RedisClient, device_idempotency_key},
+    },
     config::Config,
-    models::message::{DlqMessage, NotificationMessage},
+    dlq,
+    models::{
+        dlq::{DlqReplayRequest, FailureReasonCount},
+        health::BrokerConnectionState,
+        message::{DlqMessage, NotificationMessage},
+        status::IdempotencyStatus,
+    },
+    shutdown::{DrainReport, InFlight},
 };
 use tokio::time::sleep;
 use uuid::Uuid;
@@ -36,7 +47,7 @@ async fn test_valid_messages_are_acknowledged() -> Result<()> {
 
         assert_eq!(received.idempotency_key, test_message.idempotency_key);
 
-        rabbitmq.acknowledge(delivery.delivery_tag).await?;
+        rabbitmq.acknowledge(&delivery.acker).await?;
     }
 
     Ok(())
@@ -90,9 +101,7 @@ async fn test_rejected_messages_not_requeued() -> Result<()> {
     let mut consumer = rabbitmq.create_consumer().await?;
 
     if let Some(Ok(delivery)) = consumer.next().await {
-        let delivery_tag = delivery.delivery_tag;
-
-        rabbitmq.reject(delivery_tag, false).await?;
+        rabbitmq.reject(&delivery.acker, false).await?;
     }
 
     sleep(tokio::time::Duration::from_millis(500)).await;
@@ -128,7 +137,7 @@ async fn test_concurrent_message_processing() -> Result<()> {
         let mut count = 0;
 
         while let Some(Ok(delivery)) = consumer.next().await {
-            rabbitmq.acknowledge(delivery.delivery_tag).await.unwrap();
+            rabbitmq.acknowledge(&delivery.acker).await.unwrap();
             count += 1;
             if count >= 3 {
                 break;
@@ -143,7 +152,7 @@ async fn test_concurrent_message_processing() -> Result<()> {
         let mut count = 0;
 
         while let Some(Ok(delivery)) = consumer.next().await {
-            rabbitmq.acknowledge(delivery.delivery_tag).await.unwrap();
+            rabbitmq.acknowledge(&delivery.acker).await.unwrap();
             count += 1;
             if count >= 2 {
                 break;
@@ -192,6 +201,9 @@ async fn test_message_structure_preservation() -> Result<()> {
         metadata: metadata.clone(),
         created_by: "user_456".to_string(),
         timestamp: chrono::Utc::now().to_rfc3339(),
+        send_at: None,
+        expires_at: None,
+        ttl_seconds: None,
     };
 
     publish_test_message(&config, &original).await?;
@@ -213,7 +225,7 @@ async fn test_message_structure_preservation() -> Result<()> {
             Some(&serde_json::json!("high"))
         );
 
-        rabbitmq.acknowledge(delivery.delivery_tag).await?;
+        rabbitmq.acknowledge(&delivery.acker).await?;
     }
 
     Ok(())
@@ -248,6 +260,406 @@ async fn test_dlq_messages_contain_failure_context() -> Result<()> {
     Ok(())
 }
 
+/// Test: An event nobody subscribes to is still a successful publish
+#[tokio::test]
+async fn test_unsubscribed_events_are_not_errors() -> Result<()> {
+    let config = Config::load()?;
+    let rabbitmq = RabbitMqClient::connect(&config).await?;
+
+    let routing_key = format!("test.unroutable.{}", Uuid::new_v4());
+    rabbitmq.publish(&routing_key, b"{}").await?;
+
+    Ok(())
+}
+
+/// Test: A dead channel is replaced and publishing resumes on the new one
+#[tokio::test]
+async fn test_reconnect_recovers_a_closed_channel() -> Result<()> {
+    let config = Config::load()?;
+    let rabbitmq = RabbitMqClient::connect(&config).await?;
+
+    rabbitmq
+        .channel()
+        .close(200, "Simulated broker failure")
+        .await?;
+    assert!(
+        rabbitmq
+            .publish_to_push_queue(&serde_json::to_vec(&create_test_notification_message(
+                "test_reconnect"
+            ))?)
+            .await
+            .is_err()
+    );
+
+    rabbitmq.reconnect().await;
+
+    assert!(matches!(
+        rabbitmq.connection_state(),
+        BrokerConnectionState::Connected { .. }
+    ));
+
+    purge_queue(&config).await?;
+    let message = create_test_notification_message("test_reconnect");
+    rabbitmq
+        .publish_to_push_queue(&serde_json::to_vec(&message)?)
+        .await?;
+
+    let mut consumer = rabbitmq.create_consumer().await?;
+    if let Some(Ok(delivery)) = consumer.next().await {
+        let received: NotificationMessage = serde_json::from_slice(&delivery.data)?;
+        assert_eq!(received.idempotency_key, message.idempotency_key);
+        rabbitmq.acknowledge(&delivery.acker).await?;
+    }
+
+    Ok(())
+}
+
+/// Test: Pausing the consumer hands prefetched messages back to the queue
+#[tokio::test]
+async fn test_paused_consumer_requeues_prefetched_messages() -> Result<()> {
+    let config = Config::load()?;
+
+    purge_queue(&config).await?;
+
+    let rabbitmq = RabbitMqClient::connect(&config).await?;
+
+    for suffix in ["test_pause_1", "test_pause_2"] {
+        publish_test_message(&config, &create_test_notification_message(suffix)).await?;
+    }
+
+    let mut consumer = rabbitmq.create_consumer().await?;
+    sleep(tokio::time::Duration::from_millis(500)).await;
+
+    rabbitmq.pause_consumer(&mut consumer).await?;
+
+    sleep(tokio::time::Duration::from_millis(500)).await;
+    assert_eq!(get_queue_message_count(&config).await?, 2);
+
+    let mut consumer = rabbitmq.create_consumer().await?;
+    if let Some(Ok(delivery)) = consumer.next().await {
+        rabbitmq.acknowledge(&delivery.acker).await?;
+    }
+
+    purge_queue(&config).await?;
+
+    Ok(())
+}
+
+/// Test: A shutdown waits for finished work and requeues what overruns the grace period
+#[tokio::test]
+async fn test_drain_requeues_unfinished_messages() -> Result<()> {
+    let config = Config::load()?;
+
+    purge_queue(&config).await?;
+
+    let rabbitmq = Arc::new(RabbitMqClient::connect(&config).await?);
+    let redis_client = RedisClient::connect(&config).await?;
+
+    let quick = create_test_notification_message("test_drain_quick");
+    let slow = create_test_notification_message("test_drain_slow");
+    publish_test_message(&config, &quick).await?;
+    publish_test_message(&config, &slow).await?;
+
+    let mut consumer = rabbitmq.create_consumer().await?;
+    let mut in_flight = InFlight::new();
+
+    for _ in 0..2 {
+        let Some(Ok(delivery)) = consumer.next().await else {
+            anyhow::bail!("Expected a delivery");
+        };
+
+        let received: NotificationMessage = serde_json::from_slice(&delivery.data)?;
+        let is_slow = received.idempotency_key == slow.idempotency_key;
+        let acker = delivery.acker.clone();
+        let rabbitmq = Arc::clone(&rabbitmq);
+
+        in_flight.spawn(delivery.acker, Some(received.idempotency_key), async move {
+            if is_slow {
+                sleep(tokio::time::Duration::from_secs(60)).await;
+            }
+            rabbitmq.acknowledge(&acker).await.unwrap();
+        });
+    }
+
+    rabbitmq.cancel_consumer().await?;
+
+    let report = in_flight
+        .drain(
+            std::time::Duration::from_millis(500),
+            &rabbitmq,
+            &redis_client,
+        )
+        .await;
+
+    assert_eq!(
+        report,
+        DrainReport {
+            completed: 1,
+            requeued: 1
+        }
+    );
+
+    sleep(tokio::time::Duration::from_millis(500)).await;
+    assert_eq!(get_queue_message_count(&config).await?, 1);
+
+    purge_queue(&config).await?;
+
+    Ok(())
+}
+
+/// Test: The reconnect state is reported in a tagged shape for /health
+#[test]
+fn test_broker_connection_state_serialization() -> Result<()> {
+    let state = BrokerConnectionState::Reconnecting {
+        since: "2025-01-01T00:00:00.000Z".to_string(),
+        attempt: 3,
+        last_error: Some("Failed to connect to RabbitMQ".to_string()),
+    };
+
+    assert_eq!(
+        serde_json::to_value(&state)?,
+        serde_json::json!({
+            "state": "reconnecting",
+            "since": "2025-01-01T00:00:00.000Z",
+            "attempt": 3,
+            "last_error": "Failed to connect to RabbitMQ"
+        })
+    );
+
+    Ok(())
+}
+
+/// Test: Retryable failures are parked in the first retry tier
+#[tokio::test]
+async fn test_failed_messages_enter_first_retry_tier() -> Result<()> {
+    let config = Config::load()?;
+    let rabbitmq = RabbitMqClient::connect(&config).await?;
+
+    let first_tier = retry_queue_name(
+        &config.push_queue_name,
+        config.retry_queue_delays_seconds[0],
+    );
+
+    let connection =
+        Connection::connect(&config.rabbitmq_url, ConnectionProperties::default()).await?;
+    let channel = connection.create_channel().await?;
+    channel.queue_purge(&first_tier, Default::default()).await?;
+
+    let message = create_test_notification_message("test_retry");
+    let payload = serde_json::to_vec(&message)?;
+
+    assert!(rabbitmq.publish_for_retry(&payload, None).await?);
+
+    let parked = channel
+        .basic_get(&first_tier, Default::default())
+        .await?
+        .expect("Message should wait in the first retry tier");
+    let received: NotificationMessage = serde_json::from_slice(&parked.delivery.data)?;
+    assert_eq!(received.idempotency_key, message.idempotency_key);
+
+    parked.delivery.ack(BasicAckOptions::default()).await?;
+
+    Ok(())
+}
+
+/// Test: Replayed DLQ messages go back to the push queue in a gateway envelope
+#[tokio::test]
+async fn test_dlq_replay_republishes_selected_messages() -> Result<()> {
+    let config = Config::load()?;
+
+    purge_queue(&config).await?;
+    purge_dlq(&config).await?;
+
+    let rabbitmq = RabbitMqClient::connect(&config).await?;
+    let redis = RedisClient::connect(&config).await?;
+
+    let selected = dlq_message("test_replay", "FCM unavailable", Utc::now());
+    let skipped = dlq_message("test_replay_skip", "FCM unavailable", Utc::now());
+
+    rabbitmq.publish_to_dlq(&selected).await?;
+    rabbitmq.publish_to_dlq(&skipped).await?;
+
+    // One device was cut off mid-send, the other already got the push
+    let selected_key = &selected.original_message.idempotency_key;
+    redis.mark_as_failed(selected_key).await?;
+    redis
+        .mark_device_as_processing(selected_key, "device_1")
+        .await?;
+    redis
+        .mark_device_as_processing(selected_key, "device_2")
+        .await?;
+    redis
+        .mark_as_sent(&device_idempotency_key(selected_key, "device_2"))
+        .await?;
+
+    let request = DlqReplayRequest {
+        idempotency_keys: Some(vec![selected.original_message.idempotency_key.clone()]),
+        ..Default::default()
+    };
+    let result = dlq::replay(&rabbitmq, &redis, &request).await?;
+
+    assert_eq!(result.replayed, 1);
+    assert_eq!(result.remaining, 1);
+    assert!(matches!(
+        redis.check_idempotency(selected_key).await?,
+        IdempotencyStatus::NotFound
+    ));
+
+    // The replay delivers to the interrupted device again, but not the other
+    assert!(matches!(
+        redis
+            .check_idempotency(&device_idempotency_key(selected_key, "device_1"))
+            .await?,
+        IdempotencyStatus::NotFound
+    ));
+    assert!(matches!(
+        redis
+            .check_idempotency(&device_idempotency_key(selected_key, "device_2"))
+            .await?,
+        IdempotencyStatus::Sent
+    ));
+
+    let connection =
+        Connection::connect(&config.rabbitmq_url, ConnectionProperties::default()).await?;
+    let channel = connection.create_channel().await?;
+    let replayed = channel
+        .basic_get(&config.push_queue_name, Default::default())
+        .await?
+        .expect("Replayed message should be on the push queue");
+    let envelope: serde_json::Value = serde_json::from_slice(&replayed.delivery.data)?;
+    assert_eq!(envelope["pattern"], config.push_queue_name.as_str());
+    assert_eq!(
+        envelope["data"]["idempotency_key"],
+        selected.original_message.idempotency_key.as_str()
+    );
+    replayed.delivery.ack(BasicAckOptions::default()).await?;
+
+    let remaining = consume_from_dlq(&config).await?;
+    assert_eq!(
+        remaining.original_message.idempotency_key,
+        skipped.original_message.idempotency_key
+    );
+
+    Ok(())
+}
+
+/// Test: Purging only drops DLQ messages older than the cutoff
+#[tokio::test]
+async fn test_dlq_purge_drops_old_messages() -> Result<()> {
+    let config = Config::load()?;
+
+    purge_dlq(&config).await?;
+
+    let rabbitmq = RabbitMqClient::connect(&config).await?;
+
+    let old = dlq_message(
+        "test_purge_old",
+        "Invalid token",
+        Utc::now() - chrono::Duration::hours(2),
+    );
+    let fresh = dlq_message("test_purge_fresh", "Invalid token", Utc::now());
+
+    rabbitmq.publish_to_dlq(&old).await?;
+    rabbitmq.publish_to_dlq(&fresh).await?;
+
+    let result = dlq::purge(&rabbitmq, chrono::Duration::hours(1)).await?;
+    assert_eq!(result.purged, 1);
+    assert_eq!(result.remaining, 1);
+
+    let inspection = dlq::inspect(&rabbitmq, 10).await?;
+    assert_eq!(inspection.total, 1);
+    assert_eq!(
+        inspection.messages[0].original_message.idempotency_key,
+        fresh.original_message.idempotency_key
+    );
+
+    Ok(())
+}
+
+/// Test: DLQ messages are grouped by failure reason, most common first
+#[test]
+fn test_dlq_failure_reason_grouping() {
+    let messages = vec![
+        dlq_message("a", "Invalid token", Utc::now()),
+        dlq_message("b", "FCM unavailable", Utc::now()),
+        dlq_message("c", "FCM unavailable", Utc::now()),
+    ];
+
+    assert_eq!(
+        FailureReasonCount::group(&messages),
+        vec![
+            FailureReasonCount {
+                failure_reason: "FCM unavailable".to_string(),
+                count: 2,
+            },
+            FailureReasonCount {
+                failure_reason: "Invalid token".to_string(),
+                count: 1,
+            },
+        ]
+    );
+
+    let by_reason = DlqReplayRequest {
+        failure_reason: Some("FCM unavailable".to_string()),
+        ..Default::default()
+    };
+    assert!(!by_reason.matches(&messages[0]));
+    assert!(by_reason.matches(&messages[1]));
+    assert!(DlqReplayRequest::default().matches(&messages[0]));
+}
+
+/// Test: Retry tiers are named after their delay
+#[test]
+fn test_retry_queue_names() {
+    assert_eq!(retry_queue_name("push", 30), "push.retry.30s");
+    assert_eq!(retry_queue_name("push", 300), "push.retry.5m");
+    assert_eq!(retry_queue_name("push", 3_600), "push.retry.1h");
+    assert_eq!(retry_queue_name("push", 90), "push.retry.90s");
+}
+
+/// Test: Attempts are counted from the x-death entries of retry queues only
+#[test]
+fn test_retry_attempts_counts_x_death_entries() {
+    let retry_queues = vec!["push.retry.30s".to_string(), "push.retry.5m".to_string()];
+
+    assert_eq!(retry_attempts(None, &retry_queues), 0);
+    assert_eq!(
+        retry_attempts(Some(&FieldTable::default()), &retry_queues),
+        0
+    );
+
+    let headers = x_death(&[("push.retry.5m", 1), ("push.retry.30s", 1), ("push", 3)]);
+    assert_eq!(retry_attempts(Some(&headers), &retry_queues), 2);
+
+    let headers = x_death(&[("push.retry.30s", 2)]);
+    assert_eq!(retry_attempts(Some(&headers), &retry_queues), 2);
+}
+
+/// Builds the `x-death` header the broker attaches to dead-lettered messages
+fn x_death(deaths: &[(&str, i64)]) -> FieldTable {
+    let mut entries = FieldArray::default();
+
+    for (queue, count) in deaths {
+        let mut death = FieldTable::default();
+        death.insert("queue".into(), AMQPValue::LongString((*queue).into()));
+        death.insert("reason".into(), AMQPValue::LongString("expired".into()));
+        death.insert("count".into(), AMQPValue::LongLongInt(*count));
+        entries.push(AMQPValue::FieldTable(death));
+    }
+
+    let mut headers = FieldTable::default();
+    headers.insert("x-death".into(), AMQPValue::FieldArray(entries));
+    headers
+}
+
+fn dlq_message(suffix: &str, failure_reason: &str, failed_at: DateTime<Utc>) -> DlqMessage {
+    DlqMessage {
+        original_message: create_test_notification_message(suffix),
+        failure_reason: failure_reason.to_string(),
+        failed_at: failed_at.to_rfc3339_opts(SecondsFormat::Millis, true),
+    }
+}
+
 fn create_test_notification_message(suffix: &str) -> NotificationMessage {
     let mut variables = HashMap::new();
     variables.insert("key".to_string(), serde_json::json!("value"));
@@ -270,6 +682,9 @@ fn create_test_notification_message(suffix: &str) -> NotificationMessage {
         metadata,
         created_by: format!("user_{}", suffix),
         timestamp: chrono::Utc::now().to_rfc3339(),
+        send_at: None,
+        expires_at: None,
+        ttl_seconds: None,
     }
 }
 
@@ -278,7 +693,7 @@ async fn publish_test_message(config: &Config, message: &NotificationMessage) ->
     let payload = serde_json::to_vec(message)?;
 
     rabbitmq
-        .channel
+        .channel()
         .basic_publish(
             "",
             &config.push_queue_name,
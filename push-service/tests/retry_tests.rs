@@ -1,10 +1,13 @@
 use anyhow::{Result, anyhow};
-use push_service::{models::retry::RetryConfig, utils::retry_with_backoff};
+use push_service::{
+    models::retry::{RetryConfig, RetryDecision},
+    utils::{retry_with_backoff, retry_with_backoff_if, retry_with_backoff_policy},
+};
 use std::sync::{
     Arc,
     atomic::{AtomicU32, Ordering},
 };
-use tokio::time::Instant;
+use tokio::time::{Duration, Instant};
 
 /// Test: Successful operations complete without retry
 #[tokio::test]
@@ -108,6 +111,126 @@ async fn test_permanent_failure_exhausts_retries() -> Result<()> {
     Ok(())
 }
 
+/// Test: Errors rejected by the predicate are not retried
+#[tokio::test]
+async fn test_non_retryable_errors_stop_immediately() -> Result<()> {
+    let config = RetryConfig {
+        max_attempts: 5,
+        initial_delay_ms: 50,
+        max_delay_ms: 500,
+        backoff_multiplier: 2,
+    };
+
+    let attempt_count = Arc::new(AtomicU32::new(0));
+    let counter = Arc::clone(&attempt_count);
+
+    let result = retry_with_backoff_if(
+        &config,
+        || {
+            let counter = Arc::clone(&counter);
+            async move {
+                let attempts = counter.fetch_add(1, Ordering::SeqCst);
+
+                // First attempt is transient, second is permanent
+                if attempts == 0 {
+                    Err::<String, _>(anyhow!("transient"))
+                } else {
+                    Err(anyhow!("permanent"))
+                }
+            }
+        },
+        |e| e.to_string() == "transient",
+    )
+    .await;
+
+    assert_eq!(result.unwrap_err().to_string(), "permanent");
+    assert_eq!(
+        attempt_count.load(Ordering::SeqCst),
+        2,
+        "Should stop as soon as a non-retryable error is returned"
+    );
+
+    Ok(())
+}
+
+/// Test: Server supplied delays replace the computed backoff
+#[tokio::test]
+async fn test_server_retry_after_overrides_backoff() -> Result<()> {
+    let config = RetryConfig {
+        max_attempts: 3,
+        initial_delay_ms: 10,
+        max_delay_ms: 1000,
+        backoff_multiplier: 2,
+    };
+
+    let attempt_count = Arc::new(AtomicU32::new(0));
+    let counter = Arc::clone(&attempt_count);
+
+    let start = Instant::now();
+
+    let result = retry_with_backoff_policy(
+        &config,
+        || {
+            let counter = Arc::clone(&counter);
+            async move {
+                if counter.fetch_add(1, Ordering::SeqCst) == 0 {
+                    Err(anyhow!("throttled"))
+                } else {
+                    Ok("success")
+                }
+            }
+        },
+        |_| RetryDecision::RetryAfter(Duration::from_millis(300)),
+    )
+    .await?;
+
+    let elapsed = start.elapsed();
+
+    assert_eq!(result, "success");
+    assert!(
+        elapsed >= Duration::from_millis(300),
+        "Should wait for the server supplied delay, waited {:?}",
+        elapsed
+    );
+
+    Ok(())
+}
+
+/// Test: Server supplied delays beyond the retry budget are not waited out
+#[tokio::test]
+async fn test_server_retry_after_beyond_budget_gives_up() -> Result<()> {
+    let config = RetryConfig {
+        max_attempts: 3,
+        initial_delay_ms: 10,
+        max_delay_ms: 100,
+        backoff_multiplier: 2,
+    };
+
+    let attempt_count = Arc::new(AtomicU32::new(0));
+    let counter = Arc::clone(&attempt_count);
+
+    let start = Instant::now();
+
+    let result = retry_with_backoff_policy(
+        &config,
+        || {
+            let counter = Arc::clone(&counter);
+            async move {
+                counter.fetch_add(1, Ordering::SeqCst);
+                Err::<String, _>(anyhow!("quota exhausted"))
+            }
+        },
+        |_| RetryDecision::RetryAfter(Duration::from_secs(60)),
+    )
+    .await;
+
+    assert!(result.is_err());
+    assert_eq!(attempt_count.load(Ordering::SeqCst), 1);
+    assert!(start.elapsed() < Duration::from_secs(1));
+
+    Ok(())
+}
+
 /// Test: Retry delays follow exponential backoff
 #[tokio::test]
 async fn test_exponential_backoff_timing() -> Result<()> {
@@ -0,0 +1,217 @@
+use std::time::Duration as StdDuration;
+
+use anyhow::Result;
+use chrono::{Duration, Utc};
+use push_service::{
+    clients::database::DatabaseClient,
+    config::Config,
+    models::schedule::{ScheduleReason, ScheduleStatus, ScheduledNotification},
+};
+use uuid::Uuid;
+
+/// Long enough that no claim made by a test expires on its own
+const LEASE: StdDuration = StdDuration::from_secs(60);
+
+/// Test: Due messages are claimed once and can be released for the next poll
+#[tokio::test]
+async fn test_claim_due_dispatches_each_message_once() -> Result<()> {
+    let database_client = connect().await?;
+    let repository = database_client.scheduled_notifications();
+
+    let idempotency_key = format!("idem_scheduler_{}", Uuid::new_v4());
+    let payload = serde_json::json!({ "pattern": "push.queue", "data": {} });
+
+    let scheduled = repository
+        .schedule(
+            &idempotency_key,
+            "req_scheduler",
+            Uuid::new_v4(),
+            ScheduleReason::QuietHours,
+            &payload,
+            Utc::now() - Duration::seconds(1),
+        )
+        .await?
+        .expect("Message should be scheduled");
+    assert_eq!(scheduled.status, ScheduleStatus::Pending);
+
+    let claimed = repository.claim_due(1_000, LEASE).await?;
+    let ours = claimed
+        .iter()
+        .find(|claimed| claimed.id == scheduled.id)
+        .expect("Due message should be claimed");
+    assert_eq!(ours.status, ScheduleStatus::Dispatching);
+    assert_eq!(ours.payload, payload);
+
+    assert!(
+        repository
+            .claim_due(1_000, LEASE)
+            .await?
+            .iter()
+            .all(|claimed| claimed.id != scheduled.id),
+        "A claimed message must not be claimed twice"
+    );
+
+    repository.release(scheduled.id).await?;
+
+    assert!(
+        repository
+            .claim_due(1_000, LEASE)
+            .await?
+            .iter()
+            .any(|claimed| claimed.id == scheduled.id),
+        "A released message should be picked up again"
+    );
+
+    Ok(())
+}
+
+/// Test: A claim that is never confirmed is reclaimed once its lease runs out
+#[tokio::test]
+async fn test_claim_due_reclaims_expired_leases() -> Result<()> {
+    let database_client = connect().await?;
+    let repository = database_client.scheduled_notifications();
+
+    let idempotency_key = format!("idem_scheduler_{}", Uuid::new_v4());
+    let payload = serde_json::json!({ "pattern": "push.queue", "data": {} });
+
+    let scheduled = repository
+        .schedule(
+            &idempotency_key,
+            "req_scheduler",
+            Uuid::new_v4(),
+            ScheduleReason::SendAt,
+            &payload,
+            Utc::now() - Duration::seconds(1),
+        )
+        .await?
+        .expect("Message should be scheduled");
+
+    let claimed = |claims: &[ScheduledNotification]| claims.iter().any(|c| c.id == scheduled.id);
+
+    // As if the replica holding the claim died before publishing
+    assert!(claimed(&repository.claim_due(1_000, LEASE).await?));
+    assert!(!claimed(&repository.claim_due(1_000, LEASE).await?));
+    assert!(
+        claimed(&repository.claim_due(1_000, StdDuration::ZERO).await?),
+        "An expired claim should be handed out again"
+    );
+
+    repository.mark_dispatched(scheduled.id).await?;
+
+    assert!(
+        !claimed(&repository.claim_due(1_000, StdDuration::ZERO).await?),
+        "A confirmed dispatch must not be sent again"
+    );
+
+    Ok(())
+}
+
+/// Test: Messages are not claimed before their time, and a redelivery keeps the original slot
+#[tokio::test]
+async fn test_schedule_keeps_pending_delivery_time() -> Result<()> {
+    let database_client = connect().await?;
+    let repository = database_client.scheduled_notifications();
+
+    let idempotency_key = format!("idem_scheduler_{}", Uuid::new_v4());
+    let user_id = Uuid::new_v4();
+    let payload = serde_json::json!({ "pattern": "push.queue", "data": {} });
+    let deliver_at = Utc::now() + Duration::hours(1);
+
+    let scheduled = repository
+        .schedule(
+            &idempotency_key,
+            "req_scheduler",
+            user_id,
+            ScheduleReason::QuietHours,
+            &payload,
+            deliver_at,
+        )
+        .await?
+        .expect("Message should be scheduled");
+
+    let rescheduled = repository
+        .schedule(
+            &idempotency_key,
+            "req_scheduler",
+            user_id,
+            ScheduleReason::QuietHours,
+            &payload,
+            deliver_at + Duration::hours(1),
+        )
+        .await?
+        .expect("Message should be scheduled");
+    assert_eq!(rescheduled.id, scheduled.id);
+    assert_eq!(rescheduled.deliver_at, scheduled.deliver_at);
+
+    assert!(
+        repository
+            .claim_due(1_000, LEASE)
+            .await?
+            .iter()
+            .all(|claimed| claimed.id != scheduled.id),
+        "Future messages must wait"
+    );
+
+    Ok(())
+}
+
+/// Test: Cancelling a request stops its pending messages, including redeliveries
+#[tokio::test]
+async fn test_cancel_stops_pending_messages() -> Result<()> {
+    let database_client = connect().await?;
+    let repository = database_client.scheduled_notifications();
+
+    let request_id = format!("req_scheduler_{}", Uuid::new_v4());
+    let idempotency_key = format!("idem_scheduler_{}", Uuid::new_v4());
+    let user_id = Uuid::new_v4();
+    let payload = serde_json::json!({ "pattern": "push.queue", "data": {} });
+    let deliver_at = Utc::now() + Duration::hours(1);
+
+    repository
+        .schedule(
+            &idempotency_key,
+            &request_id,
+            user_id,
+            ScheduleReason::SendAt,
+            &payload,
+            deliver_at,
+        )
+        .await?
+        .expect("Message should be scheduled");
+
+    let cancelled = repository.cancel(&request_id).await?;
+    assert_eq!(cancelled.len(), 1);
+    assert_eq!(cancelled[0].status, ScheduleStatus::Cancelled);
+
+    assert!(
+        repository.cancel(&request_id).await?.is_empty(),
+        "Only pending messages can be cancelled"
+    );
+
+    let redelivered = repository
+        .schedule(
+            &idempotency_key,
+            &request_id,
+            user_id,
+            ScheduleReason::SendAt,
+            &payload,
+            deliver_at,
+        )
+        .await?;
+    assert!(
+        redelivered.is_none(),
+        "A cancelled message must not be rescheduled"
+    );
+
+    let listed = repository.list_for_request(&request_id).await?;
+    assert_eq!(listed.len(), 1);
+    assert_eq!(listed[0].reason, ScheduleReason::SendAt);
+    assert_eq!(listed[0].status, ScheduleStatus::Cancelled);
+
+    Ok(())
+}
+
+async fn connect() -> Result<DatabaseClient> {
+    let config = Config::load()?;
+    DatabaseClient::connect(&config.database_url).await
+}
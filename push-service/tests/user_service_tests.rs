@@ -0,0 +1,249 @@
+use anyhow::Result;
+use chrono::{NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use push_service::{
+    clients::{circuit_breaker::CircuitBreaker, user::UserServiceClient},
+    config::Config,
+    models::user::{QuietHours, UserPreferences},
+};
+use uuid::Uuid;
+use wiremock::{
+    Mock, MockServer, ResponseTemplate,
+    matchers::{header, method, path},
+};
+
+/// Test: Users are parsed out of the ApiResponse envelope and cached
+#[tokio::test]
+async fn test_fetch_user_caches_profile() -> Result<()> {
+    let mock_server = MockServer::start().await;
+    let user_id = Uuid::new_v4().to_string();
+
+    Mock::given(method("GET"))
+        .and(path(format!("/api/v1/users/{}", user_id)))
+        .respond_with(ResponseTemplate::new(200).set_body_json(user_response(&user_id)))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let client = create_user_client(&mock_server, |_| {}).await?;
+
+    for _ in 0..2 {
+        let profile = client
+            .fetch_user(&user_id)
+            .await?
+            .expect("User should exist");
+        assert_eq!(profile.push_token.as_deref(), Some("fresh_device_token"));
+        assert_eq!(
+            profile.preferences,
+            UserPreferences {
+                email: true,
+                push: false,
+                ..Default::default()
+            }
+        );
+    }
+
+    client.forget(&user_id).await;
+
+    Ok(())
+}
+
+/// Test: Unknown users are reported as missing rather than as an outage
+#[tokio::test]
+async fn test_fetch_user_returns_none_for_unknown_user() -> Result<()> {
+    let mock_server = MockServer::start().await;
+
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(404).set_body_json(serde_json::json!({
+            "success": false,
+            "message": "User not found"
+        })))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let client = create_user_client(&mock_server, |_| {}).await?;
+
+    assert!(
+        client
+            .fetch_user(&Uuid::new_v4().to_string())
+            .await?
+            .is_none()
+    );
+
+    Ok(())
+}
+
+/// Test: The configured service token is sent to the guarded user routes
+#[tokio::test]
+async fn test_fetch_user_sends_service_token() -> Result<()> {
+    let mock_server = MockServer::start().await;
+    let user_id = Uuid::new_v4().to_string();
+
+    Mock::given(method("GET"))
+        .and(path(format!("/api/v1/users/{}", user_id)))
+        .and(header("authorization", "Bearer service-jwt"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(user_response(&user_id)))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let client = create_user_client(&mock_server, |config| {
+        config.user_service_token = Some("service-jwt".to_string());
+    })
+    .await?;
+
+    assert!(client.fetch_user(&user_id).await?.is_some());
+
+    client.forget(&user_id).await;
+
+    Ok(())
+}
+
+/// Test: Server errors are retried and then surfaced as a failure
+#[tokio::test]
+async fn test_fetch_user_retries_server_errors() -> Result<()> {
+    let mock_server = MockServer::start().await;
+
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(503))
+        .expect(2)
+        .mount(&mock_server)
+        .await;
+
+    let client = create_user_client(&mock_server, |config| {
+        config.max_retry_attempts = 2;
+        config.initial_retry_delay_ms = 10;
+    })
+    .await?;
+
+    assert!(
+        client
+            .fetch_user(&Uuid::new_v4().to_string())
+            .await
+            .is_err()
+    );
+
+    Ok(())
+}
+
+/// Test: Rejected lookups are not counted as user-service outages
+#[tokio::test]
+async fn test_fetch_user_rejections_do_not_open_breaker() -> Result<()> {
+    let mock_server = MockServer::start().await;
+
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(403))
+        .expect(3)
+        .mount(&mock_server)
+        .await;
+
+    let client = create_user_client(&mock_server, |config| {
+        config.circuit_breaker_failure_threshold = 1;
+    })
+    .await?;
+
+    // Every call still reaches user-service instead of being short-circuited
+    for _ in 0..3 {
+        let error = client
+            .fetch_user(&Uuid::new_v4().to_string())
+            .await
+            .expect_err("A 403 should fail the lookup");
+        assert!(error.to_string().contains("403"));
+    }
+
+    Ok(())
+}
+
+/// Test: Quiet windows are read in the user's zone and may run past midnight
+#[test]
+fn test_quiet_hours_window_end() -> Result<()> {
+    let preferences: UserPreferences = serde_json::from_value(serde_json::json!({
+        "push": true,
+        "timezone": "Europe/Berlin",
+        "quiet_hours": { "start": "22:00", "end": "07:00" }
+    }))?;
+    assert_eq!(preferences.quiet_hours_timezone(), Some("Europe/Berlin"));
+
+    let quiet_hours = preferences.quiet_hours.expect("Quiet hours should parse");
+    let berlin: Tz = "Europe/Berlin".parse()?;
+
+    // 22:30 and 01:00 in Berlin (UTC+1 in January) both end at 07:00 local
+    let end = Utc.with_ymd_and_hms(2025, 1, 15, 6, 0, 0).unwrap();
+    for now in [
+        Utc.with_ymd_and_hms(2025, 1, 14, 21, 30, 0).unwrap(),
+        Utc.with_ymd_and_hms(2025, 1, 15, 0, 0, 0).unwrap(),
+    ] {
+        assert_eq!(quiet_hours.ends_after(now, berlin), Some(end));
+    }
+
+    // Noon in Berlin is outside the window
+    let noon = Utc.with_ymd_and_hms(2025, 1, 15, 11, 0, 0).unwrap();
+    assert_eq!(quiet_hours.ends_after(noon, berlin), None);
+
+    Ok(())
+}
+
+/// Test: A window ending inside a DST gap ends once the clocks have moved on
+#[test]
+fn test_quiet_hours_end_in_dst_gap() -> Result<()> {
+    let quiet_hours = QuietHours {
+        start: NaiveTime::from_hms_opt(0, 0, 0).unwrap(),
+        end: NaiveTime::from_hms_opt(2, 30, 0).unwrap(),
+        timezone: None,
+    };
+    let berlin: Tz = "Europe/Berlin".parse()?;
+
+    // 02:30 does not exist in Berlin on 2025-03-30; 03:30 CEST is 01:30 UTC
+    let now = Utc.with_ymd_and_hms(2025, 3, 29, 23, 30, 0).unwrap();
+    assert_eq!(
+        quiet_hours.ends_after(now, berlin),
+        Some(Utc.with_ymd_and_hms(2025, 3, 30, 1, 30, 0).unwrap())
+    );
+
+    Ok(())
+}
+
+async fn create_user_client(
+    mock_server: &MockServer,
+    configure: impl FnOnce(&mut Config),
+) -> Result<UserServiceClient> {
+    let mut config = Config::load()?;
+    config.user_service_url = mock_server.uri();
+    configure(&mut config);
+
+    let redis_client = redis::Client::open(config.redis_url.as_str())?;
+    let redis_conn = redis_client.get_multiplexed_async_connection().await?;
+
+    let circuit_breaker = CircuitBreaker::new(
+        format!("user_service_{}", Uuid::new_v4()),
+        redis_conn.clone(),
+        config.circuit_breaker_config(),
+    );
+
+    UserServiceClient::new(&config, circuit_breaker, redis_conn)
+}
+
+fn user_response(user_id: &str) -> serde_json::Value {
+    serde_json::json!({
+        "success": true,
+        "message": "User retrieved successfully",
+        "data": {
+            "id": user_id,
+            "name": "Ada",
+            "email": "ada@example.com",
+            "push_token": "fresh_device_token",
+            "preferences": { "email": true, "push": false },
+            "created_at": "2025-01-01T00:00:00.000Z",
+            "updated_at": "2025-01-01T00:00:00.000Z"
+        },
+        "meta": {
+            "total": 1,
+            "limit": 1,
+            "page": 1,
+            "total_pages": 1,
+            "has_next": false,
+            "has_previous": false
+        }
+    })
+}
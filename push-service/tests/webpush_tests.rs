@@ -0,0 +1,380 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use chrono::Utc;
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use p256::SecretKey;
+use push_service::{
+    clients::{
+        circuit_breaker::CircuitBreaker,
+        provider::PushProvider,
+        webpush::{VapidSigner, WebPushClient, decode_base64url, encrypt_payload_with_keys},
+    },
+    config::Config,
+    models::{
+        provider::{DeliveryErrorKind, PushNotification, PushOptions, PushPriority, PushTarget},
+        webpush::{VapidClaims, WebPushError, WebPushKeys, WebPushSubscription},
+    },
+};
+use uuid::Uuid;
+use wiremock::{
+    Mock, MockServer, ResponseTemplate,
+    matchers::{header, header_regex, method, path},
+};
+
+// Keys and expected output from RFC 8291, Appendix A
+const RFC_PLAINTEXT: &str = "When I grow up, I want to be a watermelon";
+const RFC_AS_PRIVATE: &str = "yfWPiYE-n46HLnH0KqZOF1fJJU3MYrct3AELtAQ-oRw";
+const RFC_AS_PUBLIC: &str =
+    "BP4z9KsN6nGRTbVYI_c7VJSPQTBtkgcy27mlmlMoZIIgDll6e3vCYLocInmYWAmS6TlzAC8wEqKK6PBru3jl7A8";
+const RFC_UA_PUBLIC: &str =
+    "BCVxsr7N_eNgVRqvHtD0zTZsEc6-VV-JvLexhqUzORcxaOzi6-AYWXvTBHm4bjyPjs7Vd8pZGH6SRpkNtoIAiw4";
+const RFC_SALT: &str = "DGv6ra1nlYgDCS1FRnbzlw";
+const RFC_AUTH: &str = "BTBZMqHH6r4Tts7J_aSIgg";
+const RFC_BODY: &str = "DGv6ra1nlYgDCS1FRnbzlwAAEABBBP4z9KsN6nGRTbVYI_c7VJSPQTBtkgcy27mlmlMoZIIgDll6e3vCYLocInmYWAmS6TlzAC8wEqKK6PBru3jl7A_yl95bQpu6cVPTpK4Mqgkf1CXztLVBSt2Ks3oZwbuwXPXLWyouBWLVWGNWQexSgSxsj_Qulcy4a-fN";
+
+/// Test: Payload encryption matches the RFC 8291 test vector
+#[test]
+fn test_encryption_matches_rfc8291_vector() -> Result<()> {
+    let as_private = SecretKey::from_slice(&decode_base64url(RFC_AS_PRIVATE)?)?;
+    let salt: [u8; 16] = decode_base64url(RFC_SALT)?.try_into().unwrap();
+
+    let body = encrypt_payload_with_keys(
+        RFC_PLAINTEXT.as_bytes(),
+        &decode_base64url(RFC_UA_PUBLIC)?,
+        &decode_base64url(RFC_AUTH)?,
+        &as_private,
+        &salt,
+    )?;
+
+    assert_eq!(URL_SAFE_NO_PAD.encode(&body), RFC_BODY);
+
+    Ok(())
+}
+
+/// Test: Oversized payloads are rejected before encryption
+#[test]
+fn test_oversized_payload_is_rejected() -> Result<()> {
+    let as_private = SecretKey::from_slice(&decode_base64url(RFC_AS_PRIVATE)?)?;
+
+    let error = encrypt_payload_with_keys(
+        &[b'x'; 4096],
+        &decode_base64url(RFC_UA_PUBLIC)?,
+        &decode_base64url(RFC_AUTH)?,
+        &as_private,
+        &[0u8; 16],
+    )
+    .expect_err("Payload larger than a record should fail");
+
+    assert_eq!(
+        error.downcast_ref::<WebPushError>(),
+        Some(&WebPushError::PayloadTooLarge)
+    );
+
+    Ok(())
+}
+
+/// Test: Push service status codes map onto delivery error kinds
+#[test]
+fn test_push_service_statuses_map_to_error_kinds() {
+    let cases = [
+        (404, DeliveryErrorKind::InvalidToken),
+        (410, DeliveryErrorKind::InvalidToken),
+        (413, DeliveryErrorKind::Permanent),
+        (400, DeliveryErrorKind::Permanent),
+        (429, DeliveryErrorKind::Retryable),
+        (503, DeliveryErrorKind::Retryable),
+    ];
+
+    for (status, expected) in cases {
+        let error = WebPushError::from_response(status, "");
+        assert_eq!(error.kind(), expected, "Unexpected kind for {}", status);
+    }
+}
+
+/// Test: VAPID tokens are ES256 JWTs scoped to the endpoint origin
+#[test]
+fn test_vapid_token_is_signed_for_origin() -> Result<()> {
+    let signer = create_signer()?;
+
+    let authorization = signer.authorization("https://push.example.net:8443/send/abc123")?;
+
+    let (token, key) = authorization
+        .strip_prefix("vapid t=")
+        .and_then(|rest| rest.split_once(", k="))
+        .expect("Authorization should use the vapid scheme");
+
+    assert_eq!(key, RFC_AS_PUBLIC);
+    assert_eq!(signer.public_key(), RFC_AS_PUBLIC);
+
+    let public_key = decode_base64url(key)?;
+    let decoding_key = DecodingKey::from_ec_components(
+        &URL_SAFE_NO_PAD.encode(&public_key[1..33]),
+        &URL_SAFE_NO_PAD.encode(&public_key[33..]),
+    )?;
+
+    let mut validation = Validation::new(Algorithm::ES256);
+    validation.set_audience(&["https://push.example.net:8443"]);
+    let decoded = jsonwebtoken::decode::<VapidClaims>(token, &decoding_key, &validation)?;
+
+    assert_eq!(decoded.claims.sub, "mailto:ops@example.com");
+    assert!(decoded.claims.exp <= Utc::now().timestamp() + 86_400);
+
+    assert_eq!(
+        token,
+        signer.token("https://push.example.net:8443")?,
+        "Token should be reused for the same audience"
+    );
+    assert_ne!(
+        token,
+        signer.token("https://other.example.net")?,
+        "Each audience should get its own token"
+    );
+
+    Ok(())
+}
+
+/// Test: Notifications are posted encrypted with Web Push headers
+#[tokio::test]
+async fn test_webpush_send_posts_encrypted_payload() -> Result<()> {
+    let mock_server = MockServer::start().await;
+
+    Mock::given(method("POST"))
+        .and(path("/push/sub-1"))
+        .and(header("content-encoding", "aes128gcm"))
+        .and(header("urgency", "normal"))
+        .and(header("topic", "order42"))
+        .and(header_regex("ttl", r"^(599|600)$"))
+        .and(header_regex(
+            "authorization",
+            r"^vapid t=[\w-]+\.[\w-]+\.[\w-]+, k=[\w-]+$",
+        ))
+        .respond_with(
+            ResponseTemplate::new(201).insert_header("location", "https://push.example/m/1"),
+        )
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let client = create_webpush_client().await?;
+
+    let mut notification = create_notification(&mock_server, "sub-1");
+    notification.priority = PushPriority::Normal;
+    notification.collapse_key = Some("order42".to_string());
+
+    let receipt = client.send(&notification).await?;
+
+    assert_eq!(
+        receipt.message_id.as_deref(),
+        Some("https://push.example/m/1")
+    );
+
+    let requests = mock_server.received_requests().await.unwrap();
+    let body = &requests[0].body;
+
+    assert_eq!(&body[16..20], &4096u32.to_be_bytes());
+    assert_eq!(body[20], 65);
+    assert!(
+        !String::from_utf8_lossy(body).contains("Order shipped"),
+        "Payload should not be sent in plaintext"
+    );
+
+    Ok(())
+}
+
+/// Test: Gone subscriptions surface as invalid token errors
+#[tokio::test]
+async fn test_webpush_gone_subscription_is_invalid_token() -> Result<()> {
+    let mock_server = MockServer::start().await;
+
+    Mock::given(method("POST"))
+        .and(path("/push/sub-2"))
+        .respond_with(ResponseTemplate::new(410))
+        .mount(&mock_server)
+        .await;
+
+    let client = create_webpush_client().await?;
+
+    let error = client
+        .send(&create_notification(&mock_server, "sub-2"))
+        .await
+        .expect_err("Gone subscription should fail");
+
+    assert_eq!(
+        client.classify_error(&error),
+        DeliveryErrorKind::InvalidToken
+    );
+    assert_eq!(
+        client.error_code(&error).as_deref(),
+        Some("SUBSCRIPTION_EXPIRED")
+    );
+
+    Ok(())
+}
+
+/// Test: Keys that cannot be encrypted for fail permanently without a request
+#[tokio::test]
+async fn test_webpush_unusable_keys_are_permanent() -> Result<()> {
+    let mock_server = MockServer::start().await;
+
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(201))
+        .expect(0)
+        .mount(&mock_server)
+        .await;
+
+    let client = create_webpush_client().await?;
+
+    // Right length, but not a point on P-256
+    let mut notification = create_notification(&mock_server, "sub-3");
+    if let PushTarget::WebPush(subscription) = &mut notification.target {
+        subscription.keys.p256dh = URL_SAFE_NO_PAD.encode([0x04; 65]);
+    }
+
+    let error = client
+        .send(&notification)
+        .await
+        .expect_err("Unusable keys should fail");
+
+    assert_eq!(client.classify_error(&error), DeliveryErrorKind::Permanent);
+    assert_eq!(
+        client.error_code(&error).as_deref(),
+        Some("INVALID_SUBSCRIPTION")
+    );
+
+    Ok(())
+}
+
+/// Test: A throttled request waits as long as the push service asks
+#[tokio::test]
+async fn test_webpush_honors_retry_after() -> Result<()> {
+    let mock_server = MockServer::start().await;
+
+    Mock::given(method("POST"))
+        .and(path("/push/sub-4"))
+        .respond_with(ResponseTemplate::new(429).insert_header("retry-after", "1"))
+        .up_to_n_times(1)
+        .mount(&mock_server)
+        .await;
+
+    Mock::given(method("POST"))
+        .and(path("/push/sub-4"))
+        .respond_with(ResponseTemplate::new(201))
+        .mount(&mock_server)
+        .await;
+
+    let client = create_webpush_client_with_attempts(2).await?;
+
+    let started = std::time::Instant::now();
+    client
+        .send(&create_notification(&mock_server, "sub-4"))
+        .await?;
+
+    assert!(
+        started.elapsed() >= Duration::from_secs(1),
+        "Retry should wait for the Retry-After delay"
+    );
+    assert_eq!(mock_server.received_requests().await.unwrap().len(), 2);
+
+    let throttled =
+        WebPushError::from_response(429, "").with_retry_after(Some(Duration::from_secs(5)));
+    assert_eq!(throttled.retry_after(), Some(Duration::from_secs(5)));
+    assert_eq!(throttled.code(), "TOO_MANY_REQUESTS");
+
+    Ok(())
+}
+
+/// Test: Web Push only accepts well-formed subscriptions
+#[tokio::test]
+async fn test_webpush_validates_subscription_target() -> Result<()> {
+    let client = create_webpush_client().await?;
+
+    assert!(
+        client
+            .validate_target(&PushTarget::Token("device_token_abc123".to_string()))
+            .is_err()
+    );
+
+    let mut subscription = create_subscription("https://push.example.net/sub");
+    assert!(
+        client
+            .validate_target(&PushTarget::WebPush(subscription.clone()))
+            .is_ok()
+    );
+
+    subscription.keys.auth = "dG9vc2hvcnQ".to_string();
+    assert!(
+        client
+            .validate_target(&PushTarget::WebPush(subscription.clone()))
+            .is_err()
+    );
+
+    let subscription = create_subscription("ftp://push.example.net/sub");
+    assert!(
+        client
+            .validate_target(&PushTarget::WebPush(subscription))
+            .is_err()
+    );
+
+    Ok(())
+}
+
+fn create_signer() -> Result<VapidSigner> {
+    VapidSigner::new(
+        RFC_AS_PRIVATE,
+        "mailto:ops@example.com".to_string(),
+        Duration::from_secs(43_200),
+    )
+}
+
+async fn create_webpush_client() -> Result<WebPushClient> {
+    create_webpush_client_with_attempts(1).await
+}
+
+async fn create_webpush_client_with_attempts(max_attempts: u32) -> Result<WebPushClient> {
+    let mut config = Config::load()?;
+    config.vapid_private_key = Some(RFC_AS_PRIVATE.to_string());
+    config.vapid_subject = Some("mailto:ops@example.com".to_string());
+    config.max_retry_attempts = max_attempts;
+    config.max_retry_delay_ms = 5_000;
+
+    let redis_client = redis::Client::open(config.redis_url.as_str())?;
+    let redis_conn = redis_client.get_multiplexed_async_connection().await?;
+
+    let circuit_breaker = CircuitBreaker::new(
+        format!("webpush_test_{}", Uuid::new_v4()),
+        redis_conn,
+        config.circuit_breaker_config(),
+    );
+
+    WebPushClient::new(&config, circuit_breaker)
+}
+
+fn create_subscription(endpoint: &str) -> WebPushSubscription {
+    WebPushSubscription {
+        endpoint: endpoint.to_string(),
+        keys: WebPushKeys {
+            p256dh: RFC_UA_PUBLIC.to_string(),
+            auth: RFC_AUTH.to_string(),
+        },
+    }
+}
+
+fn create_notification(mock_server: &MockServer, subscription_id: &str) -> PushNotification {
+    PushNotification {
+        target: PushTarget::WebPush(create_subscription(&format!(
+            "{}/push/{}",
+            mock_server.uri(),
+            subscription_id
+        ))),
+        title: "Order shipped".to_string(),
+        body: "Arriving today".to_string(),
+        trace_id: "req_webpush_001".to_string(),
+        data: None,
+        priority: PushPriority::High,
+        collapse_key: None,
+        expires_at: Some(Utc::now() + chrono::Duration::seconds(600)),
+        options: PushOptions::default(),
+    }
+}